@@ -97,3 +97,17 @@ fn similar_algorithm(bencher: Bencher, index: usize) {
             black_box(differ.generate())
         });
 }
+
+/// 10k lines with the middle one changed: with the common prefix and suffix
+/// trimmed, the LCS-based differs no longer allocate a table per pair of lines.
+#[divan::bench(args = [DiffAlgorithmType::Myers, DiffAlgorithmType::Naive])]
+fn one_line_change_in_10k_lines(bencher: Bencher, algorithm: DiffAlgorithmType) {
+    let original: String = (0..10_000).map(|i| format!("line {i}\n")).collect();
+    let modified = original.replace("line 5000\n", "changed\n");
+
+    bencher.bench(|| {
+        let differ =
+            Differ::new_with_algorithm(black_box(&original), black_box(&modified), algorithm);
+        black_box(differ.generate())
+    });
+}
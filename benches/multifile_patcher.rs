@@ -34,6 +34,8 @@ fn simulate_multifile_patch(
                     content: new_content,
                     is_new,
                     is_deleted,
+                    hunks: Vec::new(),
+                    metadata_only: patch.is_metadata_only(),
                 });
             }
         }
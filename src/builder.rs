@@ -0,0 +1,152 @@
+use crate::{Chunk, Operation, Patch};
+
+/// Builds a [`Chunk`] operation by operation, keeping the line counts in sync.
+#[derive(Debug, Clone)]
+pub struct ChunkBuilder {
+    old_start: usize,
+    new_start: Option<usize>,
+    operations: Vec<Operation>,
+}
+
+impl ChunkBuilder {
+    /// Starts a chunk at `old_start` (0-based) in the original file.
+    pub fn new(old_start: usize) -> Self {
+        Self {
+            old_start,
+            new_start: None,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Sets the start line (0-based) in the new file. Defaults to `old_start`
+    /// when built on its own; [`PatchBuilder`] derives it from the previous chunks.
+    pub fn new_start(mut self, new_start: usize) -> Self {
+        self.new_start = Some(new_start);
+        self
+    }
+
+    /// Adds an unchanged line.
+    pub fn context(mut self, line: impl Into<String>) -> Self {
+        self.operations.push(Operation::Context(line.into()));
+        self
+    }
+
+    /// Adds a line present only in the new file.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, line: impl Into<String>) -> Self {
+        self.operations.push(Operation::Add(line.into()));
+        self
+    }
+
+    /// Adds a line present only in the original file.
+    pub fn remove(mut self, line: impl Into<String>) -> Self {
+        self.operations.push(Operation::Remove(line.into()));
+        self
+    }
+
+    /// Builds the chunk, computing `old_lines` and `new_lines` from the operations.
+    pub fn build(self) -> Chunk {
+        let new_start = self.new_start.unwrap_or(self.old_start);
+        self.build_at(new_start)
+    }
+
+    fn build_at(self, new_start: usize) -> Chunk {
+        Chunk::from_operations(self.old_start, new_start, self.operations)
+    }
+}
+
+/// Builds a [`Patch`] from chunks, deriving each chunk's start line in the new
+/// file from the lines added and removed by the chunks before it.
+#[derive(Debug, Clone)]
+pub struct PatchBuilder {
+    patch: Patch,
+    /// Lines added minus lines removed by the chunks so far
+    offset: isize,
+}
+
+impl PatchBuilder {
+    /// Starts a patch from `old_file` to `new_file`.
+    pub fn new(old_file: &str, new_file: &str) -> Self {
+        Self {
+            patch: Patch {
+                old_file: old_file.to_string(),
+                new_file: new_file.to_string(),
+                ..Default::default()
+            },
+            offset: 0,
+        }
+    }
+
+    /// Sets the preamble line, e.g. `diff --git a/file b/file`.
+    pub fn preamble(mut self, preamble: impl Into<String>) -> Self {
+        self.patch.preamble = Some(preamble.into());
+        self
+    }
+
+    /// Appends a chunk. Chunks must be added in file order; the chunk's start
+    /// line in the new file is computed unless it was set explicitly.
+    pub fn chunk(mut self, chunk: ChunkBuilder) -> Self {
+        let new_start = chunk
+            .new_start
+            .unwrap_or_else(|| chunk.old_start.saturating_add_signed(self.offset));
+        let chunk = chunk.build_at(new_start);
+        self.offset += chunk.new_lines as isize - chunk.old_lines as isize;
+        self.patch.chunks.push(chunk);
+        self
+    }
+
+    /// Builds the patch.
+    pub fn build(self) -> Patch {
+        self.patch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PatchAlgorithm, Patcher};
+
+    #[test]
+    fn test_chunk_builder_counts_lines() {
+        let chunk = ChunkBuilder::new(4)
+            .context("a")
+            .remove("b")
+            .add("B")
+            .add("B2")
+            .context("c")
+            .build();
+        assert_eq!(
+            (
+                chunk.old_start,
+                chunk.old_lines,
+                chunk.new_start,
+                chunk.new_lines
+            ),
+            (4, 3, 4, 4)
+        );
+    }
+
+    #[test]
+    fn test_patch_builder_tracks_new_positions() {
+        let patch = PatchBuilder::new("file.txt", "file.txt")
+            .chunk(
+                ChunkBuilder::new(0)
+                    .remove("1")
+                    .add("one")
+                    .add("uno")
+                    .context("2"),
+            )
+            .chunk(ChunkBuilder::new(4).context("5").remove("6").context("7"))
+            .build();
+
+        assert!(patch.validate().is_ok());
+        assert_eq!(patch.chunks[0].new_start, 0);
+        assert_eq!(patch.chunks[1].new_start, 5);
+        assert_eq!(patch.chunks[1].new_lines, 2);
+
+        let content = "1\n2\n3\n4\n5\n6\n7\n";
+        let result = Patcher::new(patch.clone()).apply(content, false).unwrap();
+        assert_eq!(result, "one\nuno\n2\n3\n4\n5\n7\n");
+        assert_eq!(Patcher::new(patch).apply(&result, true).unwrap(), content);
+    }
+}
@@ -0,0 +1,555 @@
+//! Composition of two consecutive patches of the same file.
+
+use crate::patch::git_diff_paths;
+use crate::{Chunk, Error, Operation, Patch};
+
+/// Marks the gap after the last chunk, which runs to the end of the file.
+const UNBOUNDED: usize = usize::MAX;
+
+/// A patch's view of the whole file: its operations, with the unchanged lines
+/// between chunks, whose content the patch does not record, kept as gaps.
+enum Step {
+    Gap(usize),
+    Op(Operation),
+}
+
+fn steps(patch: &Patch) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut old = 0;
+    for chunk in &patch.chunks {
+        if chunk.old_start > old {
+            steps.push(Step::Gap(chunk.old_start - old));
+        }
+        steps.extend(chunk.operations.iter().cloned().map(Step::Op));
+        old = chunk.old_start + chunk.old_lines;
+    }
+    steps.push(Step::Gap(UNBOUNDED));
+    steps
+}
+
+/// Consumes one line of the step at `steps[*i]`, moving past it once it is used up.
+fn consume_line(steps: &mut [Step], i: &mut usize) {
+    match &mut steps[*i] {
+        Step::Gap(UNBOUNDED) => {}
+        Step::Gap(n) if *n > 1 => *n -= 1,
+        _ => *i += 1,
+    }
+}
+
+impl Patch {
+    /// Composes this patch with `next`, a patch of the file this one produces,
+    /// into a single patch with the changes of both, without the file content.
+    ///
+    /// Lines that both patches record must agree; otherwise `next` does not
+    /// apply on top of this patch and an `Error::ApplyError` names the first
+    /// line that differs. Lines recorded by neither patch are assumed unchanged.
+    ///
+    /// The git metadata is composed as well: a rename or copy runs from the
+    /// path of this patch to the path of `next`, and so do the `old mode`/`new
+    /// mode` and `index` headers. Moves that cannot be expressed as a single
+    /// rename or copy, e.g. copying a file and then renaming the original, are
+    /// an `Error::ApplyError` too.
+    pub fn compose(&self, next: &Patch) -> Result<Patch, Error> {
+        self.validate()?;
+        next.validate()?;
+        let relocation = compose_relocations(relocation(self), relocation(next))?;
+
+        let (mut first, mut second) = (steps(self), steps(next));
+        let (mut i, mut j) = (0, 0);
+        // Line of the intermediate file (the new side of `self`) being matched
+        let mut mid = 0;
+        let mut composed = Vec::new();
+        loop {
+            // Lines removed by `self` and added by `next` are not in the intermediate file
+            if let Step::Op(Operation::Remove(line)) = &first[i] {
+                composed.push(Step::Op(Operation::Remove(line.clone())));
+                i += 1;
+                continue;
+            }
+            if let Step::Op(Operation::Add(line)) = &second[j] {
+                composed.push(Step::Op(Operation::Add(line.clone())));
+                j += 1;
+                continue;
+            }
+
+            if let (Step::Gap(n), Step::Gap(m)) = (&mut first[i], &mut second[j]) {
+                if *n == UNBOUNDED && *m == UNBOUNDED {
+                    break;
+                }
+                let len = (*n).min(*m);
+                for gap in [n, m] {
+                    if *gap != UNBOUNDED {
+                        *gap -= len;
+                    }
+                }
+                composed.push(Step::Gap(len));
+                mid += len;
+                if matches!(first[i], Step::Gap(0)) {
+                    i += 1;
+                }
+                if matches!(second[j], Step::Gap(0)) {
+                    j += 1;
+                }
+                continue;
+            }
+
+            let (produced, added) = match &first[i] {
+                Step::Op(op) => (Some(op.line()), op.is_add()),
+                Step::Gap(_) => (None, false),
+            };
+            let (expected, removed) = match &second[j] {
+                Step::Op(op) => (Some(op.line()), op.is_remove()),
+                Step::Gap(_) => (None, false),
+            };
+            if let (Some(produced), Some(expected)) = (produced, expected)
+                && produced != expected
+            {
+                return Err(Error::ApplyError(format!(
+                    "Cannot compose patches: line {} is {:?} after the first patch, but the second expects {:?}",
+                    mid + 1,
+                    produced,
+                    expected
+                )));
+            }
+            // At least one side is an operation, so the line is known
+            let line = produced.or(expected).unwrap_or_default().to_string();
+            match (added, removed) {
+                (false, false) => composed.push(Step::Op(Operation::Context(line))),
+                (false, true) => composed.push(Step::Op(Operation::Remove(line))),
+                (true, false) => composed.push(Step::Op(Operation::Add(line))),
+                (true, true) => {}
+            }
+            consume_line(&mut first, &mut i);
+            consume_line(&mut second, &mut j);
+            mid += 1;
+        }
+
+        let mut chunks = group_into_chunks(composed);
+        let old_end = |patch: &Patch| {
+            patch
+                .chunks
+                .last()
+                .map(|chunk| chunk.old_start + chunk.old_lines)
+        };
+        let new_end = |patch: &Patch| {
+            patch
+                .chunks
+                .last()
+                .map(|chunk| chunk.new_start + chunk.new_lines)
+        };
+        let shift = |patch: &Patch| {
+            patch
+                .chunks
+                .iter()
+                .map(|chunk| chunk.new_lines as isize - chunk.old_lines as isize)
+                .sum::<isize>()
+        };
+        // The end of the original file is recorded by `self` if it reaches it, else by
+        // `next`; likewise the end of the final file by `next`, else by `self`
+        let original_end = if self.old_missing_newline || self.new_missing_newline {
+            self.old_missing_newline.then(|| old_end(self)).flatten()
+        } else if next.old_missing_newline {
+            old_end(next).map(|end| end.saturating_add_signed(-shift(self)))
+        } else {
+            None
+        };
+        let final_end = if next.old_missing_newline || next.new_missing_newline {
+            next.new_missing_newline.then(|| new_end(next)).flatten()
+        } else if self.new_missing_newline {
+            new_end(self).map(|end| end.saturating_add_signed(shift(next)))
+        } else {
+            None
+        };
+        let mut old_missing_newline = original_end.is_some()
+            && original_end == chunks.last().map(|c| c.old_start + c.old_lines);
+        let mut new_missing_newline =
+            final_end.is_some() && final_end == chunks.last().map(|c| c.new_start + c.new_lines);
+
+        // Lines removed by one patch and added back by the other are unchanged,
+        // unless the pair at the end of the file changes its trailing newline
+        let last = chunks.len().saturating_sub(1);
+        let newline_changed = old_missing_newline != new_missing_newline;
+        for (index, chunk) in chunks.iter_mut().enumerate() {
+            chunk.operations = cancel_unchanged(
+                std::mem::take(&mut chunk.operations),
+                newline_changed && index == last,
+            );
+        }
+        let last_has_changes = chunks
+            .last()
+            .is_some_and(|chunk| chunk.operations.iter().any(Operation::is_change));
+        chunks.retain(|chunk| chunk.operations.iter().any(Operation::is_change));
+        if !last_has_changes {
+            // The chunk that reached the end of the file is gone
+            old_missing_newline = false;
+            new_missing_newline = false;
+        }
+
+        let (mut copy_from, mut copy_to, mut rename_from, mut rename_to) = (None, None, None, None);
+        match relocation {
+            Some(Relocation::Copy(from, to)) => (copy_from, copy_to) = (Some(from), Some(to)),
+            Some(Relocation::Rename(from, to)) => (rename_from, rename_to) = (Some(from), Some(to)),
+            None => {}
+        }
+        Ok(Patch {
+            preamble: compose_preambles(self.preamble.as_deref(), next.preamble.as_deref()),
+            old_file: self.old_file.clone(),
+            new_file: next.new_file.clone(),
+            similarity: relocation.and(self.similarity.into_iter().chain(next.similarity).min()),
+            copy_from: copy_from.map(str::to_string),
+            copy_to: copy_to.map(str::to_string),
+            rename_from: rename_from.map(str::to_string),
+            rename_to: rename_to.map(str::to_string),
+            extended_headers: compose_extended_headers(
+                &self.extended_headers,
+                &next.extended_headers,
+            ),
+            old_prefix: self.old_prefix.clone(),
+            new_prefix: next.new_prefix.clone(),
+            old_timestamp: self.old_timestamp.clone(),
+            new_timestamp: next.new_timestamp.clone(),
+            old_missing_newline,
+            new_missing_newline,
+            chunks,
+            ..Default::default()
+        })
+    }
+}
+
+/// How a patch moves its file: copied or renamed, from one path to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Relocation<'a> {
+    Copy(&'a str, &'a str),
+    Rename(&'a str, &'a str),
+}
+
+fn relocation(patch: &Patch) -> Option<Relocation<'_>> {
+    match (
+        &patch.copy_from,
+        &patch.copy_to,
+        &patch.rename_from,
+        &patch.rename_to,
+    ) {
+        (Some(from), Some(to), _, _) => Some(Relocation::Copy(from, to)),
+        (_, _, Some(from), Some(to)) => Some(Relocation::Rename(from, to)),
+        _ => None,
+    }
+}
+
+/// The move made by two consecutive patches, from the first one's source to
+/// the second one's destination.
+fn compose_relocations<'a>(
+    first: Option<Relocation<'a>>,
+    second: Option<Relocation<'a>>,
+) -> Result<Option<Relocation<'a>>, Error> {
+    let chained = |mid: &str, next_from: &str| {
+        if mid == next_from {
+            Ok(())
+        } else {
+            Err(Error::ApplyError(format!(
+                "The second patch moves {} but the first one produced {}",
+                next_from, mid
+            )))
+        }
+    };
+    Ok(match (first, second) {
+        (first, None) => first,
+        (None, Some(Relocation::Rename(from, to))) => Some(Relocation::Rename(from, to)),
+        (Some(Relocation::Rename(from, mid)), Some(Relocation::Rename(next_from, to))) => {
+            chained(mid, next_from)?;
+            // Renamed back to where it started
+            (from != to).then_some(Relocation::Rename(from, to))
+        }
+        (Some(Relocation::Copy(from, mid)), Some(Relocation::Rename(next_from, to))) => {
+            chained(mid, next_from)?;
+            Some(Relocation::Copy(from, to))
+        }
+        (first, Some(second)) => {
+            return Err(Error::ApplyError(format!(
+                "Cannot express {:?} followed by {:?} as a single patch",
+                first, second
+            )));
+        }
+    })
+}
+
+/// The preamble of the composed patch: a `diff --git` line from the first
+/// patch's old path to the second patch's new path, or whichever preamble is set.
+fn compose_preambles(first: Option<&str>, second: Option<&str>) -> Option<String> {
+    match (
+        first.and_then(git_diff_paths),
+        second.and_then(git_diff_paths),
+    ) {
+        (Some((old, _)), Some((_, new))) => Some(format!("diff --git a/{} b/{}", old, new)),
+        _ => first.or(second).map(str::to_string),
+    }
+}
+
+/// Composes the git extended headers: the first patch's `old mode` and `new
+/// file mode`, the second patch's `new mode` and `deleted file mode`, an `index`
+/// line from the first patch's old hash to the second patch's new one, and any
+/// other lines of both. Lines whose ends are no longer known are dropped, e.g.
+/// `index` when only one patch has it, and so is a mode change that is undone.
+fn compose_extended_headers(first: &[String], second: &[String]) -> Vec<String> {
+    const MODES: [&str; 4] = [
+        "old mode ",
+        "new mode ",
+        "new file mode ",
+        "deleted file mode ",
+    ];
+    let find = |headers: &[String], prefix: &str| {
+        headers
+            .iter()
+            .find_map(|header| header.strip_prefix(prefix).map(str::to_string))
+    };
+    let either = |prefix: &str, preferred: &[String], other: &[String]| {
+        find(preferred, prefix).or_else(|| find(other, prefix))
+    };
+
+    let mut headers = Vec::new();
+    let old_mode = either("old mode ", first, second);
+    let new_mode = either("new mode ", second, first);
+    if old_mode != new_mode {
+        headers.extend(old_mode.map(|mode| format!("old mode {mode}")));
+        headers.extend(new_mode.map(|mode| format!("new mode {mode}")));
+    }
+    headers.extend(find(first, "new file mode ").map(|mode| format!("new file mode {mode}")));
+    headers
+        .extend(find(second, "deleted file mode ").map(|mode| format!("deleted file mode {mode}")));
+    if let (Some(before), Some(after)) = (find(first, "index "), find(second, "index "))
+        && let (Some((old_hash, _)), Some((_, new_hash))) =
+            (before.split_once(".."), after.split_once(".."))
+    {
+        headers.push(format!("index {old_hash}..{new_hash}"));
+    }
+    for header in first.iter().chain(second) {
+        let composed =
+            header.starts_with("index ") || MODES.iter().any(|prefix| header.starts_with(prefix));
+        if !composed && !headers.contains(header) {
+            headers.push(header.clone());
+        }
+    }
+    headers
+}
+
+/// Groups the runs of operations between gaps into chunks.
+fn group_into_chunks(steps: Vec<Step>) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let (mut old, mut new) = (0, 0);
+    let mut current: Option<Chunk> = None;
+    let mut finish = |chunk: Option<Chunk>, old: usize, new: usize| {
+        if let Some(mut chunk) = chunk {
+            chunk.old_lines = old - chunk.old_start;
+            chunk.new_lines = new - chunk.new_start;
+            chunks.push(chunk);
+        }
+    };
+    for step in steps {
+        match step {
+            Step::Gap(len) => {
+                finish(current.take(), old, new);
+                old += len;
+                new += len;
+            }
+            Step::Op(op) => {
+                match op {
+                    Operation::Add(_) => new += 1,
+                    Operation::Remove(_) => old += 1,
+                    Operation::Context(_) => {
+                        old += 1;
+                        new += 1;
+                    }
+                }
+                current
+                    .get_or_insert_with(|| Chunk {
+                        old_start: if op.is_add() { old } else { old - 1 },
+                        old_lines: 0,
+                        new_start: if op.is_remove() { new } else { new - 1 },
+                        new_lines: 0,
+                        operations: Vec::new(),
+                        section_header: None,
+                    })
+                    .operations
+                    .push(op);
+            }
+        }
+    }
+    finish(current, old, new);
+    chunks
+}
+
+/// Turns the lines at the start and end of each run of changes that are both
+/// removed and added into context. With `keep_last`, the final removed and
+/// added lines of the operations are left as they are.
+fn cancel_unchanged(operations: Vec<Operation>, keep_last: bool) -> Vec<Operation> {
+    let mut result = Vec::with_capacity(operations.len());
+    let mut operations = operations.into_iter().peekable();
+    while let Some(op) = operations.next() {
+        if op.is_context() {
+            result.push(op);
+            continue;
+        }
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let mut next = Some(op);
+        while let Some(op) = next {
+            match op {
+                Operation::Remove(line) => removed.push(line),
+                Operation::Add(line) => added.push(line),
+                Operation::Context(_) => unreachable!(),
+            }
+            next = operations.next_if(Operation::is_change);
+        }
+        let at_end = keep_last && operations.peek().is_none();
+        let limit = removed.len().min(added.len())
+            - usize::from(at_end && !removed.is_empty() && !added.is_empty());
+        let prefix = (0..limit).take_while(|&k| removed[k] == added[k]).count();
+        let suffix = if at_end {
+            0
+        } else {
+            (0..limit - prefix)
+                .take_while(|&k| removed[removed.len() - 1 - k] == added[added.len() - 1 - k])
+                .count()
+        };
+        result.extend(removed[..prefix].iter().cloned().map(Operation::Context));
+        result.extend(
+            removed[prefix..removed.len() - suffix]
+                .iter()
+                .cloned()
+                .map(Operation::Remove),
+        );
+        result.extend(
+            added[prefix..added.len() - suffix]
+                .iter()
+                .cloned()
+                .map(Operation::Add),
+        );
+        result.extend(
+            removed[removed.len() - suffix..]
+                .iter()
+                .cloned()
+                .map(Operation::Context),
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiffAlgorithm, Differ, PatchAlgorithm, Patcher};
+
+    fn diff(old: &str, new: &str, context: usize) -> Patch {
+        Differ::new(old, new).context_lines(context).generate()
+    }
+
+    #[test]
+    fn test_compose_round_trip() {
+        let v1: String = (1..=30).map(|i| format!("line {i}\n")).collect();
+        let v2 = v1
+            .replace("line 3\n", "line three\n")
+            .replace("line 20\n", "line 20\ninserted\n");
+        // Touches lines only the second patch records, a line the first one
+        // added and a line the first one changed
+        let v3 = v2
+            .replace("line 10\n", "")
+            .replace("inserted\n", "inserted again\n")
+            .replace("line three\n", "line 3!\n")
+            .replace("line 30\n", "line 30\nend\n");
+
+        for context in [0, 1, 3] {
+            let composed = diff(&v1, &v2, context)
+                .compose(&diff(&v2, &v3, context))
+                .unwrap();
+            composed.validate().unwrap();
+            assert_eq!(Patcher::new(composed).apply(&v1, false).unwrap(), v3);
+        }
+    }
+
+    #[test]
+    fn test_compose_undo_is_empty() {
+        let (v1, v2) = ("a\nb\nc\n", "a\nB\nc\n");
+        let composed = diff(v1, v2, 3).compose(&diff(v2, v1, 3)).unwrap();
+        assert!(composed.chunks.is_empty());
+    }
+
+    #[test]
+    fn test_compose_missing_newline() {
+        let (v1, v2, v3) = ("a\nb\n", "a\nb", "A\nb");
+        let composed = diff(v1, v2, 3).compose(&diff(v2, v3, 3)).unwrap();
+        assert!(composed.new_missing_newline && !composed.old_missing_newline);
+        assert_eq!(Patcher::new(composed).apply(v1, false).unwrap(), v3);
+    }
+
+    #[test]
+    fn test_compose_keeps_metadata() {
+        let mut edit = Differ::new("a\nb\n", "a\nB\n")
+            .file_names("new.txt", "new.txt")
+            .generate();
+        edit.preamble = Some("diff --git a/new.txt b/new.txt".to_string());
+        edit.extended_headers = vec![
+            "old mode 100644".to_string(),
+            "new mode 100755".to_string(),
+            "index 2222222..3333333 100755".to_string(),
+        ];
+        let mut rename = Patch::rename("old.txt", "new.txt");
+        rename.extended_headers = vec!["index 1111111..2222222 100644".to_string()];
+
+        let composed = rename.compose(&edit).unwrap();
+        assert!(composed.is_rename());
+        assert_eq!(composed.rename_from.as_deref(), Some("old.txt"));
+        assert_eq!(composed.rename_to.as_deref(), Some("new.txt"));
+        assert_eq!(composed.similarity, Some(100));
+        assert_eq!(
+            composed.preamble.as_deref(),
+            Some("diff --git a/old.txt b/new.txt")
+        );
+        assert_eq!(
+            composed.extended_headers,
+            [
+                "old mode 100644",
+                "new mode 100755",
+                "index 1111111..3333333 100755"
+            ]
+        );
+        assert_eq!(
+            (composed.old_file.as_str(), composed.new_file.as_str()),
+            ("old.txt", "new.txt")
+        );
+
+        // Renaming on and back again is no rename at all
+        let back = rename
+            .compose(&Patch::rename("new.txt", "old.txt"))
+            .unwrap();
+        assert!(!back.is_rename() && back.similarity.is_none());
+
+        let copy = |from: &str, to: &str| Patch {
+            copy_from: Some(from.to_string()),
+            copy_to: Some(to.to_string()),
+            rename_from: None,
+            rename_to: None,
+            ..Patch::rename(from, to)
+        };
+        let copied = copy("old.txt", "copy.txt")
+            .compose(&Patch::rename("copy.txt", "final.txt"))
+            .unwrap();
+        assert_eq!(copied.copy_to.as_deref(), Some("final.txt"));
+        // Renaming a file other than the one produced, or renaming and then
+        // copying, cannot be one patch
+        assert!(matches!(
+            copy("old.txt", "copy.txt").compose(&Patch::rename("other.txt", "x.txt")),
+            Err(Error::ApplyError(_))
+        ));
+        assert!(matches!(
+            rename.compose(&copy("new.txt", "x.txt")),
+            Err(Error::ApplyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_compose_incompatible() {
+        let first = diff("a\nb\nc\n", "a\nB\nc\n", 3);
+        let second = diff("a\nb\nc\n", "a\nb\nC\n", 3);
+        assert!(matches!(first.compose(&second), Err(Error::ApplyError(_))));
+    }
+}
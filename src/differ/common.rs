@@ -1,14 +1,157 @@
+use std::convert::Infallible;
+
 use tracing::warn;
 
+use super::DiffInput;
 use crate::{Chunk, Operation, Patch};
 
 /// Change type used internally for the diffing algorithms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Change {
     Equal(usize, usize),  // (old_index, new_index)
     Delete(usize, usize), // (old_index, count)
     Insert(usize, usize), // (new_index, count)
 }
 
+/// Receives the edit script of a diff, one run at a time, as produced by
+/// [`myers_diff`](super::myers_diff).
+///
+/// Indices are positions in the old and new sequences at the start of the run.
+pub trait Diff {
+    type Error;
+
+    /// `len` items starting at `old_index` and `new_index` are the same in both sequences.
+    fn equal(&mut self, old_index: usize, new_index: usize, len: usize) -> Result<(), Self::Error>;
+
+    /// `old_len` items starting at `old_index` are removed.
+    fn delete(
+        &mut self,
+        old_index: usize,
+        old_len: usize,
+        new_index: usize,
+    ) -> Result<(), Self::Error>;
+
+    /// `new_len` items starting at `new_index` are inserted.
+    fn insert(
+        &mut self,
+        old_index: usize,
+        new_index: usize,
+        new_len: usize,
+    ) -> Result<(), Self::Error>;
+
+    /// Called once after the last run.
+    fn finish(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A [`Diff`] that collects the edit script into a `Vec<Change>`, with one
+/// `Change::Equal` per unchanged item.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeCollector {
+    changes: Vec<Change>,
+}
+
+impl ChangeCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The changes collected so far.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Consumes the collector and returns the collected changes.
+    pub fn into_changes(self) -> Vec<Change> {
+        self.changes
+    }
+}
+
+impl Diff for ChangeCollector {
+    type Error = Infallible;
+
+    fn equal(&mut self, old_index: usize, new_index: usize, len: usize) -> Result<(), Infallible> {
+        self.changes
+            .extend((0..len).map(|i| Change::Equal(old_index + i, new_index + i)));
+        Ok(())
+    }
+
+    fn delete(&mut self, old_index: usize, old_len: usize, _: usize) -> Result<(), Infallible> {
+        self.changes.push(Change::Delete(old_index, old_len));
+        Ok(())
+    }
+
+    fn insert(&mut self, _: usize, new_index: usize, new_len: usize) -> Result<(), Infallible> {
+        self.changes.push(Change::Insert(new_index, new_len));
+        Ok(())
+    }
+}
+
+/// A run of lines in an edit script, as returned by [`Differ::edit_script`](crate::Differ::edit_script).
+///
+/// Applying the runs in order to the old lines yields the new lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// The next `n` lines are the same in both files
+    Equal(usize),
+    /// The next `n` lines of the old file are removed
+    Delete(usize),
+    /// The next `n` lines of the new file are inserted
+    Insert(usize),
+}
+
+/// Collapses individual changes into runs of equal, deleted and inserted lines.
+pub(crate) fn changes_to_edits(changes: &[Change]) -> Vec<Edit> {
+    let mut edits: Vec<Edit> = Vec::new();
+    for change in changes {
+        let edit = match change {
+            Change::Equal(_, _) => Edit::Equal(1),
+            Change::Delete(_, count) => Edit::Delete(*count),
+            Change::Insert(_, count) => Edit::Insert(*count),
+        };
+        match (edits.last_mut(), edit) {
+            (Some(Edit::Equal(n)), Edit::Equal(m))
+            | (Some(Edit::Delete(n)), Edit::Delete(m))
+            | (Some(Edit::Insert(n)), Edit::Insert(m)) => *n += m,
+            _ => edits.push(edit),
+        }
+    }
+    edits
+}
+
+/// Runs `diff` only on the lines between the common prefix and suffix of `old`
+/// and `new`, so a small change in a large file costs little even for quadratic
+/// algorithms. The returned changes index the full sequences, with one
+/// `Change::Equal` per trimmed line.
+pub(crate) fn diff_trimmed<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    diff: impl FnOnce(&[T], &[T]) -> Vec<Change>,
+) -> Vec<Change> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+    let mut changes: Vec<Change> = (0..prefix).map(|i| Change::Equal(i, i)).collect();
+    changes.extend(
+        diff(&old[prefix..old_end], &new[prefix..new_end])
+            .into_iter()
+            .map(|change| match change {
+                Change::Equal(o, n) => Change::Equal(o + prefix, n + prefix),
+                Change::Delete(o, count) => Change::Delete(o + prefix, count),
+                Change::Insert(n, count) => Change::Insert(n + prefix, count),
+            }),
+    );
+    changes.extend((0..suffix).map(|i| Change::Equal(old_end + i, new_end + i)));
+    changes
+}
+
 /// Handle special cases for empty files
 pub fn handle_empty_files(old_lines: &[&str], new_lines: &[&str]) -> Option<Patch> {
     // Special case for empty files
@@ -29,7 +172,9 @@ pub fn handle_empty_files(old_lines: &[&str], new_lines: &[&str]) -> Option<Patc
                 new_start: 0,
                 new_lines: new_lines.len(),
                 operations,
+                section_header: None,
             }],
+            ..Default::default()
         });
     } else if !old_lines.is_empty() && new_lines.is_empty() {
         // Removing all content
@@ -48,7 +193,9 @@ pub fn handle_empty_files(old_lines: &[&str], new_lines: &[&str]) -> Option<Patc
                 new_start: 0,
                 new_lines: 0,
                 operations,
+                section_header: None,
             }],
+            ..Default::default()
         });
     } else if old_lines.is_empty() && new_lines.is_empty() {
         // Both files are empty, no diff needed
@@ -57,6 +204,7 @@ pub fn handle_empty_files(old_lines: &[&str], new_lines: &[&str]) -> Option<Patc
             old_file: "original".to_string(),
             new_file: "modified".to_string(),
             chunks: Vec::new(),
+            ..Default::default()
         });
     }
 
@@ -130,6 +278,9 @@ fn find_next_block(
 /// and calculates the old and new line counts for the chunk.
 /// Returns the list of operations, the old line count, the new line count,
 /// and the index in `changes` after adding trailing context.
+///
+/// Equal changes at the end of the block are emitted as trailing context, so
+/// that it never exceeds `context_lines` even when the block runs on to the end.
 fn build_chunk_operations<'a>(
     changes: &[Change],
     old_lines: &'a [&'a str],
@@ -142,12 +293,16 @@ fn build_chunk_operations<'a>(
     let mut operations = Vec::new();
     let mut chunk_old_lines_count = 0;
     let mut chunk_new_lines_count = 0;
+    let block_end_idx = changes[block_start_idx..block_end_idx]
+        .iter()
+        .rposition(|c| !matches!(c, Change::Equal(_, _)))
+        .map_or(block_start_idx, |pos| block_start_idx + pos + 1);
 
     // Add context before the block
     for idx in context_start_change_idx..block_start_idx {
-        if let Change::Equal(o, _) = changes[idx] {
+        if let Change::Equal(_, n) = changes[idx] {
             // Use get for safety, though indices should be valid based on how changes are generated
-            if let Some(line) = old_lines.get(o) {
+            if let Some(line) = new_lines.get(n) {
                 operations.push(Operation::Context(line.to_string()));
                 chunk_old_lines_count += 1;
                 chunk_new_lines_count += 1;
@@ -158,8 +313,8 @@ fn build_chunk_operations<'a>(
     // Add operations from the core block
     for idx in block_start_idx..block_end_idx {
         match changes[idx] {
-            Change::Equal(o, _) => {
-                if let Some(line) = old_lines.get(o) {
+            Change::Equal(_, n) => {
+                if let Some(line) = new_lines.get(n) {
                     operations.push(Operation::Context(line.to_string()));
                     chunk_old_lines_count += 1;
                     chunk_new_lines_count += 1;
@@ -188,8 +343,8 @@ fn build_chunk_operations<'a>(
     let mut context_scan_idx = block_end_idx;
     let mut context_added_after = 0;
     while context_added_after < context_lines && context_scan_idx < changes.len() {
-        if let Change::Equal(o, _) = changes[context_scan_idx] {
-            if let Some(line) = old_lines.get(o) {
+        if let Change::Equal(_, n) = changes[context_scan_idx] {
+            if let Some(line) = new_lines.get(n) {
                 operations.push(Operation::Context(line.to_string()));
                 chunk_old_lines_count += 1;
                 chunk_new_lines_count += 1;
@@ -275,6 +430,7 @@ pub fn process_changes_to_chunks(
                 new_start: chunk_new_start,
                 new_lines: chunk_new_lines_count,
                 operations,
+                section_header: None,
             };
             chunks.push(chunk);
         }
@@ -319,7 +475,7 @@ fn determine_chunk_start_indices(
         match changes.get(block_start_idx) {
             Some(Change::Equal(o, n)) => (*o, *n),
             Some(Change::Delete(o, _)) => (*o, infer_previous_new_index(changes, block_start_idx)), // Need helper to infer previous state
-            Some(Change::Insert(_, n)) => (infer_previous_old_index(changes, block_start_idx), *n), // Need helper to infer previous state
+            Some(Change::Insert(n, _)) => (infer_previous_old_index(changes, block_start_idx), *n), // Need helper to infer previous state
             None => (0, 0), // Should not happen if block_start_idx is valid
         }
     }
@@ -352,6 +508,109 @@ fn infer_previous_old_index(changes: &[Change], current_idx: usize) -> usize {
     }
 }
 
+/// Records which sides of the diff lack a trailing newline at the end of file.
+///
+/// When only one side lacks it, the last line differs in its terminator even if
+/// its text is equal, so it is turned into a `-`/`+` pair (adding a chunk at the
+/// end of the file with up to `context_lines` of context if necessary).
+pub(crate) fn mark_missing_newlines(
+    patch: &mut Patch,
+    old: &DiffInput,
+    new: &DiffInput,
+    context_lines: usize,
+) {
+    let old_lines = old.lines();
+    let new_lines = new.lines();
+    let old_missing = old.missing_newline();
+    let new_missing = new.missing_newline();
+    let reaches_end = |chunk: &Chunk| {
+        (
+            chunk.old_start + chunk.old_lines == old_lines.len(),
+            chunk.new_start + chunk.new_lines == new_lines.len(),
+        )
+    };
+
+    if old_missing != new_missing && !old_lines.is_empty() && !new_lines.is_empty() {
+        let old_last = old_lines.len() - 1;
+        let new_last = new_lines.len() - 1;
+        match patch.chunks.last_mut() {
+            Some(chunk) if reaches_end(chunk) != (false, false) => {
+                let (old_end, new_end) = reaches_end(chunk);
+                split_unterminated_context(chunk, old_missing && old_end, new_missing && new_end);
+            }
+            // The tails are equal: extend the last chunk to the end of the file
+            // if its trailing context touches the new context, or add a chunk
+            Some(chunk) if chunk.old_start + chunk.old_lines + context_lines >= old_last => {
+                let from = chunk.old_start + chunk.old_lines;
+                for line in &old_lines[from..old_last] {
+                    chunk.operations.push(Operation::Context(line.to_string()));
+                }
+                chunk
+                    .operations
+                    .push(Operation::Remove(old_lines[old_last].to_string()));
+                chunk
+                    .operations
+                    .push(Operation::Add(new_lines[new_last].to_string()));
+                chunk.old_lines += old_last - from + 1;
+                chunk.new_lines += old_last - from + 1;
+            }
+            _ => {
+                let context_start = old_last.saturating_sub(context_lines);
+                let mut operations: Vec<Operation> = old_lines[context_start..old_last]
+                    .iter()
+                    .map(|line| Operation::Context(line.to_string()))
+                    .collect();
+                operations.push(Operation::Remove(old_lines[old_last].to_string()));
+                operations.push(Operation::Add(new_lines[new_last].to_string()));
+                let len = old_last - context_start + 1;
+                patch.chunks.push(Chunk {
+                    old_start: context_start,
+                    old_lines: len,
+                    new_start: new_lines.len() - len,
+                    new_lines: len,
+                    operations,
+                    section_header: None,
+                });
+            }
+        }
+    }
+
+    let (old_end, new_end) = patch.chunks.last().map_or((false, false), reaches_end);
+    patch.old_missing_newline = old_missing && old_end;
+    patch.new_missing_newline = new_missing && new_end;
+}
+
+/// Splits a context line at the end of the file into a `-`/`+` pair when only
+/// one side of it lacks the trailing newline.
+fn split_unterminated_context(chunk: &mut Chunk, old_missing: bool, new_missing: bool) {
+    let ops = &mut chunk.operations;
+    let last_old = ops.iter().rposition(|op| !matches!(op, Operation::Add(_)));
+    let last_new = ops
+        .iter()
+        .rposition(|op| !matches!(op, Operation::Remove(_)));
+    // If both are context lines they are the same line, since nothing but
+    // additions (or removals) can follow the last line of one side
+    let Some(i) = [last_old, last_new]
+        .into_iter()
+        .flatten()
+        .find(|&i| matches!(ops[i], Operation::Context(_)))
+    else {
+        return;
+    };
+    let line = ops[i].line().to_string();
+    let old_side = old_missing && last_old == Some(i);
+    let new_side = new_missing && last_new == Some(i);
+    if old_side != new_side {
+        ops[i] = Operation::Remove(line.clone());
+        // Keep removals of the same run ahead of the re-added line
+        let insert_at = ops[i + 1..]
+            .iter()
+            .position(|op| !matches!(op, Operation::Remove(_)))
+            .map_or(ops.len(), |p| i + 1 + p);
+        ops.insert(insert_at, Operation::Add(line));
+    }
+}
+
 /// Create a patch with the specified chunks
 pub fn create_patch(chunks: Vec<Chunk>) -> Patch {
     Patch {
@@ -359,6 +618,7 @@ pub fn create_patch(chunks: Vec<Chunk>) -> Patch {
         old_file: "original".to_string(),
         new_file: "modified".to_string(),
         chunks,
+        ..Default::default()
     }
 }
 
@@ -367,6 +627,34 @@ mod tests {
     use super::*;
     use crate::{Chunk, Operation, Patch};
 
+    #[test]
+    fn test_diff_trimmed() {
+        let old = ["a", "b", "c", "d", "e"];
+        let new = ["a", "b", "x", "d", "e"];
+        let changes = diff_trimmed(&old, &new, |old, new| {
+            assert_eq!((old, new), (&["c"][..], &["x"][..]));
+            vec![Change::Delete(0, 1), Change::Insert(0, 1)]
+        });
+        assert_eq!(
+            changes,
+            vec![
+                Change::Equal(0, 0),
+                Change::Equal(1, 1),
+                Change::Delete(2, 1),
+                Change::Insert(2, 1),
+                Change::Equal(3, 3),
+                Change::Equal(4, 4),
+            ]
+        );
+
+        // Lines are trimmed from the suffix only once the prefix is taken
+        let changes = diff_trimmed(&["a", "a"], &["a"], |old, new| {
+            assert_eq!((old, new), (&["a"][..], &[][..]));
+            vec![Change::Delete(0, 1)]
+        });
+        assert_eq!(changes, vec![Change::Equal(0, 0), Change::Delete(1, 1)]);
+    }
+
     #[test]
     fn test_handle_empty_files_add_to_empty() {
         let old_lines: Vec<&str> = vec![];
@@ -384,7 +672,9 @@ mod tests {
                     Operation::Add("a".to_string()),
                     Operation::Add("b".to_string()),
                 ],
+                section_header: None,
             }],
+            ..Default::default()
         };
         assert_eq!(
             handle_empty_files(&old_lines, &new_lines),
@@ -409,7 +699,9 @@ mod tests {
                     Operation::Remove("a".to_string()),
                     Operation::Remove("b".to_string()),
                 ],
+                section_header: None,
             }],
+            ..Default::default()
         };
         assert_eq!(
             handle_empty_files(&old_lines, &new_lines),
@@ -426,6 +718,7 @@ mod tests {
             old_file: "original".to_string(),
             new_file: "modified".to_string(),
             chunks: Vec::new(),
+            ..Default::default()
         };
         assert_eq!(
             handle_empty_files(&old_lines, &new_lines),
@@ -574,20 +867,45 @@ mod tests {
         let chunk2 = &chunks[1];
         assert_eq!(chunk2.old_start, 6); // Starts at g (context)
         assert_eq!(chunk2.new_start, 6); // Starts at g (context)
-        assert_eq!(chunk2.old_lines, 4); // g, h, i, j
-        assert_eq!(chunk2.new_lines, 4); // g, Y, i, j
+        assert_eq!(chunk2.old_lines, 3); // g, h, i
+        assert_eq!(chunk2.new_lines, 3); // g, Y, i
         assert_eq!(
             chunk2.operations,
             vec![
                 Operation::Context("g".to_string()), // Leading context
                 Operation::Remove("h".to_string()),
                 Operation::Add("Y".to_string()),
-                Operation::Context("i".to_string()),
-                Operation::Context("j".to_string()), // Trailing context
+                Operation::Context("i".to_string()), // Trailing context, j is beyond it
             ]
         );
     }
 
+    #[test]
+    fn test_process_chunks_caps_context() {
+        let old_lines = vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l"];
+        let new_lines = vec!["a", "b", "c", "X", "e", "f", "g", "Y", "i", "j", "k", "l"];
+        let mut changes: Vec<Change> = (0..3).map(|i| Change::Equal(i, i)).collect();
+        changes.extend([Change::Delete(3, 1), Change::Insert(3, 1)]);
+        changes.extend((4..7).map(|i| Change::Equal(i, i)));
+        changes.extend([Change::Delete(7, 1), Change::Insert(7, 1)]);
+        changes.extend((8..12).map(|i| Change::Equal(i, i)));
+        let chunks = process_changes_to_chunks(&changes, &old_lines, &new_lines, 2);
+
+        // The 3-line gap is merged as context, the edges get 2 lines each
+        assert_eq!(chunks.len(), 1);
+        let chunk = &chunks[0];
+        assert_eq!((chunk.old_start, chunk.old_lines), (1, 9));
+        assert_eq!((chunk.new_start, chunk.new_lines), (1, 9));
+        let context: Vec<&str> = chunk
+            .operations
+            .iter()
+            .filter(|op| op.is_context())
+            .map(|op| op.line())
+            .collect();
+        assert_eq!(context, ["b", "c", "e", "f", "g", "i", "j"]);
+        assert!(chunk.operations.last().unwrap().is_context());
+    }
+
     #[test]
     fn test_process_chunks_zero_context() {
         let old_lines = vec!["a", "b", "c"];
@@ -779,7 +1097,7 @@ mod tests {
             Change::Insert(6, 1), // block_start_idx = 1
         ];
         // context_start_idx = block_start_idx = 1
-        assert_eq!(determine_chunk_start_indices(&changes, 1, 1), (6, 1)); // Infers old index from previous Equal
+        assert_eq!(determine_chunk_start_indices(&changes, 1, 1), (6, 6)); // Infers old index from previous Equal
     }
 
     #[test]
@@ -797,7 +1115,6 @@ mod tests {
             Change::Insert(0, 1), // block_start_idx = 0
         ];
         // context_start_idx = block_start_idx = 0
-        // 0-based logic yields (0, 0). Check if 1-based (1, 1) is expected.
-        assert_eq!(determine_chunk_start_indices(&changes, 0, 0), (0, 1));
+        assert_eq!(determine_chunk_start_indices(&changes, 0, 0), (0, 0));
     }
 }
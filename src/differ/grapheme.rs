@@ -0,0 +1,86 @@
+//! Diffing of text by grapheme clusters, enabled by the `unicode` feature.
+
+use super::Differ;
+use super::common::Change;
+use super::myers::shortest_edit_script;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A run of text in an inline diff, kept, removed or inserted as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordOp {
+    /// Text present in both the old and the new string
+    Equal(String),
+    /// Text only present in the old string
+    Delete(String),
+    /// Text only present in the new string
+    Insert(String),
+}
+
+impl WordOp {
+    /// Appends `text` to this run if it has the same kind as `other`.
+    fn merge(&mut self, other: &WordOp) -> bool {
+        match (self, other) {
+            (WordOp::Equal(run), WordOp::Equal(text))
+            | (WordOp::Delete(run), WordOp::Delete(text))
+            | (WordOp::Insert(run), WordOp::Insert(text)) => {
+                run.push_str(text);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Differ {
+    /// Diffs two strings by extended grapheme clusters with the Myers algorithm,
+    /// so an emoji ZWJ sequence or a letter with combining marks is kept or
+    /// replaced as a whole instead of being split between code points.
+    ///
+    /// Consecutive clusters with the same outcome are merged into one `WordOp`.
+    pub fn grapheme_diff(old: &str, new: &str) -> Vec<WordOp> {
+        let old: Vec<&str> = old.graphemes(true).collect();
+        let new: Vec<&str> = new.graphemes(true).collect();
+
+        let mut ops: Vec<WordOp> = Vec::new();
+        let mut push = |op: WordOp| {
+            if !ops.last_mut().is_some_and(|last| last.merge(&op)) {
+                ops.push(op);
+            }
+        };
+        for change in shortest_edit_script(&old, &new) {
+            match change {
+                Change::Equal(o, _) => push(WordOp::Equal(old[o].to_string())),
+                Change::Delete(o, count) => push(WordOp::Delete(old[o..o + count].concat())),
+                Change::Insert(n, count) => push(WordOp::Insert(new[n..n + count].concat())),
+            }
+        }
+        ops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grapheme_diff_keeps_clusters_whole() {
+        assert_eq!(
+            Differ::grapheme_diff("a👨‍👩‍👧", "a👨"),
+            vec![
+                WordOp::Equal("a".to_string()),
+                WordOp::Delete("👨‍👩‍👧".to_string()),
+                WordOp::Insert("👨".to_string()),
+            ]
+        );
+        // A combining acute accent belongs to the letter before it
+        assert_eq!(
+            Differ::grapheme_diff("cafe\u{301}!", "cafe!"),
+            vec![
+                WordOp::Equal("caf".to_string()),
+                WordOp::Delete("e\u{301}".to_string()),
+                WordOp::Insert("e".to_string()),
+                WordOp::Equal("!".to_string()),
+            ]
+        );
+    }
+}
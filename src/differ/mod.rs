@@ -1,13 +1,25 @@
 mod common;
+#[cfg(feature = "unicode")]
+mod grapheme;
+mod moves;
 mod myers;
 mod naive;
+mod seq;
 pub mod similar;
 mod xdiff;
 
-use crate::Patch;
+use crate::patch::{escape_record, format_timestamp};
+use crate::{BOM, Chunk, Operation, Patch};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-pub use myers::MyersDiffer;
+#[cfg(feature = "unicode")]
+pub use grapheme::WordOp;
+pub use moves::{Move, MoveAwarePatch};
+pub use myers::{MyersDiffer, myers_diff, myers_diff_collect};
 pub use naive::NaiveDiffer;
+pub use seq::{SeqChunk, SeqOperation, SeqPatch, diff_seq, diff_seq_with_context};
 pub use similar::SimilarDiffer;
 pub use xdiff::XDiffDiffer;
 
@@ -20,6 +32,7 @@ pub trait DiffAlgorithm {
 }
 
 /// Enum to specify which diffing algorithm to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DiffAlgorithmType {
     Myers,
     Naive,
@@ -27,12 +40,129 @@ pub enum DiffAlgorithmType {
     Similar,
 }
 
+/// Content to diff, either as text or already split into lines
+#[derive(Clone)]
+pub(crate) enum DiffInput {
+    Text(String),
+    /// Lines without terminators, each treated as ending with a newline
+    Lines(Vec<String>),
+    /// Text split into records on an ASCII delimiter other than `\n`, with the
+    /// records escaped by [`escape_record`] to fit on a patch line each
+    Delimited {
+        text: String,
+        delimiter: u8,
+        records: Vec<String>,
+    },
+}
+
+impl DiffInput {
+    /// The lines of the content, without terminators
+    pub(crate) fn lines(&self) -> Vec<&str> {
+        match self {
+            DiffInput::Text(text) => text.lines().collect(),
+            DiffInput::Lines(lines) => lines.iter().map(String::as_str).collect(),
+            DiffInput::Delimited { records, .. } => records.iter().map(String::as_str).collect(),
+        }
+    }
+
+    fn delimited(text: String, delimiter: u8) -> Self {
+        let records = if text.is_empty() {
+            Vec::new()
+        } else {
+            let delimiter = delimiter as char;
+            text.strip_suffix(delimiter)
+                .unwrap_or(&text)
+                .split(delimiter)
+                .map(|record| match escape_record(record.as_bytes()) {
+                    Cow::Borrowed(_) => record.to_string(),
+                    Cow::Owned(escaped) => {
+                        String::from_utf8(escaped).expect("escaping keeps UTF-8 valid")
+                    }
+                })
+                .collect()
+        };
+        DiffInput::Delimited {
+            text,
+            delimiter,
+            records,
+        }
+    }
+
+    /// Whether the content is non-empty and its last line has no trailing newline
+    pub(crate) fn missing_newline(&self) -> bool {
+        match self {
+            DiffInput::Text(text) => !text.is_empty() && !text.ends_with('\n'),
+            DiffInput::Lines(_) => false,
+            DiffInput::Delimited {
+                text, delimiter, ..
+            } => !text.is_empty() && !text.ends_with(*delimiter as char),
+        }
+    }
+
+    /// Other content split the same way, e.g. text appended to this content
+    fn sibling(&self, text: &str) -> Self {
+        match self {
+            DiffInput::Delimited { delimiter, .. } => {
+                DiffInput::delimited(text.to_string(), *delimiter)
+            }
+            _ => DiffInput::Text(text.to_string()),
+        }
+    }
+
+    /// This content followed by `text`
+    fn appended(&self, text: &str) -> Self {
+        match self {
+            DiffInput::Text(content) => DiffInput::Text(format!("{content}{text}")),
+            DiffInput::Lines(lines) => DiffInput::Lines(
+                lines
+                    .iter()
+                    .cloned()
+                    .chain(text.lines().map(String::from))
+                    .collect(),
+            ),
+            DiffInput::Delimited {
+                text: content,
+                delimiter,
+                ..
+            } => DiffInput::delimited(format!("{content}{text}"), *delimiter),
+        }
+    }
+
+    fn with_delimiter(self, delimiter: u8) -> Self {
+        match self {
+            DiffInput::Text(text) | DiffInput::Delimited { text, .. } if delimiter == b'\n' => {
+                DiffInput::Text(text)
+            }
+            DiffInput::Text(text) | DiffInput::Delimited { text, .. } => {
+                DiffInput::delimited(text, delimiter)
+            }
+            lines => lines,
+        }
+    }
+}
+
+/// Picks the line shown after the `@@` of a chunk header
+pub(crate) type SectionMatcher = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
 /// The base Differ struct that orchestrates the diffing process
 pub struct Differ {
     pub(crate) algorithm: DiffAlgorithmType,
-    pub(crate) old: String,
-    pub(crate) new: String,
+    pub(crate) old: DiffInput,
+    pub(crate) new: DiffInput,
     pub(crate) context_lines: usize,
+    pub(crate) old_prefix: String,
+    pub(crate) new_prefix: String,
+    pub(crate) file_names: Option<(String, String)>,
+    pub(crate) preamble: Option<String>,
+    pub(crate) timestamps: Option<(SystemTime, SystemTime)>,
+    pub(crate) section_matcher: Option<SectionMatcher>,
+    pub(crate) ignore_case: bool,
+    pub(crate) ignore_whitespace: bool,
+    pub(crate) ignore_blank_lines: bool,
+    pub(crate) max_hunk_lines: Option<usize>,
+    pub(crate) lookahead: usize,
+    pub(crate) minimal: bool,
+    pub(crate) max_cost: Option<usize>,
 }
 
 impl Differ {
@@ -42,37 +172,515 @@ impl Differ {
     }
 
     /// Create a new Differ with the old and new content and a specified algorithm.
+    ///
+    /// A UTF-8 byte order mark at the start of both contents is not part of the
+    /// first line; one that is added or removed shows as a change to that line.
     pub fn new_with_algorithm(old: &str, new: &str, algorithm: DiffAlgorithmType) -> Self {
+        let (old, new) = match (old.strip_prefix(BOM), new.strip_prefix(BOM)) {
+            (Some(old), Some(new)) => (old, new),
+            _ => (old, new),
+        };
+        Self::from_input(
+            DiffInput::Text(old.to_string()),
+            DiffInput::Text(new.to_string()),
+            algorithm,
+        )
+    }
+
+    /// Create a new Differ from content that is already split into lines, using
+    /// the default algorithm (XDiff). Lines must not contain their terminators and
+    /// are treated as newline-terminated, so the patch is the same as for the
+    /// string path with every line followed by `\n`.
+    pub fn from_lines<S: AsRef<str>>(old: &[S], new: &[S]) -> Self {
+        let to_lines =
+            |lines: &[S]| DiffInput::Lines(lines.iter().map(|l| l.as_ref().to_string()).collect());
+        Self::from_input(to_lines(old), to_lines(new), DiffAlgorithmType::XDiff)
+    }
+
+    /// Set the diffing algorithm.
+    pub fn algorithm(mut self, algorithm: DiffAlgorithmType) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    fn from_input(old: DiffInput, new: DiffInput, algorithm: DiffAlgorithmType) -> Self {
         Self {
             algorithm,
-            old: old.to_string(),
-            new: new.to_string(),
+            old,
+            new,
             context_lines: 3, // Default number of context lines
+            old_prefix: "a/".to_string(),
+            new_prefix: "b/".to_string(),
+            file_names: None,
+            preamble: None,
+            timestamps: None,
+            section_matcher: None,
+            ignore_case: false,
+            ignore_whitespace: false,
+            ignore_blank_lines: false,
+            max_hunk_lines: None,
+            lookahead: 10,
+            minimal: false,
+            max_cost: None,
         }
     }
 
+    /// Split the content into records on `delimiter` instead of `\n`, e.g. `b'\0'`
+    /// for `find -print0` output. Apply the patch with a [`Patcher`](crate::Patcher)
+    /// using the same delimiter. Has no effect on content given as lines.
+    ///
+    /// Records may contain `\n`: in the patch, backslashes in records are
+    /// doubled and line feeds and carriage returns are written as `\n` and `\r`,
+    /// so each record stays on one line. The patcher undoes this.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is not ASCII.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        assert!(delimiter.is_ascii(), "delimiter must be an ASCII byte");
+        self.old = self.old.with_delimiter(delimiter);
+        self.new = self.new.with_delimiter(delimiter);
+        self
+    }
+
     /// Set the number of context lines to include in the generated patch.
     pub fn context_lines(mut self, lines: usize) -> Self {
         self.context_lines = lines;
         self
     }
+
+    /// Set the prefixes written before the file paths in the `---`/`+++` headers
+    /// of the generated patch (`a/` and `b/` by default). Pass empty strings for bare paths.
+    pub fn path_prefixes(mut self, old_prefix: &str, new_prefix: &str) -> Self {
+        self.old_prefix = old_prefix.to_string();
+        self.new_prefix = new_prefix.to_string();
+        self
+    }
+
+    /// Set the file paths recorded in the generated patch. Without an explicit
+    /// [`preamble`](Self::preamble), a `diff --git` line is derived from these names.
+    pub fn file_names(mut self, old: &str, new: &str) -> Self {
+        self.file_names = Some((old.to_string(), new.to_string()));
+        self
+    }
+
+    /// Set the preamble line of the generated patch, e.g. `diff -u old new`.
+    pub fn preamble(mut self, preamble: Option<String>) -> Self {
+        self.preamble = preamble;
+        self
+    }
+
+    /// Attach modification times to the `---`/`+++` headers, as GNU `diff -u` does.
+    pub fn timestamps(mut self, old: SystemTime, new: SystemTime) -> Self {
+        self.timestamps = Some((old, new));
+        self
+    }
+
+    /// Treat lines that differ only in letter case as equal. Unchanged lines are
+    /// written with their content from the new side.
+    pub fn ignore_case(mut self, ignore: bool) -> Self {
+        self.ignore_case = ignore;
+        self
+    }
+
+    /// Treat lines that differ only in whitespace as equal, like `git diff -w`.
+    /// Unchanged lines are written with their content from the new side.
+    pub fn ignore_whitespace(mut self, ignore: bool) -> Self {
+        self.ignore_whitespace = ignore;
+        self
+    }
+
+    /// Leave out chunks whose added and removed lines are all blank or
+    /// whitespace-only, like `diff -B`. Later chunks are renumbered as if those
+    /// lines had not changed, so the patch reproduces the new content except for
+    /// the ignored blank lines. A chunk that also changes other lines is kept whole.
+    pub fn ignore_blank_lines(mut self, ignore: bool) -> Self {
+        self.ignore_blank_lines = ignore;
+        self
+    }
+
+    /// Split chunks so that none has more than `lines` operations (context, added
+    /// and removed lines), e.g. to fit each hunk into a size-limited prompt.
+    ///
+    /// Chunks are preferably cut next to a context line, otherwise in the middle
+    /// of a run of changes, which leaves the pieces without context on that side.
+    /// Pieces that end up with only context are left out. The patch still
+    /// applies to the same result.
+    pub fn max_hunk_lines(mut self, lines: usize) -> Self {
+        self.max_hunk_lines = Some(lines.max(1));
+        self
+    }
+
+    /// Set how many lines ahead the naive algorithm searches on each side for the
+    /// next matching line after a mismatch (defaults to 10). Larger windows realign
+    /// past bigger insertions or deletions and repeated blocks, at a higher cost:
+    /// each mismatch compares up to `window * window` line pairs, so a diff of `n`
+    /// lines approaches O(n * window²). Other algorithms ignore this setting.
+    pub fn lookahead(mut self, window: usize) -> Self {
+        self.lookahead = window;
+        self
+    }
+
+    /// Always find the smallest possible diff, like `git diff --minimal`. The XDiff
+    /// algorithm otherwise gives up on a minimal edit script for large, dissimilar
+    /// inputs in favor of speed; other algorithms ignore this setting.
+    pub fn minimal(mut self, minimal: bool) -> Self {
+        self.minimal = minimal;
+        self
+    }
+
+    /// Bound the work spent searching for a small diff, so pathological inputs
+    /// yield a valid but possibly larger diff instead of taking very long.
+    ///
+    /// `cost` is the number of edit steps XDiff explores per split before settling
+    /// for the furthest-reaching path (by default the larger of 256 and the square
+    /// root of the input size; ignored with [`minimal`](Self::minimal)). Myers falls
+    /// back to that bounded search when its `old × new` line table would exceed
+    /// `cost²` cells, and the naive algorithm looks ahead at most `cost` lines.
+    /// The Similar algorithm ignores it. Use [`generate_with_stats`](Self::generate_with_stats)
+    /// to learn whether the limit was reached.
+    pub fn max_cost(mut self, cost: usize) -> Self {
+        self.max_cost = Some(cost.max(1));
+        self
+    }
+
+    /// Generates the patch like [`generate`](DiffAlgorithm::generate), along with
+    /// whether the search reached its cost limit (see [`max_cost`](Self::max_cost))
+    /// and settled for a best-effort diff. The patch still applies, but may not be
+    /// the smallest one.
+    pub fn generate_with_stats(&self) -> (Patch, bool) {
+        match self.algorithm {
+            DiffAlgorithmType::Myers => MyersDiffer::new(self).generate_with_stats(),
+            DiffAlgorithmType::Naive => NaiveDiffer::new(self).generate_with_stats(),
+            DiffAlgorithmType::XDiff => XDiffDiffer::new(self).generate_with_stats(),
+            DiffAlgorithmType::Similar => (SimilarDiffer::new(self).generate(), false),
+        }
+    }
+
+    /// The lines as the differs compare them, normalized according to
+    /// [`ignore_case`](Self::ignore_case) and [`ignore_whitespace`](Self::ignore_whitespace).
+    pub(crate) fn comparison_keys<'b>(&self, lines: &[&'b str]) -> Vec<Cow<'b, str>> {
+        lines
+            .iter()
+            .map(|&line| {
+                let mut key = Cow::Borrowed(line);
+                if self.ignore_whitespace {
+                    key = Cow::Owned(key.split_whitespace().collect());
+                }
+                if self.ignore_case {
+                    key = Cow::Owned(key.to_lowercase());
+                }
+                key
+            })
+            .collect()
+    }
+
+    /// Computes the raw edit script between the old and new lines with the
+    /// Myers (LCS) core, without grouping it into chunks with context.
+    ///
+    /// Unlike [`generate`](DiffAlgorithm::generate) this ignores the configured
+    /// algorithm. Runs of the same kind are merged, e.g. `[Equal(2), Delete(1), Insert(3)]`.
+    pub fn edit_script(&self) -> Vec<Edit> {
+        changes_to_edits(&myers::shortest_edit_script(
+            &self.comparison_keys(&self.old.lines()),
+            &self.comparison_keys(&self.new.lines()),
+        ))
+    }
+
+    /// Show the enclosing function after the `@@` of each chunk header, like git.
+    ///
+    /// Uses git's default heuristic: the nearest line above the chunk that starts
+    /// with a letter, `_` or `$`.
+    pub fn function_context(self) -> Self {
+        self.hunk_context_matcher(is_function_line)
+    }
+
+    /// Show the nearest line above each chunk for which `matcher` returns `true`
+    /// after the `@@` of the chunk header, e.g. to match a language's function
+    /// definitions more precisely than [`function_context`](Self::function_context).
+    pub fn hunk_context_matcher<F: Fn(&str) -> bool + Send + Sync + 'static>(
+        mut self,
+        matcher: F,
+    ) -> Self {
+        self.section_matcher = Some(Arc::new(matcher));
+        self
+    }
+
+    /// Fills in the section header of each chunk from the old content.
+    fn add_section_headers(&self, patch: &mut Patch) {
+        let Some(matcher) = &self.section_matcher else {
+            return;
+        };
+        let old_lines = self.old.lines();
+        for chunk in &mut patch.chunks {
+            let start = chunk.old_start.min(old_lines.len());
+            chunk.section_header = old_lines[..start]
+                .iter()
+                .rev()
+                .find(|line| matcher(line))
+                .map(|line| truncate_section_header(line));
+        }
+    }
+
+    /// A differ with the same settings for other content.
+    fn with_input(&self, old: DiffInput, new: DiffInput) -> Differ {
+        Differ {
+            algorithm: self.algorithm,
+            old,
+            new,
+            context_lines: self.context_lines,
+            old_prefix: self.old_prefix.clone(),
+            new_prefix: self.new_prefix.clone(),
+            file_names: self.file_names.clone(),
+            preamble: self.preamble.clone(),
+            timestamps: self.timestamps,
+            section_matcher: self.section_matcher.clone(),
+            ignore_case: self.ignore_case,
+            ignore_whitespace: self.ignore_whitespace,
+            ignore_blank_lines: self.ignore_blank_lines,
+            max_hunk_lines: self.max_hunk_lines,
+            lookahead: self.lookahead,
+            minimal: self.minimal,
+            max_cost: self.max_cost,
+        }
+    }
+
+    /// Generates the patch from the new content back to the old one, diffing the
+    /// two the other way around rather than inverting the forward patch.
+    ///
+    /// File names and timestamps are swapped along with the contents. Since the
+    /// algorithms are not all symmetric, the chunks can differ from those of
+    /// [`generate`](DiffAlgorithm::generate) with the sides exchanged.
+    pub fn generate_reverse(&self) -> Patch {
+        let mut differ = self.with_input(self.new.clone(), self.old.clone());
+        differ.file_names = self
+            .file_names
+            .as_ref()
+            .map(|(old, new)| (new.clone(), old.clone()));
+        differ.timestamps = self.timestamps.map(|(old, new)| (new, old));
+        differ.generate()
+    }
+
+    /// Extends `previous_patch`, generated by this differ, to the contents with
+    /// `appended_old` and `appended_new` added at the end, e.g. for growing logs.
+    ///
+    /// Only the appended text is diffed; its chunks are moved past the previous
+    /// content and merged onto the previous patch. If either previous content
+    /// lacks a trailing newline, the appended text continues its last line and
+    /// both contents are diffed again in full.
+    pub fn extend(&self, previous_patch: &Patch, appended_old: &str, appended_new: &str) -> Patch {
+        if self.old.missing_newline() || self.new.missing_newline() {
+            return self
+                .with_input(
+                    self.old.appended(appended_old),
+                    self.new.appended(appended_new),
+                )
+                .generate();
+        }
+
+        let mut tail_differ = self.with_input(
+            self.old.sibling(appended_old),
+            self.new.sibling(appended_new),
+        );
+        tail_differ.section_matcher = None;
+        let tail = tail_differ.generate();
+
+        let old_lines = self.old.lines();
+        let new_count = self.new.lines().len();
+        let tail_old_lines = tail_differ.old.lines();
+        let mut patch = previous_patch.clone();
+        for mut chunk in tail.chunks {
+            if let Some(matcher) = &self.section_matcher {
+                let start = chunk.old_start.min(tail_old_lines.len());
+                chunk.section_header = tail_old_lines[..start]
+                    .iter()
+                    .rev()
+                    .chain(old_lines.iter().rev())
+                    .find(|line| matcher(line))
+                    .map(|line| truncate_section_header(line));
+            }
+            chunk.old_start += old_lines.len();
+            chunk.new_start += new_count;
+            patch.chunks.push(chunk);
+        }
+        // The previous contents end with a newline, so only the tail can lack one
+        patch.old_missing_newline = tail.old_missing_newline;
+        patch.new_missing_newline = tail.new_missing_newline;
+        patch.coalesce();
+        patch
+    }
+
+    /// Apply the differ's header settings to a patch produced by an algorithm.
+    pub(crate) fn finalize_patch(&self, mut patch: Patch) -> Patch {
+        if let Some((old, new)) = &self.file_names {
+            patch.old_file = old.clone();
+            patch.new_file = new.clone();
+        }
+        patch.preamble = self.preamble.clone();
+        mark_missing_newlines(&mut patch, &self.old, &self.new, self.context_lines);
+        if self.ignore_blank_lines {
+            drop_blank_line_chunks(&mut patch);
+        }
+        if let Some(max) = self.max_hunk_lines {
+            split_large_chunks(&mut patch, max);
+        }
+        self.add_section_headers(&mut patch);
+        patch.old_prefix = self.old_prefix.clone();
+        patch.new_prefix = self.new_prefix.clone();
+        if let Some((old, new)) = self.timestamps {
+            patch.old_timestamp = Some(format_timestamp(old));
+            patch.new_timestamp = Some(format_timestamp(new));
+        }
+        patch
+    }
+}
+
+/// Removes the chunks that only add or remove blank lines, moving the new-side
+/// start of the chunks after them back by the lines they no longer add.
+fn drop_blank_line_chunks(patch: &mut Patch) {
+    let last = patch.chunks.len().saturating_sub(1);
+    let mut shift = 0isize;
+    let mut kept = Vec::with_capacity(patch.chunks.len());
+    for (index, mut chunk) in std::mem::take(&mut patch.chunks).into_iter().enumerate() {
+        let blank_only = chunk
+            .operations
+            .iter()
+            .filter(|op| op.is_change())
+            .all(|op| op.line().trim().is_empty());
+        if blank_only {
+            shift += chunk.old_lines as isize - chunk.new_lines as isize;
+            if index == last {
+                // The end of the file is no longer part of the patch
+                patch.old_missing_newline = false;
+                patch.new_missing_newline = false;
+            }
+            continue;
+        }
+        chunk.new_start = chunk.new_start.saturating_add_signed(shift);
+        kept.push(chunk);
+    }
+    patch.chunks = kept;
+}
+
+/// Cuts every chunk with more than `max` operations into pieces of at most `max`,
+/// see [`Differ::max_hunk_lines`].
+fn split_large_chunks(patch: &mut Patch, max: usize) {
+    let last = patch.chunks.len().saturating_sub(1);
+    let mut pieces = Vec::with_capacity(patch.chunks.len());
+    for (index, chunk) in std::mem::take(&mut patch.chunks).into_iter().enumerate() {
+        if chunk.operations.len() <= max {
+            pieces.push(chunk);
+            continue;
+        }
+        let ops = &chunk.operations;
+        let (mut start, mut old_start, mut new_start) = (0, chunk.old_start, chunk.new_start);
+        while start < ops.len() {
+            let end = if ops.len() - start <= max {
+                ops.len()
+            } else {
+                let limit = start + max;
+                let first_change = (start..limit).find(|&i| ops[i].is_change());
+                // The last cut next to a context line that keeps a change in this piece
+                first_change
+                    .and_then(|first| {
+                        (first + 1..=limit)
+                            .rev()
+                            .find(|&i| ops[i - 1].is_context() || ops[i].is_context())
+                    })
+                    .unwrap_or(limit)
+            };
+            let piece = Chunk::from_operations(old_start, new_start, ops[start..end].to_vec());
+            (old_start, new_start) = (old_start + piece.old_lines, new_start + piece.new_lines);
+            if piece.operations.iter().any(Operation::is_change) {
+                pieces.push(Chunk {
+                    section_header: chunk.section_header.clone().filter(|_| start == 0),
+                    ..piece
+                });
+            } else if index == last && end == ops.len() {
+                // The end of the file is no longer part of the patch
+                patch.old_missing_newline = false;
+                patch.new_missing_newline = false;
+            }
+            start = end;
+        }
+    }
+    patch.chunks = pieces;
+}
+
+/// Git's default function-name heuristic: a line starting with a letter, `_` or `$`.
+fn is_function_line(line: &str) -> bool {
+    line.starts_with(|c: char| c.is_alphabetic() || c == '_' || c == '$')
+}
+
+/// Trims trailing whitespace and, like git, keeps at most 80 bytes.
+fn truncate_section_header(line: &str) -> String {
+    let line = line.trim_end();
+    let mut end = line.len().min(80);
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line[..end].to_string()
 }
 
 impl DiffAlgorithm for Differ {
     fn generate(&self) -> Patch {
-        match self.algorithm {
-            DiffAlgorithmType::Myers => MyersDiffer::new(self).generate(),
-            DiffAlgorithmType::Naive => NaiveDiffer::new(self).generate(),
-            DiffAlgorithmType::XDiff => XDiffDiffer::new(self).generate(),
-            DiffAlgorithmType::Similar => SimilarDiffer::new(self).generate(),
-        }
+        self.generate_with_stats().0
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{PatchAlgorithm, Patcher};
+    use crate::{Operation, PatchAlgorithm, Patcher, PatcherAlgorithm};
+
+    #[test]
+    fn test_differ_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Differ>();
+    }
+
+    #[test]
+    fn test_function_context() {
+        let old = "fn first() {\n    1\n}\n\nfn second() {\n    let a = 1;\n    let b = 2;\n    let c = 3;\n    a + b + c\n}\n";
+        let new = old.replace("let c = 3;", "let c = 4;");
+
+        let patch = Differ::new(old, &new)
+            .context_lines(1)
+            .function_context()
+            .generate();
+        assert_eq!(
+            patch.chunks[0].section_header.as_deref(),
+            Some("fn second() {")
+        );
+        let header = |patch: &Patch| {
+            let text = patch.to_string();
+            text.lines()
+                .find(|l| l.starts_with("@@ "))
+                .unwrap()
+                .to_string()
+        };
+        assert!(header(&patch).ends_with(" @@ fn second() {"));
+
+        let patch = Differ::new(old, &new).context_lines(1).generate();
+        assert_eq!(patch.chunks[0].section_header, None);
+        assert!(header(&patch).ends_with(" @@"));
+    }
+
+    #[test]
+    fn test_edit_script() {
+        let differ = Differ::new("a\nb\nc\nd\n", "a\nx\ny\nc\n");
+        assert_eq!(
+            differ.edit_script(),
+            vec![
+                Edit::Equal(1),
+                Edit::Delete(1),
+                Edit::Insert(2),
+                Edit::Equal(1),
+                Edit::Delete(1),
+            ]
+        );
+        assert!(Differ::new("", "").edit_script().is_empty());
+    }
 
     #[test]
     fn test_different_algorithms_produce_valid_patches() {
@@ -117,4 +725,361 @@ mod tests {
         let xdiff_result = Patcher::new(xdiff_patch).apply(old, false).unwrap();
         assert_eq!(xdiff_result, new);
     }
+
+    #[test]
+    fn test_path_prefixes_round_trip() {
+        let old = "line1\nline2\nline3";
+        let new = "line1\nline2 modified\nline3";
+
+        let patch = Differ::new(old, new)
+            .path_prefixes("old/", "new/")
+            .generate();
+        let text = patch.to_string();
+        assert!(text.contains("--- old/original\n"));
+        assert!(text.contains("+++ new/modified\n"));
+        let parsed = Patch::parse(&text).unwrap();
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(Patcher::new(parsed).apply(old, false).unwrap(), new);
+
+        let patch = Differ::new(old, new).path_prefixes("", "").generate();
+        let text = patch.to_string();
+        assert!(text.contains("--- original\n"));
+        assert!(text.contains("+++ modified\n"));
+        let parsed = Patch::parse(&text).unwrap();
+        assert_eq!(parsed.old_file, "original");
+        assert_eq!(parsed.new_file, "modified");
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn test_file_names_and_preamble() {
+        let old = "line1\nline2\nline3";
+        let new = "line1\nline2 modified\nline3";
+
+        let patch = Differ::new(old, new)
+            .file_names("src/lib.rs", "src/lib.rs")
+            .generate();
+        assert_eq!(patch.old_file, "src/lib.rs");
+        assert_eq!(patch.new_file, "src/lib.rs");
+        let text = patch.to_string();
+        assert!(text.starts_with(
+            "diff --git a/src/lib.rs b/src/lib.rs\n--- a/src/lib.rs\n+++ b/src/lib.rs\n"
+        ));
+        let parsed = Patch::parse(&text).unwrap();
+        assert_eq!(
+            parsed.preamble.as_deref(),
+            Some("diff --git a/src/lib.rs b/src/lib.rs")
+        );
+        assert_eq!(parsed.old_file, "src/lib.rs");
+
+        let patch = Differ::new_with_algorithm(old, new, DiffAlgorithmType::Similar)
+            .file_names("old.txt", "new.txt")
+            .preamble(Some("diff -u old.txt new.txt".to_string()))
+            .generate();
+        assert_eq!(patch.old_file, "old.txt");
+        assert_eq!(patch.new_file, "new.txt");
+        assert!(patch.to_string().starts_with("diff -u old.txt new.txt\n"));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let old = "line1\nline2\nline3";
+        let new = "line1\nline2 modified\nline3";
+        let patch = Differ::new(old, new)
+            .file_names("file.txt", "file.txt")
+            .timestamps(
+                UNIX_EPOCH + Duration::from_secs(1_672_567_200),
+                UNIX_EPOCH + Duration::from_secs(1_672_653_600),
+            )
+            .generate();
+        let text = patch.to_string();
+        assert!(text.contains("--- a/file.txt\t2023-01-01 10:00:00.000000000 +0000\n"));
+        assert!(text.contains("+++ b/file.txt\t2023-01-02 10:00:00.000000000 +0000\n"));
+        let parsed = Patch::parse(&text).unwrap();
+        assert_eq!(parsed.old_file, "file.txt");
+        assert_eq!(parsed.to_string(), text);
+    }
+
+    #[test]
+    fn test_trailing_newline_combinations() {
+        let bodies = [
+            ("a\nb\nc", "a\nB\nc"),
+            ("1\n2\n3\n4\n5\n6\n7\n8\n9", "1\nTWO\n3\n4\n5\n6\n7\n8\n9"),
+            ("x\ny", "x\ny"),
+            ("only", "only"),
+        ];
+        for (old_body, new_body) in bodies {
+            for (old_nl, new_nl) in [(false, false), (false, true), (true, false), (true, true)] {
+                let old = format!("{}{}", old_body, if old_nl { "\n" } else { "" });
+                let new = format!("{}{}", new_body, if new_nl { "\n" } else { "" });
+                for algorithm in [
+                    DiffAlgorithmType::Myers,
+                    DiffAlgorithmType::Naive,
+                    DiffAlgorithmType::XDiff,
+                    DiffAlgorithmType::Similar,
+                ] {
+                    let patch = Differ::new_with_algorithm(&old, &new, algorithm).generate();
+                    let text = patch.to_string();
+                    let marker_lines = text
+                        .lines()
+                        .filter(|l| *l == "\\ No newline at end of file")
+                        .count();
+                    if old_nl != new_nl {
+                        assert_eq!(marker_lines, 1, "{:?} -> {:?}:\n{}", old, new, text);
+                    }
+                    let parsed = Patch::parse(&text).unwrap();
+                    assert_eq!(parsed.old_missing_newline, patch.old_missing_newline);
+                    assert_eq!(parsed.new_missing_newline, patch.new_missing_newline);
+                    for patcher_algorithm in [PatcherAlgorithm::Naive, PatcherAlgorithm::Similar] {
+                        let patcher =
+                            Patcher::new_with_algorithm(parsed.clone(), patcher_algorithm);
+                        assert_eq!(patcher.apply(&old, false).unwrap(), new, "{}", text);
+                        assert_eq!(patcher.apply(&new, true).unwrap(), old, "{}", text);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_lines_matches_string_input() {
+        let old: Vec<String> = ["fn main() {", "    println!(\"hi\");", "}"]
+            .map(String::from)
+            .to_vec();
+        let new: Vec<String> = ["fn main() {", "    println!(\"hello\");", "}"]
+            .map(String::from)
+            .to_vec();
+        let old_text = "fn main() {\n    println!(\"hi\");\n}\n";
+        let new_text = "fn main() {\n    println!(\"hello\");\n}\n";
+
+        for algorithm in [
+            DiffAlgorithmType::Myers,
+            DiffAlgorithmType::Naive,
+            DiffAlgorithmType::XDiff,
+            DiffAlgorithmType::Similar,
+        ] {
+            let from_lines = Differ::from_lines(&old, &new)
+                .algorithm(algorithm)
+                .generate();
+            let from_text = Differ::new_with_algorithm(old_text, new_text, algorithm).generate();
+            assert_eq!(from_lines, from_text);
+        }
+    }
+
+    #[test]
+    fn test_zero_context_hunk_boundaries() {
+        // Expected headers match `git diff -U0`: one hunk per run of changes.
+        let cases: [(&str, &str, &[&str]); 4] = [
+            (
+                "a\nb\nc\nd\ne\nf\n",
+                "a\nB\nc\nD\nE\nf\n",
+                &["@@ -2 +2 @@", "@@ -4,2 +4,2 @@"],
+            ),
+            (
+                "a\nb\nc\n",
+                "x\na\nb\nc\ny\n",
+                &["@@ -0,0 +1 @@", "@@ -3,0 +5 @@"],
+            ),
+            (
+                "a\nb\nc\nd\n",
+                "a\nc\n",
+                &["@@ -2 +1,0 @@", "@@ -4 +2,0 @@"],
+            ),
+            (
+                "1\n2\n3\n4\n5\n6\n7\n",
+                "1\nX\n3\n4\n5\nY\n7\n",
+                &["@@ -2 +2 @@", "@@ -6 +6 @@"],
+            ),
+        ];
+
+        for (old, new, expected) in cases {
+            for algorithm in [
+                DiffAlgorithmType::Myers,
+                DiffAlgorithmType::Naive,
+                DiffAlgorithmType::XDiff,
+                DiffAlgorithmType::Similar,
+            ] {
+                let patch = Differ::new_with_algorithm(old, new, algorithm)
+                    .context_lines(0)
+                    .generate();
+                let headers: Vec<String> = patch
+                    .chunks
+                    .iter()
+                    .map(|chunk| chunk.to_string().lines().next().unwrap().to_string())
+                    .collect();
+                assert_eq!(headers, expected, "{algorithm:?}: {old:?} -> {new:?}");
+                assert!(
+                    patch
+                        .chunks
+                        .iter()
+                        .flat_map(|chunk| &chunk.operations)
+                        .all(|op| !op.is_context())
+                );
+                assert_eq!(Patcher::new(patch).apply(old, false).unwrap(), new);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ignore_case_and_whitespace() {
+        for algorithm in [
+            DiffAlgorithmType::Myers,
+            DiffAlgorithmType::Naive,
+            DiffAlgorithmType::XDiff,
+            DiffAlgorithmType::Similar,
+        ] {
+            let patch = Differ::new_with_algorithm("Hello\n", "hello\n", algorithm)
+                .ignore_case(true)
+                .generate();
+            assert!(patch.chunks.is_empty(), "{algorithm:?}");
+
+            let patch = Differ::new_with_algorithm("a  b\nc\n", "a b\nc\n", algorithm)
+                .ignore_whitespace(true)
+                .generate();
+            assert!(patch.chunks.is_empty(), "{algorithm:?}");
+
+            // Unchanged lines keep the new content
+            let patch = Differ::new_with_algorithm("One\ntwo\n", "one\nTWO!\n", algorithm)
+                .ignore_case(true)
+                .generate();
+            assert_eq!(
+                patch.chunks[0].operations,
+                vec![
+                    Operation::Context("one".to_string()),
+                    Operation::Remove("two".to_string()),
+                    Operation::Add("TWO!".to_string()),
+                ],
+                "{algorithm:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_hunk_lines() {
+        let old: String = (0..100).map(|i| format!("line {i}\n")).collect();
+        let inserted: String = (0..500).map(|i| format!("new {i}\n")).collect();
+        let new = old.replace("line 50\n", &format!("line 50\n{inserted}"));
+
+        let patch = Differ::new(&old, &new).max_hunk_lines(50).generate();
+        assert!(
+            (10..=11).contains(&patch.chunks.len()),
+            "{}",
+            patch.chunks.len()
+        );
+        assert!(
+            patch
+                .chunks
+                .iter()
+                .all(|chunk| chunk.operations.len() <= 50)
+        );
+        patch.validate().unwrap();
+        assert_eq!(Patcher::new(patch.clone()).apply(&old, false).unwrap(), new);
+        assert_eq!(Patcher::new(patch).apply(&new, true).unwrap(), old);
+
+        // Changes every few lines merge into one chunk, which is cut next to context
+        let new: String = (0..100)
+            .map(|i| match i % 4 {
+                0 => format!("changed {i}\n"),
+                _ => format!("line {i}\n"),
+            })
+            .collect();
+        let patch = Differ::new(&old, &new).max_hunk_lines(10).generate();
+        assert!(patch.chunks.len() > 1);
+        for chunk in &patch.chunks {
+            assert!(chunk.operations.len() <= 10);
+            assert!(
+                chunk.operations.last().unwrap().is_context()
+                    || chunk.old_start + chunk.old_lines == 100
+            );
+        }
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar);
+        assert_eq!(patcher.apply(&old, false).unwrap(), new);
+    }
+
+    #[test]
+    fn test_ignore_blank_lines() {
+        let old: String = (1..=20)
+            .map(|i| format!("line {i}\n"))
+            .collect::<String>()
+            .replace("line 10\n", "line 10\n\n");
+        // Adds a blank and a whitespace-only line and removes a blank line
+        let blank_only = old
+            .replace("line 3\n", "line 3\n\n  \n")
+            .replace("line 10\n\n", "line 10\n");
+        let patch = Differ::new(&old, &blank_only)
+            .ignore_blank_lines(true)
+            .generate();
+        assert!(patch.chunks.is_empty());
+
+        // Only the real change is kept, at its line in the content the patch produces
+        let new = blank_only.replace("line 19\n", "nineteen\n");
+        let patch = Differ::new(&old, &new).ignore_blank_lines(true).generate();
+        assert_eq!(patch.chunks.len(), 1);
+        assert_eq!(patch.chunks[0].new_start, patch.chunks[0].old_start);
+        assert_eq!(
+            Patcher::new(patch).apply(&old, false).unwrap(),
+            old.replace("line 19\n", "nineteen\n")
+        );
+    }
+
+    #[test]
+    fn test_generate_reverse() {
+        let old = "a\nb\nc\nd\n";
+        let new = "a\nc\nx\nd\ne";
+
+        for algorithm in [
+            DiffAlgorithmType::Myers,
+            DiffAlgorithmType::Naive,
+            DiffAlgorithmType::XDiff,
+            DiffAlgorithmType::Similar,
+        ] {
+            let differ =
+                Differ::new_with_algorithm(old, new, algorithm).file_names("old.txt", "new.txt");
+            let patch = differ.generate_reverse();
+            assert_eq!(
+                (patch.old_file.as_str(), patch.new_file.as_str()),
+                ("new.txt", "old.txt")
+            );
+            assert_eq!(Patcher::new(patch).apply(new, false).unwrap(), old);
+        }
+    }
+
+    #[test]
+    fn test_extend_appended_content() {
+        let old: String = (1..=10).map(|i| format!("log {i}\n")).collect();
+        let new = old.replace("log 2\n", "log two\n");
+        let differ = Differ::new(&old, &new);
+        let previous = differ.generate();
+
+        // Far from the previous change: same chunks as a full diff
+        let appended_old: String = (11..=20).map(|i| format!("log {i}\n")).collect();
+        let appended_new = appended_old.replace("log 18\n", "log eighteen\n");
+        let extended = differ.extend(&previous, &appended_old, &appended_new);
+        let full_old = format!("{old}{appended_old}");
+        let full_new = format!("{new}{appended_new}");
+        assert_eq!(
+            extended.chunks,
+            Differ::new(&full_old, &full_new).generate().chunks
+        );
+
+        // Right at the seam: chunks are merged
+        let differ = Differ::new("a\nb\n", "a\nB\n");
+        let extended = differ.extend(&differ.generate(), "c\n", "C\nd");
+        assert_eq!(extended.chunks.len(), 1);
+        assert!(extended.new_missing_newline);
+        assert_eq!(
+            Patcher::new(extended).apply("a\nb\nc\n", false).unwrap(),
+            "a\nB\nC\nd"
+        );
+
+        // Without a trailing newline the appended text continues the last line
+        let differ = Differ::new("a\nb", "a\nb");
+        let extended = differ.extend(&differ.generate(), "c\n", "d\n");
+        assert_eq!(
+            Patcher::new(extended).apply("a\nbc\n", false).unwrap(),
+            "a\nbd\n"
+        );
+    }
 }
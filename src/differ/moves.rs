@@ -0,0 +1,152 @@
+//! Detection of blocks of lines that were moved rather than removed and added.
+
+use super::{DiffAlgorithm, Differ};
+use crate::{Operation, Patch};
+
+/// A block of lines removed at one place and added unchanged at another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    /// First line of the block in the old content (0-based)
+    pub old_start: usize,
+    /// First line of the block in the new content (0-based)
+    pub new_start: usize,
+    /// Number of lines in the block
+    pub lines: usize,
+}
+
+/// A patch together with the moved blocks among its changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveAwarePatch {
+    /// The patch, unchanged: a moved block is still removed and added
+    pub patch: Patch,
+    /// The moved blocks, in the order of their old lines
+    pub moves: Vec<Move>,
+}
+
+impl MoveAwarePatch {
+    /// The new line a removed old line was moved to, if it is part of a move.
+    pub fn moved_to(&self, old_line: usize) -> Option<usize> {
+        self.moves
+            .iter()
+            .find(|m| (m.old_start..m.old_start + m.lines).contains(&old_line))
+            .map(|m| m.new_start + (old_line - m.old_start))
+    }
+
+    /// The old line an added new line was moved from, if it is part of a move.
+    pub fn moved_from(&self, new_line: usize) -> Option<usize> {
+        self.moves
+            .iter()
+            .find(|m| (m.new_start..m.new_start + m.lines).contains(&new_line))
+            .map(|m| m.old_start + (new_line - m.new_start))
+    }
+}
+
+/// A run of consecutive removed or added lines and where it starts.
+struct Run<'a> {
+    start: usize,
+    lines: Vec<&'a str>,
+}
+
+/// The runs of removed and of added lines in `patch`.
+fn change_runs(patch: &Patch) -> (Vec<Run<'_>>, Vec<Run<'_>>) {
+    let (mut removed, mut added) = (Vec::<Run>::new(), Vec::<Run>::new());
+    for chunk in &patch.chunks {
+        let (mut old_line, mut new_line) = (chunk.old_start, chunk.new_start);
+        let mut previous: Option<&Operation> = None;
+        for op in &chunk.operations {
+            let continues = previous.is_some_and(|previous| {
+                previous.is_add() == op.is_add() && previous.is_remove() == op.is_remove()
+            });
+            match op {
+                Operation::Remove(line) => {
+                    match removed.last_mut() {
+                        Some(run) if continues => run.lines.push(line),
+                        _ => removed.push(Run {
+                            start: old_line,
+                            lines: vec![line],
+                        }),
+                    }
+                    old_line += 1;
+                }
+                Operation::Add(line) => {
+                    match added.last_mut() {
+                        Some(run) if continues => run.lines.push(line),
+                        _ => added.push(Run {
+                            start: new_line,
+                            lines: vec![line],
+                        }),
+                    }
+                    new_line += 1;
+                }
+                Operation::Context(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+            }
+            previous = Some(op);
+        }
+    }
+    (removed, added)
+}
+
+impl Differ {
+    /// Generates the patch like [`generate`](DiffAlgorithm::generate) and finds
+    /// the blocks in it that were moved: a run of removed lines with the same
+    /// content as a run of added lines elsewhere.
+    ///
+    /// Runs are matched whole and each run is used at most once, first come
+    /// first served. Runs of only blank lines are not counted as moves.
+    pub fn generate_with_moves(&self) -> MoveAwarePatch {
+        let patch = self.generate();
+        let (removed, added) = change_runs(&patch);
+        let mut used = vec![false; added.len()];
+        let mut moves = Vec::new();
+        for run in &removed {
+            if run.lines.iter().all(|line| line.trim().is_empty()) {
+                continue;
+            }
+            let Some(index) =
+                (0..added.len()).find(|&index| !used[index] && added[index].lines == run.lines)
+            else {
+                continue;
+            };
+            used[index] = true;
+            moves.push(Move {
+                old_start: run.start,
+                new_start: added[index].start,
+                lines: run.lines.len(),
+            });
+        }
+        MoveAwarePatch { patch, moves }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_with_moves() {
+        let old = "a\nmoved\n\nc\nd\ne\nf\ng\n";
+        let new = "a\nc\nd\ne\nf\nmoved\n\ng\nh\n";
+
+        let result = Differ::new(old, new).context_lines(1).generate_with_moves();
+        assert_eq!(
+            result.patch,
+            Differ::new(old, new).context_lines(1).generate()
+        );
+        assert_eq!(
+            result.moves,
+            vec![Move {
+                old_start: 1,
+                new_start: 5,
+                lines: 2,
+            }]
+        );
+        assert_eq!(result.moved_to(2), Some(6));
+        assert_eq!(result.moved_from(5), Some(1));
+        assert_eq!(result.moved_to(0), None);
+        // The added last line has no removed counterpart
+        assert_eq!(result.moved_from(8), None);
+    }
+}
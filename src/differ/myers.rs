@@ -1,7 +1,9 @@
 use crate::Differ;
-use crate::differ::{Change, DiffAlgorithm};
+use crate::differ::{Change, ChangeCollector, Diff, DiffAlgorithm};
+use std::cell::Cell;
 
-use super::{create_patch, handle_empty_files, process_changes_to_chunks};
+use super::common::diff_trimmed;
+use super::{XDiffDiffer, create_patch, handle_empty_files, process_changes_to_chunks};
 
 /// The Myers differ implementation that uses Myers algorithm for diffing
 ///
@@ -11,102 +13,172 @@ use super::{create_patch, handle_empty_files, process_changes_to_chunks};
 /// this implementation prioritizes correctness and readability based on LCS.
 pub struct MyersDiffer<'a> {
     differ: &'a Differ,
+    /// Whether the diff fell back to the bounded search during the current diff
+    cost_exceeded: Cell<bool>,
 }
 
 impl<'a> MyersDiffer<'a> {
     /// Create a new MyersDiffer from a base Differ instance
     pub fn new(differ: &'a Differ) -> Self {
-        Self { differ }
+        Self {
+            differ,
+            cost_exceeded: Cell::new(false),
+        }
     }
 
-    /// Implements a diffing algorithm based on Myers' principles (using LCS)
-    /// Finds the shortest edit script (SES) between old_lines and new_lines
-    fn myers_diff(&self, old_lines: &[&str], new_lines: &[&str]) -> Vec<Change> {
-        // Special cases for empty inputs
-        if old_lines.is_empty() && new_lines.is_empty() {
-            return Vec::new();
-        }
-        if old_lines.is_empty() {
-            // All new lines are insertions
-            return vec![Change::Insert(0, new_lines.len())];
-        }
-        if new_lines.is_empty() {
-            // All old lines are deletions
-            return vec![Change::Delete(0, old_lines.len())];
-        }
-        // If files are identical, return no changes
-        if old_lines == new_lines {
-            return Vec::new();
-        }
+    /// Generates the patch and whether the table exceeded the cost limit.
+    pub(crate) fn generate_with_stats(&self) -> (crate::Patch, bool) {
+        let patch = self.generate();
+        (patch, self.cost_exceeded.get())
+    }
+}
 
-        // Use Longest Common Subsequence (LCS) table to find the differences.
-        // This is equivalent to finding the shortest edit path in Myers' algorithm,
-        // although the O(ND) version avoids constructing the full table explicitly.
-        let n = old_lines.len();
-        let m = new_lines.len();
-        // `lcs[i][j]` stores the length of the LCS between old_lines[0..i] and new_lines[0..j]
-        let mut lcs = vec![vec![0; m + 1]; n + 1];
-        for i in 1..=n {
-            for j in 1..=m {
-                if old_lines[i - 1] == new_lines[j - 1] {
-                    lcs[i][j] = lcs[i - 1][j - 1] + 1; // Match: extend LCS diagonally
-                } else {
-                    // No match: take max LCS from deletion (up) or insertion (left)
-                    lcs[i][j] = std::cmp::max(lcs[i - 1][j], lcs[i][j - 1]);
+/// Diffs two sequences with the Myers (LCS) core and feeds the edit script
+/// to `d`, merging consecutive equal items into a single run.
+pub fn myers_diff<D: Diff, T: PartialEq>(d: &mut D, old: &[T], new: &[T]) -> Result<(), D::Error> {
+    let (mut old_index, mut new_index) = (0, 0);
+    let mut changes = shortest_edit_script(old, new).into_iter().peekable();
+    while let Some(change) = changes.next() {
+        match change {
+            Change::Equal(_, _) => {
+                let mut len = 1;
+                while changes
+                    .next_if(|c| matches!(c, Change::Equal(_, _)))
+                    .is_some()
+                {
+                    len += 1;
                 }
+                d.equal(old_index, new_index, len)?;
+                old_index += len;
+                new_index += len;
+            }
+            Change::Delete(_, count) => {
+                d.delete(old_index, count, new_index)?;
+                old_index += count;
+            }
+            Change::Insert(_, count) => {
+                d.insert(old_index, new_index, count)?;
+                new_index += count;
             }
         }
+    }
+    d.finish()
+}
+
+/// Diffs two sequences and returns the edit script collected by a [`ChangeCollector`].
+pub fn myers_diff_collect<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Change> {
+    let mut collector = ChangeCollector::new();
+    let Ok(()) = myers_diff(&mut collector, old, new);
+    collector.into_changes()
+}
+
+/// Implements a diffing algorithm based on Myers' principles (using LCS)
+/// Finds the shortest edit script (SES) between old_lines and new_lines
+///
+/// Only the lines between the common prefix and suffix go into the LCS table.
+pub(crate) fn shortest_edit_script<T: PartialEq>(old_lines: &[T], new_lines: &[T]) -> Vec<Change> {
+    diff_trimmed(old_lines, new_lines, lcs_edit_script)
+}
 
-        // Backtrack through the LCS table to reconstruct the edit script (Changes)
-        let mut changes = Vec::new();
-        let mut i = n;
-        let mut j = m;
-        while i > 0 || j > 0 {
-            if i > 0 && j > 0 && old_lines[i - 1] == new_lines[j - 1] {
-                // Match found: move diagonally up-left
-                changes.push(Change::Equal(i - 1, j - 1));
-                i -= 1;
-                j -= 1;
-            } else if j > 0 && (i == 0 || lcs[i][j - 1] >= lcs[i - 1][j]) {
-                // Insertion preferred (or only choice): move left
-                changes.push(Change::Insert(j - 1, 1));
-                j -= 1;
-            } else if i > 0 {
-                // Deletion preferred (or only choice): move up
-                changes.push(Change::Delete(i - 1, 1));
-                i -= 1;
+/// Finds the shortest edit script with a full `old × new` LCS table.
+fn lcs_edit_script<T: PartialEq>(old_lines: &[T], new_lines: &[T]) -> Vec<Change> {
+    // Special cases for empty inputs
+    if old_lines.is_empty() && new_lines.is_empty() {
+        return Vec::new();
+    }
+    if old_lines.is_empty() {
+        // All new lines are insertions
+        return vec![Change::Insert(0, new_lines.len())];
+    }
+    if new_lines.is_empty() {
+        // All old lines are deletions
+        return vec![Change::Delete(0, old_lines.len())];
+    }
+    // If files are identical, return no changes
+    if old_lines == new_lines {
+        return Vec::new();
+    }
+
+    // Use Longest Common Subsequence (LCS) table to find the differences.
+    // This is equivalent to finding the shortest edit path in Myers' algorithm,
+    // although the O(ND) version avoids constructing the full table explicitly.
+    let n = old_lines.len();
+    let m = new_lines.len();
+    // `lcs[i][j]` stores the length of the LCS between old_lines[0..i] and new_lines[0..j]
+    let mut lcs = vec![vec![0; m + 1]; n + 1];
+    for i in 1..=n {
+        for j in 1..=m {
+            if old_lines[i - 1] == new_lines[j - 1] {
+                lcs[i][j] = lcs[i - 1][j - 1] + 1; // Match: extend LCS diagonally
             } else {
-                // Should be unreachable if LCS table and backtracking are correct
-                break;
+                // No match: take max LCS from deletion (up) or insertion (left)
+                lcs[i][j] = std::cmp::max(lcs[i - 1][j], lcs[i][j - 1]);
             }
         }
+    }
 
-        // Changes were collected in reverse order during backtrack
-        changes.reverse();
-
-        // Merging adjacent operations is not needed here, as process_changes_to_chunks
-        // expects individual changes (including single Change::Equal). The old logic
-        // for merging Delete/Insert/Equal has been removed.
-        changes
+    // Backtrack through the LCS table to reconstruct the edit script (Changes)
+    let mut changes = Vec::new();
+    let mut i = n;
+    let mut j = m;
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 && old_lines[i - 1] == new_lines[j - 1] {
+            // Match found: move diagonally up-left
+            changes.push(Change::Equal(i - 1, j - 1));
+            i -= 1;
+            j -= 1;
+        } else if j > 0 && (i == 0 || lcs[i][j - 1] >= lcs[i - 1][j]) {
+            // Insertion preferred (or only choice): move left
+            changes.push(Change::Insert(j - 1, 1));
+            j -= 1;
+        } else if i > 0 {
+            // Deletion preferred (or only choice): move up
+            changes.push(Change::Delete(i - 1, 1));
+            i -= 1;
+        } else {
+            // Should be unreachable if LCS table and backtracking are correct
+            break;
+        }
     }
+
+    // Changes were collected in reverse order during backtrack
+    changes.reverse();
+
+    // Merging adjacent operations is not needed here, as process_changes_to_chunks
+    // expects individual changes (including single Change::Equal). The old logic
+    // for merging Delete/Insert/Equal has been removed.
+    changes
 }
 
 impl DiffAlgorithm for MyersDiffer<'_> {
     /// Generate a patch between the old and new content using the Myers diffing algorithm (LCS based)
     fn generate(&self) -> crate::Patch {
-        let old_lines: Vec<&str> = self.differ.old.lines().collect();
-        let new_lines: Vec<&str> = self.differ.new.lines().collect();
+        self.cost_exceeded.set(false);
+        let old_lines: Vec<&str> = self.differ.old.lines();
+        let new_lines: Vec<&str> = self.differ.new.lines();
         // Handle special cases for empty files
         if let Some(patch) = handle_empty_files(&old_lines, &new_lines) {
-            return patch;
+            return self.differ.finalize_patch(patch);
         }
         // Find the line-level changes using Myers/LCS
-        let changes = self.myers_diff(&old_lines, &new_lines);
+        let old_keys = self.differ.comparison_keys(&old_lines);
+        let new_keys = self.differ.comparison_keys(&new_lines);
+        // The LCS table has a cell per pair of lines between the common prefix and
+        // suffix; past the cost limit, use XDiff's bounded search
+        let changes = diff_trimmed(&old_keys, &new_keys, |old, new| {
+            match self.differ.max_cost {
+                Some(cost) if old.len() * new.len() > cost.saturating_mul(cost) => {
+                    self.cost_exceeded.set(true);
+                    XDiffDiffer::new(self.differ).xdiff(old, new)
+                }
+                _ => lcs_edit_script(old, new),
+            }
+        });
         // Process the changes into chunks with context
         let chunks =
             process_changes_to_chunks(&changes, &old_lines, &new_lines, self.differ.context_lines);
         // Create the final patch
-        create_patch(chunks)
+        self.differ.finalize_patch(create_patch(chunks))
     }
 }
 
@@ -115,6 +187,22 @@ mod tests {
     use super::*;
     use crate::{PatchAlgorithm, Patcher, test_utils::load_fixture};
 
+    #[test]
+    fn test_myers_diff_collect_bytes() {
+        let old: Vec<u8> = b"abcd".to_vec();
+        let new: Vec<u8> = b"abxd".to_vec();
+        assert_eq!(
+            myers_diff_collect(&old, &new),
+            vec![
+                Change::Equal(0, 0),
+                Change::Equal(1, 1),
+                Change::Delete(2, 1),
+                Change::Insert(2, 1),
+                Change::Equal(3, 3),
+            ]
+        );
+    }
+
     #[test]
     fn test_simple_myers_diff() {
         let old = "line1\nline2\nline3";
@@ -1,25 +1,38 @@
 use crate::differ::{Change, DiffAlgorithm};
 use crate::{Differ, Patch};
+use std::cell::Cell;
 use std::cmp::min;
 
+use super::common::diff_trimmed;
 use super::{create_patch, handle_empty_files, process_changes_to_chunks};
 
 /// The Naive differ implementation
 pub struct NaiveDiffer<'a> {
     differ: &'a Differ,
+    /// Whether a lookahead capped by the cost limit missed a match during the current diff
+    cost_exceeded: Cell<bool>,
 }
 
 impl<'a> NaiveDiffer<'a> {
     /// Create a new NaiveDiffer from a base Differ instance
     pub fn new(differ: &'a Differ) -> Self {
-        Self { differ }
+        Self {
+            differ,
+            cost_exceeded: Cell::new(false),
+        }
+    }
+
+    /// Generates the patch and whether the cost limit cut a lookahead short.
+    pub(crate) fn generate_with_stats(&self) -> (Patch, bool) {
+        let patch = self.generate();
+        (patch, self.cost_exceeded.get())
     }
 
     /// Find the next match looking ahead a certain number of lines
-    fn find_next_match(
+    fn find_next_match<T: PartialEq>(
         &self,
-        old_lines: &[&str],
-        new_lines: &[&str],
+        old_lines: &[T],
+        new_lines: &[T],
         max_look_ahead: usize,
     ) -> (usize, usize) {
         let max_old_look_ahead = min(old_lines.len(), max_look_ahead);
@@ -37,7 +50,7 @@ impl<'a> NaiveDiffer<'a> {
     }
 
     /// Find line-level changes between old and new content using a simple heuristic
-    fn find_line_changes(&self, old_lines: &[&str], new_lines: &[&str]) -> Vec<Change> {
+    fn find_line_changes<T: PartialEq>(&self, old_lines: &[T], new_lines: &[T]) -> Vec<Change> {
         let mut changes = Vec::new();
         let mut i = 0; // current index for old_lines
         let mut j = 0; // current index for new_lines
@@ -49,10 +62,20 @@ impl<'a> NaiveDiffer<'a> {
                 i += 1;
                 j += 1;
             } else {
-                // Lines differ, look ahead for a match
-                // Note: A small lookahead (e.g., 10) keeps it somewhat naive
+                // Lines differ, look ahead for a match within the configured window
+                let window = self.differ.max_cost.map_or(self.differ.lookahead, |cost| {
+                    cost.min(self.differ.lookahead)
+                });
                 let (skip_old, skip_new) =
-                    self.find_next_match(&old_lines[i..], &new_lines[j..], 10);
+                    self.find_next_match(&old_lines[i..], &new_lines[j..], window);
+                // A match beyond the capped window goes unseen
+                if skip_old == 0
+                    && skip_new == 0
+                    && window < self.differ.lookahead
+                    && (old_lines.len() - i > window || new_lines.len() - j > window)
+                {
+                    self.cost_exceeded.set(true);
+                }
 
                 if skip_old > 0 {
                     // If a match was found skipping some old lines, mark them as deleted
@@ -91,19 +114,24 @@ impl<'a> NaiveDiffer<'a> {
 impl DiffAlgorithm for NaiveDiffer<'_> {
     /// Generate a patch between the old and new content using the naive diffing algorithm
     fn generate(&self) -> Patch {
-        let old_lines: Vec<&str> = self.differ.old.lines().collect();
-        let new_lines: Vec<&str> = self.differ.new.lines().collect();
+        self.cost_exceeded.set(false);
+        let old_lines: Vec<&str> = self.differ.old.lines();
+        let new_lines: Vec<&str> = self.differ.new.lines();
         // Handle special cases for empty files
         if let Some(patch) = handle_empty_files(&old_lines, &new_lines) {
-            return patch;
+            return self.differ.finalize_patch(patch);
         }
-        // Find the line-level changes
-        let changes = self.find_line_changes(&old_lines, &new_lines);
+        // Find the line-level changes between the common prefix and suffix
+        let changes = diff_trimmed(
+            &self.differ.comparison_keys(&old_lines),
+            &self.differ.comparison_keys(&new_lines),
+            |old, new| self.find_line_changes(old, new),
+        );
         // Process the changes into chunks with context
         let chunks =
             process_changes_to_chunks(&changes, &old_lines, &new_lines, self.differ.context_lines);
         // Create the final patch
-        create_patch(chunks)
+        self.differ.finalize_patch(create_patch(chunks))
     }
 }
 
@@ -125,6 +153,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lookahead_window() {
+        let old: String = (0..20).map(|i| format!("line {i}\n")).collect();
+        // A changed last line keeps the unchanged lines from being trimmed as a common suffix
+        let new = (0..15).map(|i| format!("new {i}\n")).collect::<String>() + &old + "end\n";
+        let removed = |window: usize| {
+            let differ =
+                Differ::new_with_algorithm(&old, &new, DiffAlgorithmType::Naive).lookahead(window);
+            let patch = NaiveDiffer::new(&differ).generate();
+            assert_eq!(Patcher::new(patch.clone()).apply(&old, false).unwrap(), new);
+            patch
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.operations)
+                .filter(|op| op.is_remove())
+                .count()
+        };
+
+        // The first unchanged line is 15 lines away, out of reach of the default window
+        assert_eq!(removed(10), 20);
+        assert_eq!(removed(50), 0);
+    }
+
     #[test]
     fn test_add_line() {
         let old = "line1\nline2\nline3";
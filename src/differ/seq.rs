@@ -0,0 +1,164 @@
+use super::Change;
+use super::myers::shortest_edit_script;
+
+/// An operation on a single item of a generic sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeqOperation<T> {
+    /// Add a new item
+    Add(T),
+    /// Remove an item
+    Remove(T),
+    /// Context item (unchanged)
+    Context(T),
+}
+
+/// A continuous section of changes in a sequence, with surrounding context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqChunk<T> {
+    /// Starting index in the original sequence
+    pub old_start: usize,
+    /// Number of items in the original sequence
+    pub old_len: usize,
+    /// Starting index in the new sequence
+    pub new_start: usize,
+    /// Number of items in the new sequence
+    pub new_len: usize,
+    /// The operations in this chunk
+    pub operations: Vec<SeqOperation<T>>,
+}
+
+/// The differences between two sequences of arbitrary items, the generic
+/// counterpart of [`Patch`](crate::Patch)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeqPatch<T> {
+    pub chunks: Vec<SeqChunk<T>>,
+}
+
+/// Diffs two sequences with the Myers (LCS) core, keeping 3 items of context
+/// around each change like [`Differ`](crate::Differ) does for lines.
+pub fn diff_seq<T: Clone + PartialEq>(old: &[T], new: &[T]) -> SeqPatch<T> {
+    diff_seq_with_context(old, new, 3)
+}
+
+/// Diffs two sequences, keeping `context` unchanged items around each change.
+pub fn diff_seq_with_context<T: Clone + PartialEq>(
+    old: &[T],
+    new: &[T],
+    context: usize,
+) -> SeqPatch<T> {
+    // Every item of the edit script with the old and new positions before it
+    let mut script = Vec::with_capacity(old.len().max(new.len()));
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for change in shortest_edit_script(old, new) {
+        match change {
+            Change::Equal(_, _) => {
+                script.push((
+                    old_pos,
+                    new_pos,
+                    SeqOperation::Context(old[old_pos].clone()),
+                ));
+                old_pos += 1;
+                new_pos += 1;
+            }
+            Change::Delete(_, count) => {
+                for _ in 0..count {
+                    script.push((old_pos, new_pos, SeqOperation::Remove(old[old_pos].clone())));
+                    old_pos += 1;
+                }
+            }
+            Change::Insert(_, count) => {
+                for _ in 0..count {
+                    script.push((old_pos, new_pos, SeqOperation::Add(new[new_pos].clone())));
+                    new_pos += 1;
+                }
+            }
+        }
+    }
+
+    // Ranges of the script covered by each chunk, merging changes whose
+    // context would overlap or touch
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in script
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, _, op))| !matches!(op, SeqOperation::Context(_)))
+    {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(script.len());
+        match ranges.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let chunks = ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let items = &script[start..end];
+            let operations: Vec<SeqOperation<T>> =
+                items.iter().map(|(_, _, op)| op.clone()).collect();
+            let old_len = operations
+                .iter()
+                .filter(|op| !matches!(op, SeqOperation::Add(_)))
+                .count();
+            let new_len = operations
+                .iter()
+                .filter(|op| !matches!(op, SeqOperation::Remove(_)))
+                .count();
+            SeqChunk {
+                old_start: items[0].0,
+                old_len,
+                new_start: items[0].1,
+                new_len,
+                operations,
+            }
+        })
+        .collect();
+
+    SeqPatch { chunks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Ident(&'static str),
+        Number(i64),
+    }
+
+    #[test]
+    fn test_diff_seq_tokens() {
+        use Token::*;
+        let old = vec![Ident("let"), Ident("x"), Number(1)];
+        let new = vec![Ident("let"), Ident("x"), Number(2)];
+
+        let patch = diff_seq_with_context(&old, &new, 1);
+        assert_eq!(patch.chunks.len(), 1);
+        let chunk = &patch.chunks[0];
+        assert_eq!((chunk.old_start, chunk.old_len), (1, 2));
+        assert_eq!((chunk.new_start, chunk.new_len), (1, 2));
+        assert_eq!(
+            chunk.operations,
+            vec![
+                SeqOperation::Context(Ident("x")),
+                SeqOperation::Remove(Number(1)),
+                SeqOperation::Add(Number(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_seq_splits_distant_changes() {
+        let old: Vec<u32> = (0..20).collect();
+        let mut new = old.clone();
+        new[2] = 100;
+        new[17] = 200;
+
+        let patch = diff_seq(&old, &new);
+        assert_eq!(patch.chunks.len(), 2);
+        assert_eq!(patch.chunks[1].old_start, 14);
+        assert!(diff_seq(&old, &old).chunks.is_empty());
+    }
+}
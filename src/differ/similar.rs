@@ -1,7 +1,17 @@
-use super::{DiffAlgorithm, Differ};
-use crate::patch::{Chunk, Operation, Patch};
+use super::{
+    Change, DiffAlgorithm, Differ, create_patch, handle_empty_files, process_changes_to_chunks,
+};
+use crate::patch::Patch;
 use similar::{Algorithm as SimilarAlgorithm, DiffTag, TextDiff};
 
+/// The differ backed by the `similar` crate's patience diff
+///
+/// Patience diff anchors on lines that occur exactly once in both files, such as
+/// function signatures, so a rewritten block shows as one block of removed lines
+/// followed by its replacement instead of being interleaved with matching braces
+/// and blank lines. Between the anchors it finds a shortest edit script. The
+/// edit script is grouped into chunks like the other differs, so the patches
+/// apply with any [`Patcher`](crate::Patcher) algorithm.
 pub struct SimilarDiffer<'a> {
     differ: &'a Differ,
 }
@@ -14,82 +24,39 @@ impl<'a> SimilarDiffer<'a> {
 
 impl DiffAlgorithm for SimilarDiffer<'_> {
     fn generate(&self) -> Patch {
-        let old_lines: Vec<&str> = self.differ.old.lines().collect();
-        let new_lines: Vec<&str> = self.differ.new.lines().collect();
+        let old_lines: Vec<&str> = self.differ.old.lines();
+        let new_lines: Vec<&str> = self.differ.new.lines();
+        if let Some(patch) = handle_empty_files(&old_lines, &new_lines) {
+            return self.differ.finalize_patch(patch);
+        }
 
+        let old_keys = self.differ.comparison_keys(&old_lines);
+        let new_keys = self.differ.comparison_keys(&new_lines);
+        let old_keys: Vec<&str> = old_keys.iter().map(AsRef::as_ref).collect();
+        let new_keys: Vec<&str> = new_keys.iter().map(AsRef::as_ref).collect();
         let diff = TextDiff::configure()
             .algorithm(SimilarAlgorithm::Patience)
-            .diff_lines(&self.differ.old, &self.differ.new);
-
-        let mut patch_chunks = Vec::new();
-
-        let grouped_ops = diff.grouped_ops(self.differ.context_lines);
-
-        for group in grouped_ops {
-            let first_op = group.first().expect("Group should not be empty");
-            let _last_op = group.last().expect("Group should not be empty");
-
-            let chunk_old_start = first_op.old_range().start;
-            let chunk_new_start = first_op.new_range().start;
-
-            let mut actual_old_lines = 0;
-            let mut actual_new_lines = 0;
-            let mut chunk_operations = Vec::new();
-
-            for op in group {
-                match op.tag() {
-                    DiffTag::Equal => {
-                        for i in op.old_range() {
-                            chunk_operations.push(Operation::Context(old_lines[i].to_string()));
-                        }
-                        actual_old_lines += op.old_range().len();
-                        actual_new_lines += op.new_range().len();
-                    }
-                    DiffTag::Delete => {
-                        for i in op.old_range() {
-                            chunk_operations.push(Operation::Remove(old_lines[i].to_string()));
-                        }
-                        actual_old_lines += op.old_range().len();
-                    }
-                    DiffTag::Insert => {
-                        for j in op.new_range() {
-                            chunk_operations.push(Operation::Add(new_lines[j].to_string()));
-                        }
-                        actual_new_lines += op.new_range().len();
-                    }
-                    DiffTag::Replace => {
-                        for i in op.old_range() {
-                            chunk_operations.push(Operation::Remove(old_lines[i].to_string()));
-                        }
-                        for j in op.new_range() {
-                            chunk_operations.push(Operation::Add(new_lines[j].to_string()));
-                        }
-                        actual_old_lines += op.old_range().len();
-                        actual_new_lines += op.new_range().len();
-                    }
+            .diff_slices(&old_keys, &new_keys);
+
+        // Only the index on the side an operation covers is reliable: patience
+        // diff may report the other side's index off by the length of a move
+        let mut changes = Vec::new();
+        for op in diff.ops() {
+            let (old, new) = (op.old_range(), op.new_range());
+            match op.tag() {
+                DiffTag::Equal => changes.extend(old.zip(new).map(|(o, n)| Change::Equal(o, n))),
+                DiffTag::Delete => changes.push(Change::Delete(old.start, old.len())),
+                DiffTag::Insert => changes.push(Change::Insert(new.start, new.len())),
+                DiffTag::Replace => {
+                    changes.push(Change::Delete(old.start, old.len()));
+                    changes.push(Change::Insert(new.start, new.len()));
                 }
             }
-
-            if chunk_operations
-                .iter()
-                .any(|op| !matches!(op, Operation::Context(_)))
-            {
-                patch_chunks.push(Chunk {
-                    old_start: chunk_old_start,
-                    old_lines: actual_old_lines,
-                    new_start: chunk_new_start,
-                    new_lines: actual_new_lines,
-                    operations: chunk_operations,
-                });
-            }
         }
 
-        Patch {
-            old_file: "a".to_string(),
-            new_file: "b".to_string(),
-            chunks: patch_chunks,
-            preamble: None,
-        }
+        let chunks =
+            process_changes_to_chunks(&changes, &old_lines, &new_lines, self.differ.context_lines);
+        self.differ.finalize_patch(create_patch(chunks))
     }
 }
 
@@ -200,6 +167,30 @@ mod tests {
         assert_eq!(result, new);
     }
 
+    #[test]
+    fn test_multi_change_round_trip() {
+        let old = "fn a() {\n    1\n}\n\nfn b() {\n    2\n}\n\nfn c() {\n    3\n}\n";
+        let new = "fn a() {\n    one\n}\n\nfn c() {\n    3\n}\n\nfn d() {\n    4\n}";
+        for context in [0, 1, 3] {
+            let patch = Differ::new_with_algorithm(old, new, DiffAlgorithmType::Similar)
+                .context_lines(context)
+                .generate();
+            patch.validate().unwrap();
+            let patcher = Patcher::new(patch);
+            assert_eq!(patcher.apply(old, false).unwrap(), new);
+            assert_eq!(patcher.apply(new, true).unwrap(), old);
+        }
+    }
+
+    #[test]
+    fn test_chunk_starting_with_moved_lines() {
+        // Patience diff reports the deletion at the start at new line 1, not 0
+        let old = "l0\nl3\nl3\nl2\nl0\nl0\nl3\nl2\nl1\n";
+        let new = "l2\nl2\nl0";
+        let result = run_diff_and_apply(old, new, 1);
+        assert_eq!(result, new);
+    }
+
     // Test integration with the main Differ struct
     #[test]
     fn test_differ_integration() {
@@ -211,4 +202,27 @@ mod tests {
         let result = Patcher::new(patch).apply(old, false).unwrap();
         assert_eq!(result, new);
     }
+
+    #[test]
+    fn test_hunk_counts_on_fixtures() {
+        // Patience diff trades a shortest edit script for fewer, cleaner blocks
+        for (before, after, expected) in [
+            ("simple_before.rs", "simple_after.rs", [1, 1, 1]),
+            ("complex_before.rs", "complex_after.rs", [2, 2, 2]),
+        ] {
+            let (original, modified) = (load_fixture(before), load_fixture(after));
+            let hunks = [
+                DiffAlgorithmType::Myers,
+                DiffAlgorithmType::XDiff,
+                DiffAlgorithmType::Similar,
+            ]
+            .map(|algorithm| {
+                Differ::new_with_algorithm(&original, &modified, algorithm)
+                    .generate()
+                    .chunks
+                    .len()
+            });
+            assert_eq!(hunks, expected, "{before}: Myers, XDiff, Similar");
+        }
+    }
 }
@@ -2,6 +2,7 @@ use tracing::warn;
 
 use crate::differ::{Change, DiffAlgorithm};
 use crate::{Differ, Patch};
+use std::cell::Cell;
 use std::cmp::{max, min};
 
 use super::{create_patch, handle_empty_files, process_changes_to_chunks};
@@ -37,22 +38,39 @@ struct SplitPoint {
 /// XDiff differ implementation based on LibXDiff algorithm
 pub struct XDiffDiffer<'a> {
     differ: &'a Differ,
+    /// Whether the search reached its cost limit during the current diff
+    cost_exceeded: Cell<bool>,
 }
 
 impl<'a> XDiffDiffer<'a> {
     /// Create a new XDiffDiffer from a base Differ instance
     pub fn new(differ: &'a Differ) -> Self {
-        Self { differ }
+        Self {
+            differ,
+            cost_exceeded: Cell::new(false),
+        }
+    }
+
+    /// Generates the patch and whether the search reached its cost limit.
+    pub(crate) fn generate_with_stats(&self) -> (Patch, bool) {
+        let patch = self.generate();
+        (patch, self.cost_exceeded.get())
     }
 
     /// Implementation of the XDiff algorithm based on xdl_do_diff and xdl_recs_cmp
-    fn xdiff(&self, old_lines: &[&str], new_lines: &[&str]) -> Vec<Change> {
+    pub(crate) fn xdiff<T: AsRef<str>>(&self, old_lines: &[T], new_lines: &[T]) -> Vec<Change> {
         let old_len = old_lines.len();
         let new_len = new_lines.len();
 
         // Create hash vectors for faster comparison
-        let old_hash: Vec<u64> = old_lines.iter().map(|&line| self.hash_line(line)).collect();
-        let new_hash: Vec<u64> = new_lines.iter().map(|&line| self.hash_line(line)).collect();
+        let old_hash: Vec<u64> = old_lines
+            .iter()
+            .map(|line| self.hash_line(line.as_ref()))
+            .collect();
+        let new_hash: Vec<u64> = new_lines
+            .iter()
+            .map(|line| self.hash_line(line.as_ref()))
+            .collect();
 
         // Initialize change markers
         // Note: C uses 1-based indexing in rchg internally, but markers are applied to 0-based lines.
@@ -74,15 +92,16 @@ impl<'a> XDiffDiffer<'a> {
         // Calculate heuristic parameters
         // bogosqrt approximation: sqrt(N) - adjust if needed
         let approx_sqrt = (ndiags as f64).sqrt() as usize;
-        let mxcost = max(approx_sqrt, XDL_MAX_COST_MIN);
+        let mxcost = self
+            .differ
+            .max_cost
+            .unwrap_or(max(approx_sqrt, XDL_MAX_COST_MIN));
         let env = AlgoEnv {
             mxcost,
             snake_cnt: XDL_SNAKE_CNT,
             heur_min: XDL_HEUR_MIN_COST,
-            // TODO: Integrate XDF_NEED_MINIMAL flag if available. Currently hardcoded to false,
-            // meaning the algorithm might perform heuristic cutoffs even if a guaranteed
-            // minimal diff is requested elsewhere.
-            need_min: false,
+            // XDF_NEED_MINIMAL: skip the heuristics and cost cutoff entirely
+            need_min: self.differ.minimal,
         };
 
         // Run the recursive comparison
@@ -477,6 +496,7 @@ impl<'a> XDiffDiffer<'a> {
 
                 // Cutoff: Max cost reached, find furthest reaching point
                 if ec >= env.mxcost {
+                    self.cost_exceeded.set(true);
                     let mut fbest_val = -1;
                     let mut fbest_i1 = NEG_ONE;
 
@@ -533,13 +553,8 @@ impl<'a> XDiffDiffer<'a> {
                     }
                 }
             }
-            // If need_min is true, we skip heuristics and continue until overlap or error
-            else if env.need_min && ec >= env.mxcost {
-                // Avoid infinite loop if need_min is true and no overlap found within cost limit
-                // This condition isn't explicitly in C's xdl_split loop, but needed for safety
-                warn!("XDiff: Max cost reached in minimal mode without finding overlap.");
-                return Err(()); // Indicate failure
-            }
+            // If need_min is true, we skip heuristics and continue until the paths overlap,
+            // which they do after at most (N + M + 1) / 2 iterations
         } // End main loop (ec)
 
         // Should not be reached if logic is correct, but needed for compiler
@@ -652,23 +667,27 @@ impl<'a> XDiffDiffer<'a> {
 impl DiffAlgorithm for XDiffDiffer<'_> {
     /// Generate a patch between the old and new content using the XDiff algorithm
     fn generate(&self) -> Patch {
-        let old_lines: Vec<&str> = self.differ.old.lines().collect();
-        let new_lines: Vec<&str> = self.differ.new.lines().collect();
+        self.cost_exceeded.set(false);
+        let old_lines: Vec<&str> = self.differ.old.lines();
+        let new_lines: Vec<&str> = self.differ.new.lines();
 
         // Handle special cases for empty files
         if let Some(patch) = handle_empty_files(&old_lines, &new_lines) {
-            return patch;
+            return self.differ.finalize_patch(patch);
         }
 
         // Find the line-level changes using the XDiff implementation
-        let changes = self.xdiff(&old_lines, &new_lines);
+        let changes = self.xdiff(
+            &self.differ.comparison_keys(&old_lines),
+            &self.differ.comparison_keys(&new_lines),
+        );
 
         // Process the changes into chunks with context
         let chunks =
             process_changes_to_chunks(&changes, &old_lines, &new_lines, self.differ.context_lines);
 
         // Create the final patch
-        create_patch(chunks)
+        self.differ.finalize_patch(create_patch(chunks))
     }
 }
 
@@ -680,6 +699,55 @@ mod tests {
     // Keeping existing tests - they should still pass if the algorithm is correct,
     // though the exact chunking might differ slightly from the previous LCS impl.
 
+    #[test]
+    fn test_minimal_diff() {
+        // Large, dissimilar inputs make the heuristics give up on a minimal diff
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut lines = |n| -> String { (0..n).map(|_| format!("l{}\n", rng.u8(0..4))).collect() };
+        let (old, new) = (lines(2000), lines(2000));
+        let changed = |differ: Differ| {
+            let patch = differ.generate();
+            assert_eq!(Patcher::new(patch.clone()).apply(&old, false).unwrap(), new);
+            patch
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.operations)
+                .filter(|op| op.is_change())
+                .count()
+        };
+
+        let heuristic = changed(Differ::new(&old, &new));
+        let minimal = changed(Differ::new(&old, &new).minimal(true));
+        let shortest = changed(Differ::new_with_algorithm(
+            &old,
+            &new,
+            DiffAlgorithmType::Myers,
+        ));
+        assert!(minimal < heuristic, "{minimal} >= {heuristic}");
+        assert_eq!(minimal, shortest);
+    }
+
+    #[test]
+    fn test_max_cost() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let mut lines = |n| -> String { (0..n).map(|_| format!("l{}\n", rng.u8(0..4))).collect() };
+        let (old, new) = (lines(500), lines(500));
+
+        for algorithm in [
+            DiffAlgorithmType::XDiff,
+            DiffAlgorithmType::Myers,
+            DiffAlgorithmType::Naive,
+        ] {
+            let differ = Differ::new_with_algorithm(&old, &new, algorithm).max_cost(4);
+            let (patch, cost_exceeded) = differ.generate_with_stats();
+            assert!(cost_exceeded, "{algorithm:?}");
+            assert_eq!(Patcher::new(patch).apply(&old, false).unwrap(), new);
+            // The same differ reports afresh for other content
+            let small = Differ::new_with_algorithm("a\nb\n", "a\nc\n", algorithm).max_cost(4);
+            assert!(!small.generate_with_stats().1, "{algorithm:?}");
+        }
+    }
+
     #[test]
     fn test_simple_xdiff() {
         let old = "line1\\nline2\\nline3";
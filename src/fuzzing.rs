@@ -0,0 +1,140 @@
+//! `Arbitrary` implementations for fuzzing, enabled by the `arbitrary` feature.
+//!
+//! Generated chunks always have `old_lines`/`new_lines` matching their
+//! operations, and generated patches have ordered, non-overlapping chunks whose
+//! new-side starts account for the lines added and removed before them.
+
+use crate::{Chunk, Operation, Patch};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// An arbitrary line of text, without line terminators.
+fn line(u: &mut Unstructured<'_>) -> Result<String> {
+    let text = String::arbitrary(u)?;
+    Ok(text.replace(['\n', '\r'], ""))
+}
+
+/// An arbitrary relative path of ASCII letters, digits, `.`, `_` and `/`.
+fn path(u: &mut Unstructured<'_>) -> Result<String> {
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789._/";
+    let len = u.int_in_range(1..=16)?;
+    (0..len)
+        .map(|_| u.choose(CHARS).map(|&c| c as char))
+        .collect()
+}
+
+/// Non-empty operations for a chunk.
+fn operations(u: &mut Unstructured<'_>) -> Result<Vec<Operation>> {
+    let len = u.int_in_range(1..=16)?;
+    (0..len).map(|_| Operation::arbitrary(u)).collect()
+}
+
+/// A chunk at the given start lines, with counts derived from its operations.
+fn chunk_at(u: &mut Unstructured<'_>, old_start: usize, new_start: usize) -> Result<Chunk> {
+    let operations = operations(u)?;
+    let old_lines = operations.iter().filter(|op| !op.is_add()).count();
+    let new_lines = operations.iter().filter(|op| !op.is_remove()).count();
+    let section_header = if bool::arbitrary(u)? {
+        Some(line(u)?)
+    } else {
+        None
+    };
+    Ok(Chunk {
+        old_start,
+        old_lines,
+        new_start,
+        new_lines,
+        operations,
+        section_header,
+    })
+}
+
+impl<'a> Arbitrary<'a> for Operation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let line = line(u)?;
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Operation::Add(line),
+            1 => Operation::Remove(line),
+            _ => Operation::Context(line),
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Chunk {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let old_start = u.int_in_range(0..=1000)?;
+        let new_start = u.int_in_range(0..=1000)?;
+        chunk_at(u, old_start, new_start)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Patch {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let old_file = path(u)?;
+        let new_file = if bool::arbitrary(u)? {
+            old_file.clone()
+        } else {
+            path(u)?
+        };
+        let count = u.int_in_range(0..=8)?;
+        let mut chunks: Vec<Chunk> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (old_end, new_end) = chunks.last().map_or((0, 0), |chunk| {
+                (
+                    chunk.old_start + chunk.old_lines,
+                    chunk.new_start + chunk.new_lines,
+                )
+            });
+            // Keep at least one unchanged line between chunks
+            let gap = u.int_in_range(usize::from(!chunks.is_empty())..=20)?;
+            chunks.push(chunk_at(u, old_end + gap, new_end + gap)?);
+        }
+        Ok(Patch {
+            old_file,
+            new_file,
+            chunks,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PatchAlgorithm, Patcher};
+
+    /// The original content a patch applies to: its context and removed lines,
+    /// with distinct filler lines for the gaps between chunks.
+    fn source_of(patch: &Patch) -> String {
+        let mut lines = Vec::new();
+        for chunk in &patch.chunks {
+            while lines.len() < chunk.old_start {
+                lines.push(format!("\u{0}filler {}", lines.len()));
+            }
+            lines.extend(
+                chunk
+                    .operations
+                    .iter()
+                    .filter(|op| !op.is_add())
+                    .map(|op| op.line().to_string()),
+            );
+        }
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+
+    #[test]
+    fn test_arbitrary_patch_round_trip() {
+        let mut rng = fastrand::Rng::with_seed(42);
+        for _ in 0..500 {
+            let bytes: Vec<u8> = (0..rng.usize(0..2048)).map(|_| rng.u8(..)).collect();
+            let Ok(patch) = Patch::arbitrary(&mut Unstructured::new(&bytes)) else {
+                continue;
+            };
+            patch.validate().unwrap();
+
+            let source = source_of(&patch);
+            let patcher = Patcher::new(patch);
+            let patched = patcher.apply(&source, false).unwrap();
+            assert_eq!(patcher.apply(&patched, true).unwrap(), source);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Sliding ambiguous hunk boundaries to natural block boundaries, like git's
+//! `--indent-heuristic`.
+
+use crate::{Operation, Patch};
+
+/// Width of the leading whitespace of `line`, with tabs advancing to the next
+/// multiple of 8, or `None` for a blank line.
+fn indentation(line: &str) -> Option<usize> {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 8 - width % 8,
+            c if c.is_whitespace() => {}
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+/// How far the block `operations[start..end]` is from starting and ending at
+/// block boundaries: the indentation of its first and last non-blank lines.
+fn penalty(operations: &[Operation], start: usize, end: usize) -> usize {
+    let mut indents = operations[start..end]
+        .iter()
+        .filter_map(|op| indentation(op.line()));
+    let first = indents.next().unwrap_or(0);
+    let last = indents.next_back().unwrap_or(first);
+    first + last
+}
+
+/// Moves the run of changes `operations[*start..*end]` one line up, or down
+/// with `down`, by turning the context line it moves onto into a change and
+/// the line it leaves into context. The lines themselves stay in order.
+fn slide(
+    operations: &mut [Operation],
+    start: &mut usize,
+    end: &mut usize,
+    down: bool,
+    change: fn(String) -> Operation,
+) {
+    let (entered, left) = if down {
+        (*end, *start)
+    } else {
+        (*start - 1, *end - 1)
+    };
+    operations[entered] = change(operations[entered].line().to_string());
+    operations[left] = Operation::Context(operations[left].line().to_string());
+    if down {
+        (*start, *end) = (*start + 1, *end + 1);
+    } else {
+        (*start, *end) = (*start - 1, *end - 1);
+    }
+}
+
+impl Patch {
+    /// Slides each run of only added or only removed lines that could move up or
+    /// down, because the context line on one side repeats its line on the other,
+    /// to where the run starts and ends at the lowest indentation. On a tie the
+    /// lowest position wins.
+    ///
+    /// This keeps e.g. an added function together with its closing brace instead
+    /// of reusing the closing brace of the function before it. The patched
+    /// content is unchanged; only which lines count as context moves.
+    pub fn apply_indent_heuristic(&mut self) {
+        let last = self.chunks.len().saturating_sub(1);
+        let pinned_end = self.old_missing_newline || self.new_missing_newline;
+        for (index, chunk) in self.chunks.iter_mut().enumerate() {
+            let ops = &mut chunk.operations;
+            // The no-newline marker belongs to the final line, which must stay in place
+            let len = if pinned_end && index == last {
+                ops.len().saturating_sub(1)
+            } else {
+                ops.len()
+            };
+
+            let mut start = 0;
+            while start < len {
+                if ops[start].is_context() {
+                    start += 1;
+                    continue;
+                }
+                let change: fn(String) -> Operation = if ops[start].is_add() {
+                    Operation::Add
+                } else {
+                    Operation::Remove
+                };
+                let same_kind = |op: &Operation| op.is_add() == ops[start].is_add();
+                let mut end = start;
+                while end < len && ops[end].is_change() && same_kind(&ops[end]) {
+                    end += 1;
+                }
+                // Only runs bounded by context (or the chunk edges) can slide
+                let bounded_above = start == 0 || ops[start - 1].is_context();
+                let bounded_below = end >= ops.len() || ops[end].is_context();
+                if !bounded_above || !bounded_below {
+                    start = end;
+                    continue;
+                }
+
+                let can_slide_up = |ops: &[Operation], start: usize, end: usize| {
+                    start > 0
+                        && ops[start - 1].is_context()
+                        && ops[start - 1].line() == ops[end - 1].line()
+                };
+                let can_slide_down = |ops: &[Operation], start: usize, end: usize| {
+                    end < len && ops[end].is_context() && ops[end].line() == ops[start].line()
+                };
+                while can_slide_up(ops, start, end) {
+                    slide(ops, &mut start, &mut end, false, change);
+                }
+                let (mut best, mut best_penalty) = (start, penalty(ops, start, end));
+                while can_slide_down(ops, start, end) {
+                    slide(ops, &mut start, &mut end, true, change);
+                    let penalty = penalty(ops, start, end);
+                    if penalty <= best_penalty {
+                        (best, best_penalty) = (start, penalty);
+                    }
+                }
+                while start > best {
+                    slide(ops, &mut start, &mut end, false, change);
+                }
+                start = end;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PatchAlgorithm, Patcher};
+
+    #[test]
+    fn test_indent_heuristic_keeps_added_function_whole() {
+        let old = "fn a() {\n    x();\n}\n";
+        let new = "fn a() {\n    x();\n}\n\nfn b() {\n    x();\n}\n";
+        let mut patch = Patch::parse(
+            "\
+--- a/lib.rs
++++ b/lib.rs
+@@ -1,3 +1,7 @@
+ fn a() {
+     x();
++}
++
++fn b() {
++    x();
+ }
+",
+        )
+        .unwrap();
+
+        patch.apply_indent_heuristic();
+        let context = |line: &str| Operation::Context(line.to_string());
+        let add = |line: &str| Operation::Add(line.to_string());
+        assert_eq!(
+            patch.chunks[0].operations,
+            vec![
+                context("fn a() {"),
+                context("    x();"),
+                context("}"),
+                add(""),
+                add("fn b() {"),
+                add("    x();"),
+                add("}"),
+            ]
+        );
+        assert_eq!(Patcher::new(patch).apply(old, false).unwrap(), new);
+    }
+}
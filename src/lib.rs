@@ -1,16 +1,36 @@
 pub mod differ;
 pub mod patcher;
 
+mod builder;
+mod compose;
+#[cfg(feature = "arbitrary")]
+mod fuzzing;
+mod indent_heuristic;
 mod multipatch;
 mod patch;
 
 use thiserror::Error;
 
 // Re-export the differ implementations for convenience
-pub use differ::{DiffAlgorithm, Differ, MyersDiffer, NaiveDiffer};
-pub use multipatch::{ApplyResult, MultifilePatch, MultifilePatcher, PatchedFile};
-pub use patch::{Chunk, Operation, Patch};
-pub use patcher::{NaivePatcher, PatchAlgorithm, Patcher, PatcherAlgorithm};
+pub use builder::{ChunkBuilder, PatchBuilder};
+#[cfg(feature = "unicode")]
+pub use differ::WordOp;
+pub use differ::{
+    DiffAlgorithm, Differ, Edit, Move, MoveAwarePatch, MyersDiffer, NaiveDiffer, SeqChunk,
+    SeqOperation, SeqPatch, diff_seq,
+};
+pub use multipatch::{
+    ApplyResult, DiskFileSource, FileSource, MultifilePatch, MultifilePatcher, PatchedFile,
+    ProgressCallback,
+};
+pub use patch::{
+    Chunk, CombinedChunk, CombinedLine, LineMapping, Operation, ParseLimits, Patch,
+    compose_line_maps,
+};
+pub use patcher::{
+    ApplyReport, DefaultSimilarity, HunkApplication, HunkStatus, LineSimilarity, NaivePatcher,
+    PatchAlgorithm, Patcher, PatcherAlgorithm,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -20,27 +40,94 @@ pub enum Error {
     #[error("Invalid patch format: {0}")]
     InvalidPatchFormat(String),
 
+    #[error("Hunk {second} overlaps the region already patched by hunk {first}")]
+    OverlappingHunks { first: usize, second: usize },
+
+    #[error("Patch applies neither forward nor in reverse")]
+    NotApplicable,
+
+    #[error("Context mismatch at line {line_num}: expected '{expected}', got '{actual}'")]
+    ContextMismatch {
+        line_num: usize,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Removed line mismatch at line {line_num}: expected '{expected}', got '{actual}'")]
+    RemovedLineMismatch {
+        line_num: usize,
+        expected: String,
+        actual: String,
+    },
+
     #[error("Line {line_num} not found in content while applying patch")]
     LineNotFound { line_num: usize },
 
-    #[error("IO error: {0}")]
-    IoError(#[from] std::io::Error),
+    #[error("IO error{}: {source}", path_suffix(path))]
+    IoError {
+        /// The file the operation failed on, if known
+        path: Option<std::path::PathBuf>,
+        #[source]
+        source: std::io::Error,
+    },
 
     #[error("File not found: {path}")]
     FileNotFound { path: String },
 
-    #[error("Could not parse chunk header: {header}")]
-    InvalidChunkHeader { header: String },
+    #[error("Target already exists: {path}")]
+    TargetExists { path: String },
 
-    #[error("Could not parse number '{value}' for {field}: {source}")]
-    InvalidNumberFormat {
-        value: String,
-        field: String,
-        #[source]
-        source: std::num::ParseIntError,
+    #[error("Could not parse chunk header '{header}': {reason}")]
+    InvalidChunkHeader {
+        header: String,
+        reason: ChunkHeaderProblem,
     },
 }
 
+/// What is wrong with a chunk header, as reported by `Error::InvalidChunkHeader`.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChunkHeaderProblem {
+    #[error("expected the form '@@ -start,count +start,count @@'")]
+    Malformed,
+
+    #[error("the old range does not start with '-'")]
+    MissingOldRange,
+
+    #[error("the new range does not start with '+'")]
+    MissingNewRange,
+
+    #[error("'{value}' is not a valid {field}")]
+    InvalidNumber { value: String, field: String },
+
+    #[error("range '{range}' starts at line 0 but is not empty")]
+    ZeroStartWithLines { range: String },
+}
+
+impl Error {
+    /// An `Error::IoError` for an operation on the file at `path`.
+    pub fn io<P: AsRef<std::path::Path>>(path: P, source: std::io::Error) -> Self {
+        Error::IoError {
+            path: Some(path.as_ref().to_path_buf()),
+            source,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::IoError { path: None, source }
+    }
+}
+
+fn path_suffix(path: &Option<std::path::PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| format!(" on {}", path.display()))
+        .unwrap_or_default()
+}
+
+/// The UTF-8 byte order mark some editors write at the start of a file
+pub(crate) const BOM: char = '\u{feff}';
+
 #[cfg(test)]
 mod test_utils {
     pub(crate) fn load_fixture(name: &str) -> String {
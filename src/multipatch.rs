@@ -1,14 +1,21 @@
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fs::{self, File};
-use std::io::{self, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use tracing::warn;
 
-use crate::{Error, Patch, PatchAlgorithm, Patcher};
+use crate::patch::parse_chunk_header;
+use crate::patcher::{normalize_whitespace, similarity_score};
+use crate::{
+    DiffAlgorithm, Differ, Error, HunkApplication, Operation, Patch, Patcher, PatcherAlgorithm,
+};
 
 /// Represents a file that has been patched.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PatchedFile {
     /// Path to the file relative to the application root.
     pub path: String,
@@ -18,6 +25,11 @@ pub struct PatchedFile {
     pub is_new: bool,
     /// Indicates whether the file was deleted by the patch.
     pub is_deleted: bool,
+    /// Where each chunk of the patch was applied, including any offset from its header.
+    pub hunks: Vec<HunkApplication>,
+    /// Indicates whether the patch only changed metadata, such as the name or the
+    /// mode, and `content` is the source content unchanged.
+    pub metadata_only: bool,
 }
 
 /// A collection of patches for multiple files, typically parsed from a unified diff format.
@@ -27,14 +39,113 @@ pub struct MultifilePatch {
     pub patches: Vec<Patch>,
 }
 
+/// Callback invoked with `(index, total, path)` before each patch is applied.
+pub type ProgressCallback = Box<dyn Fn(usize, usize, &str) + Send + Sync>;
+
+/// Storage that a `MultifilePatcher` reads the files to patch from and writes
+/// the results to. `DiskFileSource` is the real filesystem; other implementations
+/// can keep the files in memory, e.g. to apply patches in tests or a sandbox.
+pub trait FileSource {
+    /// Reads the file at `path`. A missing file is an `io::ErrorKind::NotFound` error.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Writes `content` to the file at `path`, creating or replacing it.
+    fn write(&self, path: &Path, content: &str) -> io::Result<()>;
+
+    /// Deletes the file at `path`.
+    fn delete(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns whether anything exists at `path`.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Checks that `write` would succeed without writing anything, for `plan`.
+    fn check_write(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Checks that `delete` would succeed without deleting anything, for `plan`.
+    fn check_delete(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Removes the empty directory at `path`, which `write` created for a new file,
+    /// when `apply_and_write_atomic` rolls back. Sources without directories can
+    /// keep the default, which does nothing.
+    fn remove_dir(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The real filesystem. Writing a file creates its missing parent directories.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskFileSource;
+
+impl FileSource for DiskFileSource {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(path).and_then(|mut output_file| output_file.write_all(content.as_bytes()))
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn check_write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            check_can_create_dir_all(parent)?;
+        }
+        check_can_write_file(path)
+    }
+
+    fn check_delete(&self, path: &Path) -> io::Result<()> {
+        check_can_remove_file(path)
+    }
+}
+
 /// Applies a `MultifilePatch` to a set of files.
-#[derive(Debug)]
 pub struct MultifilePatcher {
     /// The collection of patches to apply.
     multifile_patch: MultifilePatch,
     /// Optional root directory to apply patches relative to.
     /// If None, paths in the patch are treated as relative to the current working directory.
     root_dir: Option<PathBuf>,
+    /// Algorithm used to apply each individual patch.
+    algorithm: PatcherAlgorithm,
+    /// Optional callback reporting progress through the patches.
+    on_progress: Option<ProgressCallback>,
+    /// Number of leading path components removed from the file names in the patches.
+    strip_components: usize,
+    /// Whether patches creating a file may replace an existing, non-empty one.
+    force: bool,
+    /// Where the files are read from and written to.
+    source: Box<dyn FileSource + Send + Sync>,
+}
+
+impl fmt::Debug for MultifilePatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultifilePatcher")
+            .field("multifile_patch", &self.multifile_patch)
+            .field("root_dir", &self.root_dir)
+            .field("algorithm", &self.algorithm)
+            .field("on_progress", &self.on_progress.is_some())
+            .field("strip_components", &self.strip_components)
+            .field("force", &self.force)
+            .finish_non_exhaustive()
+    }
 }
 
 /// Represents the status of applying a single patch within a multifile patch operation.
@@ -58,7 +169,9 @@ impl MultifilePatch {
 
     /// Parses a multi-file patch (unified diff format) from a string.
     ///
-    /// Handles concatenated diffs (multiple `diff --git ...` sections).
+    /// Handles concatenated diffs (multiple `diff --git ...` sections) as well as
+    /// concatenated bare unified diffs: a `---`/`+++` header pair following the
+    /// chunks of the current section starts a new patch.
     pub fn parse(content: &str) -> Result<Self, Error> {
         let mut patches = Vec::new();
         let lines: Vec<&str> = content.lines().collect();
@@ -67,52 +180,67 @@ impl MultifilePatch {
             return Ok(Self { patches: vec![] });
         }
 
+        let mut sections: Vec<(usize, usize)> = Vec::new();
         let mut patch_start_index: Option<usize> = None;
-
-        for (i, line) in lines.iter().enumerate() {
-            if line.starts_with("diff --git ") {
-                // If we found the start of a new patch, process the previous one (if any)
+        let mut section_has_chunks = false;
+
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let is_bare_header = line.starts_with("--- ")
+                && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+                && (patch_start_index.is_none() || section_has_chunks);
+            let is_diff_line = ["diff --git ", "diff --cc ", "diff --combined "]
+                .iter()
+                .any(|prefix| line.starts_with(prefix));
+            if is_diff_line || is_bare_header {
+                // Close the previous section (if any) and start a new one here
                 if let Some(start) = patch_start_index {
-                    let patch_lines_slice = &lines[start..i];
-                    // Check if the slice is non-empty before joining and parsing
-                    if !patch_lines_slice.is_empty() {
-                        let patch_content = patch_lines_slice.join("\n"); // Join only the slice
-                        match Patch::parse(&patch_content) {
-                            Ok(patch) => patches.push(patch),
-                            Err(e) => {
-                                // Provide more context in the warning
-                                warn!(
-                                    "Warning: Skipping malformed patch section (lines {}-{}): {}\n--- Patch Content Start ---\n{}\n--- Patch Content End ---",
-                                    start + 1,
-                                    i,
-                                    e,
-                                    patch_content
-                                );
+                    sections.push((start, i));
+                }
+                patch_start_index = Some(i);
+                section_has_chunks = false;
+            } else if line.starts_with("@@@") && patch_start_index.is_some() {
+                section_has_chunks = true;
+            } else if line.starts_with("@@ ") && patch_start_index.is_some() {
+                section_has_chunks = true;
+                // Skip the chunk body so that removed lines starting with "-- " are not
+                // mistaken for headers
+                if let Ok((_, mut old_lines, _, mut new_lines)) = parse_chunk_header(line) {
+                    while (old_lines > 0 || new_lines > 0) && i + 1 < lines.len() {
+                        match lines[i + 1].chars().next() {
+                            Some('-') => old_lines = old_lines.saturating_sub(1),
+                            Some('+') => new_lines = new_lines.saturating_sub(1),
+                            Some(' ') => {
+                                old_lines = old_lines.saturating_sub(1);
+                                new_lines = new_lines.saturating_sub(1);
                             }
+                            Some('\\') | None => {}
+                            Some(_) => break,
                         }
+                        i += 1;
                     }
                 }
-                // Mark the start line index of the new patch section
-                patch_start_index = Some(i);
             }
+            i += 1;
         }
-
-        // Process the last patch section found in the file (from last diff to EOF)
         if let Some(start) = patch_start_index {
-            let patch_lines_slice = &lines[start..]; // Slice from start to the end
-            if !patch_lines_slice.is_empty() {
-                let patch_content = patch_lines_slice.join("\n"); // Join the last slice
-                match Patch::parse(&patch_content) {
-                    Ok(patch) => patches.push(patch),
-                    Err(e) => {
-                        warn!(
-                            "Warning: Skipping malformed patch section at end of file (lines {}-{}): {}\n--- Patch Content Start ---\n{}\n--- Patch Content End ---",
-                            start + 1,
-                            lines.len(),
-                            e,
-                            patch_content
-                        );
-                    }
+            sections.push((start, lines.len()));
+        }
+
+        for (start, end) in sections {
+            let patch_content = lines[start..end].join("\n");
+            match Patch::parse(&patch_content) {
+                Ok(patch) => patches.push(patch),
+                Err(e) => {
+                    // Provide more context in the warning
+                    warn!(
+                        "Warning: Skipping malformed patch section (lines {}-{}): {}\n--- Patch Content Start ---\n{}\n--- Patch Content End ---",
+                        start + 1,
+                        end,
+                        e,
+                        patch_content
+                    );
                 }
             }
         }
@@ -120,7 +248,10 @@ impl MultifilePatch {
         // Check for validity: If the input wasn't empty but no patches were parsed,
         // determine if it was due to missing 'diff' lines or parsing errors.
         if patches.is_empty() && !content.trim().is_empty() {
-            if !content.lines().any(|l| l.starts_with("diff ")) {
+            if !content
+                .lines()
+                .any(|l| l.starts_with("diff ") || l.starts_with("--- "))
+            {
                 // Content exists but no 'diff --git' lines found
                 return Err(Error::InvalidPatchFormat(
                     "No patch sections found starting with 'diff '".to_string(),
@@ -139,28 +270,518 @@ impl MultifilePatch {
 
     /// Parses a multi-file patch from a file specified by the path.
     pub fn parse_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let content = fs::read_to_string(path.as_ref()).map_err(Error::IoError)?;
+        let content = fs::read_to_string(path.as_ref()).map_err(|e| Error::io(path.as_ref(), e))?;
         Self::parse(&content)
     }
+
+    /// Parses a multi-file patch from a reader, e.g. `io::stdin()`.
+    pub fn parse_from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Self::parse(&content)
+    }
+
+    /// Writes the patches as [`Display`](fmt::Display) formats them to `writer`,
+    /// one patch and chunk at a time, without building the whole text in memory.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+
+    /// Formats every patch that changes something with
+    /// [`Patch::to_git_format`], one after the other, as a single `git diff`
+    /// output that [`parse`](Self::parse) reads back into the same patches.
+    pub fn to_git_format(&self) -> String {
+        let mut out = String::new();
+        for patch in self.patches.iter().filter(|patch| !is_unchanged(patch)) {
+            patch
+                .write_git_format(&mut out)
+                .expect("writing to a String cannot fail");
+        }
+        out
+    }
+
+    /// Returns `true` if no patch changes anything: there are no patches, or each
+    /// one has no chunks and neither creates, deletes, copies, renames nor changes
+    /// the mode of a file.
+    pub fn is_empty(&self) -> bool {
+        self.patches.iter().all(is_unchanged)
+    }
+
+    /// Returns the patches whose new or old path satisfies `predicate`, e.g. to
+    /// apply only the changes under `src/`. `/dev/null` is never passed to the
+    /// predicate. Each patch is self-contained, so the result applies on its own.
+    pub fn filter<F: Fn(&str) -> bool>(&self, predicate: F) -> MultifilePatch {
+        let patches = self
+            .patches
+            .iter()
+            .filter(|patch| {
+                [&patch.new_file, &patch.old_file]
+                    .into_iter()
+                    .any(|path| path != "/dev/null" && predicate(path))
+            })
+            .cloned()
+            .collect();
+        Self { patches }
+    }
+
+    /// Appends the patches of `other`, e.g. produced by a different tool, so both
+    /// can be applied together. A patch of `other` whose `new_file` already has a
+    /// patch here is composed onto it with [`Patch::compose`], so the merged patch
+    /// takes the file through both changes, including renames and copies.
+    ///
+    /// Returns an error, leaving `self` unchanged, if such a pair does not compose
+    /// because the second patch does not apply on top of the first.
+    pub fn merge(&mut self, other: MultifilePatch) -> Result<(), Error> {
+        let mut patches = self.patches.clone();
+        for patch in other.patches {
+            let existing = (patch.new_file != "/dev/null")
+                .then(|| patches.iter().position(|p| p.new_file == patch.new_file))
+                .flatten();
+            match existing {
+                Some(index) => patches[index] = patches[index].compose(&patch)?,
+                None => patches.push(patch),
+            }
+        }
+        self.patches = patches;
+        Ok(())
+    }
+
+    /// Orders the patches by the path of the file they produce, `new_file`, or
+    /// `old_file` for deletions, comparing paths as strings. Patches for the same
+    /// path keep their relative order. Use this to make the output independent of
+    /// the order the patches were collected in, e.g. from a directory walk.
+    pub fn sort_by_path(&mut self) {
+        self.patches.sort_by(|a, b| sort_key(a).cmp(sort_key(b)));
+    }
+
+    /// Rewrites the paths of every patch with `f`, see [`Patch::remap_paths`].
+    pub fn remap_paths<F: Fn(&str) -> String>(&mut self, f: F) {
+        for patch in &mut self.patches {
+            patch.remap_paths(&f);
+        }
+    }
+
+    /// Renders a summary of the changes like `git diff --stat`, fitted to `width`
+    /// columns: one line per file with its number of changed lines and a `+`/`-`
+    /// bar graph, scaled down if needed, followed by the totals, e.g.
+    /// ` 2 files changed, 4 insertions(+), 3 deletions(-)`.
+    pub fn diffstat(&self, width: usize) -> String {
+        let files: Vec<(&str, usize, usize)> = self
+            .patches
+            .iter()
+            .filter(|patch| !is_unchanged(patch))
+            .map(|patch| {
+                let (insertions, deletions) = patch.stats();
+                (sort_key(patch), insertions, deletions)
+            })
+            .collect();
+        let name_width = files.iter().map(|(name, _, _)| name.chars().count()).max();
+        let max_change = files.iter().map(|(_, i, d)| i + d).max().unwrap_or(0);
+        let count_width = max_change.to_string().len();
+        // " name | count graph"
+        let graph_width = width
+            .saturating_sub(name_width.unwrap_or(0) + count_width + 5)
+            .max(1);
+        let scale = |n: usize| {
+            if max_change <= graph_width || n == 0 {
+                n
+            } else {
+                1 + n * (graph_width - 1) / max_change
+            }
+        };
+
+        let mut out = String::new();
+        for &(name, insertions, deletions) in &files {
+            let total = insertions + deletions;
+            let added = scale(insertions);
+            // Keep the bar of each file proportional to its total
+            let removed = if deletions == 0 {
+                0
+            } else {
+                (scale(total) - added).max(1)
+            };
+            out.push_str(&format!(
+                " {:<name_width$} | {:>count_width$} {}{}\n",
+                name,
+                total,
+                "+".repeat(added),
+                "-".repeat(removed),
+                name_width = name_width.unwrap_or(0),
+            ));
+        }
+
+        let (insertions, deletions) = files
+            .iter()
+            .fold((0, 0), |(i, d), (_, fi, fd)| (i + fi, d + fd));
+        let plural =
+            |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        out.push(' ');
+        out.push_str(&plural(files.len(), "file changed", "files changed"));
+        if insertions > 0 {
+            out.push_str(", ");
+            out.push_str(&plural(insertions, "insertion(+)", "insertions(+)"));
+        }
+        if deletions > 0 {
+            out.push_str(", ");
+            out.push_str(&plural(deletions, "deletion(-)", "deletions(-)"));
+        }
+        out.push('\n');
+        out
+    }
+
+    /// Pairs file deletions with file creations whose content is similar and
+    /// rewrites each pair into a single rename patch, like git's rename detection.
+    ///
+    /// Similarity is the Levenshtein-based score (0.0 to 1.0) of the deleted and the
+    /// created content; pairs scoring at least `threshold` are merged, best scores first.
+    /// The rename patch records the score as a percentage in `Patch::similarity`.
+    pub fn detect_renames(&mut self, threshold: f64) {
+        let is_dev_null = |path: &str| path == "/dev/null";
+        let deletions: Vec<usize> = (0..self.patches.len())
+            .filter(|&i| is_dev_null(&self.patches[i].new_file))
+            .collect();
+        let creations: Vec<usize> = (0..self.patches.len())
+            .filter(|&i| is_dev_null(&self.patches[i].old_file))
+            .collect();
+
+        let contents: Vec<String> = self.patches.iter().map(side_content).collect();
+        let mut candidates = rename_candidates(&contents, &deletions, &creations, threshold);
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut paired = vec![false; self.patches.len()];
+        let mut removed = HashSet::new();
+        for (score, d, c) in candidates {
+            if paired[d] || paired[c] {
+                continue;
+            }
+            paired[d] = true;
+            paired[c] = true;
+            let from = self.patches[d].old_file.clone();
+            let to = self.patches[c].new_file.clone();
+            let mut rename = Differ::new(&contents[d], &contents[c])
+                .file_names(&from, &to)
+                .preamble(Some(format!("diff --git a/{} b/{}", from, to)))
+                .generate();
+            rename.rename_from = Some(from);
+            rename.rename_to = Some(to);
+            rename.similarity = Some((score * 100.0).floor() as u8);
+            self.patches[d] = rename;
+            removed.insert(c);
+        }
+
+        self.patches = std::mem::take(&mut self.patches)
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !removed.contains(i))
+            .map(|(_, patch)| patch)
+            .collect();
+    }
+
+    /// Generates a multi-file patch from two directory trees.
+    ///
+    /// Both trees are walked recursively and files are paired by their relative path.
+    /// Changed files get a regular patch, files only present in `old_dir` are deleted
+    /// (`+++ /dev/null`) and files only present in `new_dir` are created (`--- /dev/null`).
+    /// A file only present in `old_dir` whose content, byte for byte, is that of a
+    /// file only present in `new_dir` becomes a pure rename ([`Patch::rename`])
+    /// instead. Files that are not valid UTF-8 are skipped with a warning. The
+    /// patches are ordered by relative path, as with [`sort_by_path`](Self::sort_by_path),
+    /// on every platform.
+    pub fn from_dirs<P: AsRef<Path>, Q: AsRef<Path>>(
+        old_dir: P,
+        new_dir: Q,
+        context_lines: usize,
+    ) -> Result<Self, Error> {
+        let (old_dir, new_dir) = (old_dir.as_ref(), new_dir.as_ref());
+        let mut paths = BTreeSet::new();
+        collect_files(old_dir, old_dir, &mut paths)?;
+        collect_files(new_dir, new_dir, &mut paths)?;
+
+        let mut patches = Vec::new();
+        // Content of the files only one tree has, with the indices of their patches
+        let mut deleted = Vec::new();
+        let mut created: HashMap<String, VecDeque<usize>> = HashMap::new();
+        for rel in paths {
+            let old = read_if_file(&old_dir.join(&rel))?;
+            let new = read_if_file(&new_dir.join(&rel))?;
+            if old == new {
+                continue;
+            }
+            let (old_file, new_file) = match (&old, &new) {
+                (Some(_), None) => (rel.as_str(), "/dev/null"),
+                (None, Some(_)) => ("/dev/null", rel.as_str()),
+                _ => (rel.as_str(), rel.as_str()),
+            };
+            let (Ok(old), Ok(new)) = (
+                String::from_utf8(old.unwrap_or_default()),
+                String::from_utf8(new.unwrap_or_default()),
+            ) else {
+                warn!("Skipping non-UTF-8 file {}", rel);
+                continue;
+            };
+            let patch = Differ::new(&old, &new)
+                .context_lines(context_lines)
+                .file_names(old_file, new_file)
+                .preamble(Some(format!("diff --git a/{} b/{}", rel, rel)))
+                .generate();
+            if old_file == "/dev/null" {
+                created.entry(new).or_default().push_back(patches.len());
+            } else if new_file == "/dev/null" {
+                deleted.push((patches.len(), old));
+            }
+            // Bytes can differ without any line changing, e.g. CRLF vs LF endings
+            if !is_unchanged(&patch) {
+                patches.push(patch);
+            }
+        }
+
+        // Like git, empty files are never paired up as renames
+        let mut removed = HashSet::new();
+        for (d, content) in deleted.iter().filter(|(_, content)| !content.is_empty()) {
+            let Some(c) = created.get_mut(content).and_then(VecDeque::pop_front) else {
+                continue;
+            };
+            patches[c] = Patch::rename(&patches[*d].old_file, &patches[c].new_file);
+            removed.insert(*d);
+        }
+        let patches = patches
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| !removed.contains(i))
+            .map(|(_, patch)| patch)
+            .collect();
+
+        Ok(Self { patches })
+    }
+}
+
+/// The `(score, deletion, creation)` pairs of patch indices whose contents score
+/// at least `threshold`.
+///
+/// Only pairs that can reach the threshold are scored: the score is at most the
+/// ratio of the shorter to the longer length, so creations are searched by length,
+/// except that contents differing only in whitespace score 0.95 whatever their
+/// lengths, so those are looked up by their normalized content.
+fn rename_candidates(
+    contents: &[String],
+    deletions: &[usize],
+    creations: &[usize],
+    threshold: f64,
+) -> Vec<(f64, usize, usize)> {
+    let lens: Vec<usize> = contents
+        .iter()
+        .map(|content| content.chars().count())
+        .collect();
+    let in_window = |d_len: f64, c_len: usize| {
+        c_len as f64 >= d_len * threshold && d_len >= c_len as f64 * threshold
+    };
+    let mut by_len: Vec<(usize, usize)> = creations.iter().map(|&c| (lens[c], c)).collect();
+    by_len.sort_unstable();
+    let mut by_whitespace: HashMap<Cow<str>, Vec<usize>> = HashMap::new();
+    if threshold <= 0.95 {
+        for &c in creations {
+            by_whitespace
+                .entry(normalize_whitespace(&contents[c]))
+                .or_default()
+                .push(c);
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for &d in deletions {
+        let d_len = lens[d] as f64;
+        // The window is contiguous in the sorted lengths
+        let start = by_len.partition_point(|&(c_len, _)| (c_len as f64) < d_len * threshold);
+        let in_range = by_len[start..]
+            .iter()
+            .take_while(|&&(c_len, _)| in_window(d_len, c_len))
+            .map(|&(_, c)| c);
+        let same_words = by_whitespace
+            .get(&normalize_whitespace(&contents[d]))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&c| !in_window(d_len, lens[c]));
+        for c in in_range.chain(same_words) {
+            let score = similarity_score(&contents[d], &contents[c]);
+            if score >= threshold {
+                candidates.push((score, d, c));
+            }
+        }
+    }
+    candidates
+}
+
+/// Whether the patch leaves its file untouched.
+fn is_unchanged(patch: &Patch) -> bool {
+    patch.chunks.is_empty()
+        && patch.combined_chunks.is_empty()
+        && patch.old_file != "/dev/null"
+        && patch.new_file != "/dev/null"
+        && !patch.is_metadata_only()
+}
+
+/// Reconstructs the whole content a creation (or deletion) patch adds (or removes).
+fn side_content(patch: &Patch) -> String {
+    let created = patch.old_file == "/dev/null";
+    let lines: Vec<&str> = patch
+        .chunks
+        .iter()
+        .flat_map(|chunk| &chunk.operations)
+        .filter(|op| match op {
+            Operation::Add(_) => created,
+            Operation::Remove(_) => !created,
+            Operation::Context(_) => false,
+        })
+        .map(|op| op.line())
+        .collect();
+    let missing_newline = if created {
+        patch.new_missing_newline
+    } else {
+        patch.old_missing_newline
+    };
+    let mut content = lines.join("\n");
+    if !lines.is_empty() && !missing_newline {
+        content.push('\n');
+    }
+    content
+}
+
+/// The path `MultifilePatch::sort_by_path` orders a patch by.
+fn sort_key(patch: &Patch) -> &str {
+    if patch.new_file == "/dev/null" {
+        &patch.old_file
+    } else {
+        &patch.new_file
+    }
+}
+
+/// Recursively collects the paths of all files under `dir`, relative to `root`
+/// and using `/` as separator.
+fn collect_files(root: &Path, dir: &Path, paths: &mut BTreeSet<String>) -> Result<(), Error> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, paths)?;
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            let rel: Vec<String> = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            paths.insert(rel.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the file at `path`, or returns `None` if there is no file there.
+fn read_if_file(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    if path.is_file() {
+        Ok(Some(fs::read(path)?))
+    } else {
+        Ok(None)
+    }
 }
 
 impl MultifilePatcher {
     /// Creates a new `MultifilePatcher` for the given `MultifilePatch`.
     /// Patches will be applied relative to the current working directory.
     pub fn new(multifile_patch: MultifilePatch) -> Self {
-        Self {
-            multifile_patch,
-            root_dir: None,
-        }
+        Self::with_source(multifile_patch, DiskFileSource)
     }
 
     /// Creates a new `MultifilePatcher` for the given `MultifilePatch`,
     /// applying patches relative to the specified `root_dir`.
     pub fn with_root<P: AsRef<Path>>(multifile_patch: MultifilePatch, root_dir: P) -> Self {
         Self {
-            multifile_patch,
             root_dir: Some(root_dir.as_ref().to_path_buf()),
+            ..Self::new(multifile_patch)
+        }
+    }
+
+    /// Creates a new `MultifilePatcher` for the given `MultifilePatch` that reads and
+    /// writes files through `source` instead of the filesystem.
+    /// Paths in the patch are passed to `source` as they are.
+    pub fn with_source<S: FileSource + Send + Sync + 'static>(
+        multifile_patch: MultifilePatch,
+        source: S,
+    ) -> Self {
+        Self {
+            multifile_patch,
+            root_dir: None,
+            algorithm: PatcherAlgorithm::Naive,
+            on_progress: None,
+            strip_components: 0,
+            force: false,
+            source: Box::new(source),
+        }
+    }
+
+    /// Diffs `old_dir` against `new_dir` with [`MultifilePatch::from_dirs`] and returns
+    /// a patcher rooted at `old_dir`, so applying it turns the old tree into the new one.
+    pub fn from_directory_diff<P: AsRef<Path>, Q: AsRef<Path>>(
+        old_dir: P,
+        new_dir: Q,
+        context_lines: usize,
+    ) -> Result<Self, Error> {
+        let multifile_patch = MultifilePatch::from_dirs(&old_dir, new_dir, context_lines)?;
+        Ok(Self::with_root(multifile_patch, old_dir))
+    }
+
+    /// The patches this patcher applies.
+    pub fn multifile_patch(&self) -> &MultifilePatch {
+        &self.multifile_patch
+    }
+
+    /// Sets the algorithm used to apply each patch (defaults to `PatcherAlgorithm::Naive`).
+    /// `PatcherAlgorithm::Similar` can relocate chunks whose context moved; the resulting
+    /// offsets are reported in `PatchedFile::hunks`.
+    pub fn algorithm(mut self, algorithm: PatcherAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Registers a callback invoked before each patch with `(index, total, path)`,
+    /// where `index` is 0-based and `path` is the file the patch writes (or deletes).
+    /// It fires for `apply` and everything built on it, e.g. `apply_and_write`.
+    pub fn on_progress(mut self, callback: ProgressCallback) -> Self {
+        self.on_progress = Some(callback);
+        self
+    }
+
+    /// Allows patches that create a file (from `/dev/null`) to replace a target that
+    /// already exists with content. By default such patches fail with `Error::TargetExists`
+    /// instead of silently overwriting the file.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Removes `count` leading components from the file names in the patches before
+    /// resolving them, like GNU `patch -p`: with `strip_components(1)`,
+    /// `a/src/foo.rs` becomes `src/foo.rs`. The file name itself is never removed,
+    /// and `/dev/null` is left as is.
+    pub fn strip_components(mut self, count: usize) -> Self {
+        self.strip_components = count;
+        self
+    }
+
+    /// Removes the configured number of leading components from a patch file path.
+    fn strip_path<'p>(&self, patch_path: &'p str) -> &'p str {
+        if patch_path == "/dev/null" {
+            return patch_path;
+        }
+        let mut path = patch_path;
+        for _ in 0..self.strip_components {
+            match path.split_once('/') {
+                Some((_, rest)) if !rest.is_empty() => path = rest.trim_start_matches('/'),
+                _ => break,
+            }
         }
+        path
     }
 
     /// Resolves a patch file path relative to the `root_dir` if set,
@@ -184,11 +805,14 @@ impl MultifilePatcher {
     /// # Returns
     ///
     /// * `Ok(Vec<ApplyResult>)` - A vector containing the result status for each patch.
+    ///   A successfully applied rename yields an `Applied` result for the target
+    ///   followed by a `Deleted` result for the source.
     /// * `Err(Error)` - If a fatal error occurs during setup (e.g., reading root dir fails, though unlikely here).
     pub fn apply(&self, reverse: bool) -> Result<Vec<ApplyResult>, Error> {
-        let mut results = Vec::with_capacity(self.multifile_patch.patches.len());
+        let total = self.multifile_patch.patches.len();
+        let mut results = Vec::with_capacity(total);
 
-        for patch in &self.multifile_patch.patches {
+        for (index, patch) in self.multifile_patch.patches.iter().enumerate() {
             let (source_path_str, target_path_str, is_new_file, is_delete_file) = if reverse {
                 // When reversing:
                 // Source is the *new* file (or /dev/null if it was a deletion).
@@ -197,7 +821,9 @@ impl MultifilePatcher {
                     &patch.new_file,
                     &patch.old_file,
                     patch.new_file == "/dev/null" || patch.new_file.ends_with("/dev/null"), // Reversing a delete results in creation
-                    patch.old_file == "/dev/null" || patch.old_file.ends_with("/dev/null"), // Reversing a create results in deletion
+                    patch.old_file == "/dev/null"
+                        || patch.old_file.ends_with("/dev/null")
+                        || patch.is_copy(), // Reversing a create (or a copy) results in deletion
                 )
             } else {
                 // When applying normally:
@@ -211,34 +837,84 @@ impl MultifilePatcher {
                 )
             };
 
+            let source_path_str = &self.strip_path(source_path_str);
+            let target_path_str = &self.strip_path(target_path_str);
+
+            if let Some(on_progress) = &self.on_progress {
+                let path = if is_delete_file {
+                    source_path_str
+                } else {
+                    target_path_str
+                };
+                on_progress(index, total, path);
+            }
+
             // Determine the actual file path to read content from.
             let source_path = self.resolve_path(source_path_str);
             let target_path = self.resolve_path(target_path_str); // Target path as string for PatchedFile
 
+            // A creation must not clobber a file that is already there
+            if is_new_file
+                && !self.force
+                && self
+                    .source
+                    .read(&target_path)
+                    .is_ok_and(|existing| !existing.is_empty())
+            {
+                results.push(ApplyResult::Failed(
+                    target_path.display().to_string(),
+                    Error::TargetExists {
+                        path: target_path.display().to_string(),
+                    },
+                ));
+                continue;
+            }
+
             // Read the source file content.
             let source_content_result = if is_new_file {
                 // If it's a new file patch, the source content is empty.
                 Ok(String::new())
             } else {
-                fs::read_to_string(&source_path)
+                self.source.read(&source_path)
             };
 
             let result = match source_content_result {
                 Ok(content) => {
                     // Apply the individual patch.
-                    let patcher = Patcher::new(patch.clone());
-                    match patcher.apply(&content, reverse) {
-                        Ok(new_content) => {
+                    let metadata_only = patch.is_metadata_only();
+                    let applied = if metadata_only {
+                        // Only the name or mode changes, the content stays as it is
+                        Ok((content, Vec::new()))
+                    } else {
+                        Patcher::new_with_algorithm(patch.clone(), self.algorithm.clone())
+                            .apply_tracked(&content, reverse)
+                    };
+                    match applied {
+                        Ok((new_content, hunks)) => {
                             if is_delete_file {
                                 // If the target is /dev/null, it signifies a deletion.
                                 ApplyResult::Deleted(source_path_str.to_string())
+                            } else if patch.is_rename() {
+                                // A rename writes the target and removes the source
+                                results.push(ApplyResult::Applied(PatchedFile {
+                                    path: target_path.display().to_string(),
+                                    content: new_content,
+                                    is_new: true,
+                                    is_deleted: false,
+                                    hunks,
+                                    metadata_only,
+                                }));
+                                ApplyResult::Deleted(source_path_str.to_string())
                             } else {
                                 // Otherwise, it's a modification or creation.
                                 ApplyResult::Applied(PatchedFile {
                                     path: target_path.display().to_string(),
                                     content: new_content,
-                                    is_new: is_new_file, // is_new determined earlier
+                                    // A copy creates its target while leaving the source untouched
+                                    is_new: is_new_file || (patch.is_copy() && !reverse),
                                     is_deleted: false,
+                                    hunks,
+                                    metadata_only,
                                 })
                             }
                         }
@@ -252,13 +928,10 @@ impl MultifilePatcher {
                     }
                 }
                 Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                    if reverse && (is_new_file || is_delete_file) {
-                        // If reversing a creation (is_delete_file true) or deletion (is_new_file true)
-                        // and the source file doesn't exist, it means the state is already as expected.
-                        // e.g. Reversing creation of file X -> delete X. If X doesn't exist, skip.
-                        // e.g. Reversing deletion of file Y -> create Y. If Y doesn't exist (target of delete), skip?
-                        // This skip logic might need refinement depending on desired behavior for reverse.
-                        // Current: Skip reversing creation/deletion if the file is already gone/present.
+                    if reverse && is_delete_file {
+                        // Reversing a creation deletes the file; if it is already gone there is
+                        // nothing to do. (Reversing a deletion never reads its source: the file
+                        // is rebuilt from the removed lines above.)
                         ApplyResult::Skipped(format!(
                             "Skipping reverse for non-existent file involved in creation/deletion: {}",
                             source_path.display()
@@ -277,7 +950,10 @@ impl MultifilePatcher {
                     // Other I/O error reading the file.
                     // Report failure associated with the *target* path, as failure to read source
                     // prevents the target operation.
-                    ApplyResult::Failed(target_path.display().to_string(), Error::IoError(err))
+                    ApplyResult::Failed(
+                        target_path.display().to_string(),
+                        Error::io(&source_path, err),
+                    )
                 }
             };
             results.push(result);
@@ -292,6 +968,7 @@ impl MultifilePatcher {
     /// Applies the patches and writes the results directly to the filesystem.
     ///
     /// Creates necessary directories, writes modified/new files, and deletes files marked for deletion.
+    /// A patcher built with `with_source` writes through its `FileSource` instead.
     ///
     /// # Arguments
     ///
@@ -303,6 +980,133 @@ impl MultifilePatcher {
     /// * `Err(Error)` - If a fatal error occurs during file I/O.
     pub fn apply_and_write(&self, reverse: bool) -> Result<Vec<ApplyResult>, Error> {
         let results = self.apply(reverse)?;
+        self.write_results(results, false)
+    }
+
+    /// Like `apply_and_write`, but all-or-nothing.
+    ///
+    /// All patches are applied in memory and the writes are checked as in `plan` first;
+    /// if any result is `ApplyResult::Failed`, nothing is written and the results are
+    /// returned as is. Otherwise the original content of every touched file is backed up
+    /// before writing, and restored if a write still fails midway.
+    ///
+    /// # Arguments
+    ///
+    /// * `reverse` - If `true`, applies the patches in reverse (reverting changes).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<ApplyResult>)` - The result status for each patch; if any is `Failed`,
+    ///   the filesystem was left untouched.
+    /// * `Err(Error)` - If writing failed midway. The original files have been restored
+    ///   and directories created for new files removed; the error names anything that
+    ///   could not be restored.
+    pub fn apply_and_write_atomic(&self, reverse: bool) -> Result<Vec<ApplyResult>, Error> {
+        // A successful dry run passes the results of `apply` through unchanged
+        let results = self.plan(reverse)?;
+        if results
+            .iter()
+            .any(|result| matches!(result, ApplyResult::Failed(_, _)))
+        {
+            return Ok(results);
+        }
+
+        let mut backups = Vec::new();
+        for result in &results {
+            let path = match result {
+                ApplyResult::Applied(file) => self.resolve_path(&file.path),
+                ApplyResult::Deleted(path) => self.resolve_path(path),
+                _ => continue,
+            };
+            let original = match self.source.read(&path) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(Error::io(&path, e)),
+            };
+            backups.push((path, original));
+        }
+        // Directories that writing the new files may create
+        let mut new_dirs = BTreeSet::new();
+        for (path, _) in backups.iter().filter(|(_, original)| original.is_none()) {
+            for dir in path.ancestors().skip(1) {
+                if dir.as_os_str().is_empty() || self.source.exists(dir) {
+                    break;
+                }
+                new_dirs.insert(dir.to_path_buf());
+            }
+        }
+
+        let failure = match self.write_results(results, false) {
+            Ok(written) => match written.iter().find_map(|result| match result {
+                ApplyResult::Failed(path, e) => Some(format!("{}: {}", path, e)),
+                _ => None,
+            }) {
+                None => return Ok(written),
+                Some(failure) => failure,
+            },
+            Err(e) => e.to_string(),
+        };
+
+        // Restore in reverse order so the earliest backup of a file wins, trying
+        // every file even if some fail
+        let mut unrestored = Vec::new();
+        for (path, original) in backups.into_iter().rev() {
+            let restored = match original {
+                Some(content) => self.source.write(&path, &content),
+                None if self.source.exists(&path) => self.source.delete(&path),
+                None => Ok(()),
+            };
+            if let Err(e) = restored {
+                unrestored.push(format!("{}: {}", path.display(), e));
+            }
+        }
+        // Nested directories sort after their parents
+        for dir in new_dirs.iter().rev() {
+            if self.source.exists(dir)
+                && let Err(e) = self.source.remove_dir(dir)
+            {
+                unrestored.push(format!("{}: {}", dir.display(), e));
+            }
+        }
+        if unrestored.is_empty() {
+            Err(Error::ApplyError(format!(
+                "Failed to write patched files, restored the originals: {}",
+                failure
+            )))
+        } else {
+            Err(Error::ApplyError(format!(
+                "Failed to write patched files: {}; could not restore {}",
+                failure,
+                unrestored.join(", ")
+            )))
+        }
+    }
+
+    /// Computes the results `apply_and_write` would produce without touching the filesystem.
+    ///
+    /// No directories are created, no files are written and nothing is deleted. Writes that
+    /// are known to fail (e.g. the target path is a directory) are reported as
+    /// `ApplyResult::Failed` exactly like a real run would report them.
+    ///
+    /// # Arguments
+    ///
+    /// * `reverse` - If `true`, plans applying the patches in reverse (reverting changes).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<ApplyResult>)` - The predicted result status for each patch.
+    /// * `Err(Error)` - If a real run would hit a fatal error.
+    pub fn plan(&self, reverse: bool) -> Result<Vec<ApplyResult>, Error> {
+        let results = self.apply(reverse)?;
+        self.write_results(results, true)
+    }
+
+    /// Writes (or, in `dry_run` mode, only validates) the in-memory results of `apply`.
+    fn write_results(
+        &self,
+        results: Vec<ApplyResult>,
+        dry_run: bool,
+    ) -> Result<Vec<ApplyResult>, Error> {
         let mut final_results = Vec::with_capacity(results.len());
 
         for result in results {
@@ -310,37 +1114,38 @@ impl MultifilePatcher {
                 ApplyResult::Applied(ref file) => {
                     let target_path = self.resolve_path(&file.path);
 
-                    // Create parent directories if they don't exist.
-                    if let Some(parent) = target_path.parent() {
-                        fs::create_dir_all(parent).map_err(Error::IoError)?;
-                    }
-
                     // Write the patched content to the file.
-                    match File::create(&target_path) {
-                        Ok(mut output_file) => {
-                            if let Err(e) = output_file.write_all(file.content.as_bytes()) {
-                                final_results.push(ApplyResult::Failed(
-                                    file.path.clone(),
-                                    Error::IoError(e),
-                                ));
-                            } else {
-                                final_results.push(result); // Keep original successful ApplyResult::Applied
-                            }
-                        }
+                    let write_result = if dry_run {
+                        self.source.check_write(&target_path)
+                    } else {
+                        self.source.write(&target_path, &file.content)
+                    };
+                    match write_result {
+                        // Keep original successful ApplyResult::Applied
+                        Ok(()) => final_results.push(result),
                         Err(e) => {
-                            final_results
-                                .push(ApplyResult::Failed(file.path.clone(), Error::IoError(e)));
+                            final_results.push(ApplyResult::Failed(
+                                file.path.clone(),
+                                Error::io(&target_path, e),
+                            ));
                         }
                     }
                 }
                 ApplyResult::Deleted(ref path_str) => {
                     let path_to_delete = self.resolve_path(path_str);
-                    if path_to_delete.exists() {
-                        match fs::remove_file(&path_to_delete) {
+                    if self.source.exists(&path_to_delete) {
+                        let remove_result = if dry_run {
+                            self.source.check_delete(&path_to_delete)
+                        } else {
+                            self.source.delete(&path_to_delete)
+                        };
+                        match remove_result {
                             Ok(_) => final_results.push(result), // Keep original successful ApplyResult::Deleted
                             Err(e) => {
-                                final_results
-                                    .push(ApplyResult::Failed(path_str.clone(), Error::IoError(e)));
+                                final_results.push(ApplyResult::Failed(
+                                    path_str.clone(),
+                                    Error::io(&path_to_delete, e),
+                                ));
                             }
                         }
                     } else {
@@ -359,19 +1164,62 @@ impl MultifilePatcher {
     }
 }
 
+/// Mirrors the failure modes of `fs::create_dir_all` without creating anything:
+/// the closest existing ancestor of `dir` must be a directory.
+fn check_can_create_dir_all(dir: &Path) -> io::Result<()> {
+    match dir.ancestors().find(|p| p.exists()) {
+        Some(existing) if !existing.is_dir() => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a directory", existing.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Mirrors the failure modes of `File::create` without creating or truncating the file.
+fn check_can_write_file(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Mirrors the failure modes of `fs::remove_file` without removing anything.
+fn check_can_remove_file(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            format!("{} is a directory", path.display()),
+        ));
+    }
+    Ok(())
+}
+
 impl fmt::Display for MultifilePatch {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for patch in &self.patches {
+        for patch in self.patches.iter().filter(|patch| !is_unchanged(patch)) {
             writeln!(f, "{}", patch)?;
         }
         Ok(())
     }
 }
 
+impl FromStr for MultifilePatch {
+    type Err = Error;
+
+    /// Parses a multi-file patch like [`MultifilePatch::parse`].
+    fn from_str(content: &str) -> Result<Self, Error> {
+        MultifilePatch::parse(content)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{DiffAlgorithm, Differ};
+    use crate::{DiffAlgorithm, Differ, PatchAlgorithm};
     use std::fs;
     use tempfile::tempdir; // Use tempdir instead of TempDir for simpler Result handling
 
@@ -468,14 +1316,118 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_git_diff_format() {
-        let patch_content = "diff --git a/src/main.rs b/src/main.rs
-index 123..456 100644
---- a/src/main.rs
-+++ b/src/main.rs
-@@ -1,3 +1,4 @@
- use std::fs;
-
+    fn test_plan_does_not_touch_filesystem() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+
+        let modified_name = "modified.txt";
+        fs::write(temp_path.join(modified_name), "line1\nline2\n")?;
+        let mut modify = Differ::new("line1\nline2\n", "line1\nchanged\n").generate();
+        modify.old_file = modified_name.to_string();
+        modify.new_file = modified_name.to_string();
+
+        let deleted_name = "deleted.txt";
+        fs::write(temp_path.join(deleted_name), "bye\n")?;
+        let delete = Patch {
+            preamble: None,
+            old_file: deleted_name.to_string(),
+            new_file: "/dev/null".to_string(),
+            chunks: vec![crate::Chunk {
+                old_start: 0,
+                old_lines: 1,
+                new_start: 0,
+                new_lines: 0,
+                operations: vec![crate::Operation::Remove("bye".to_string())],
+                section_header: None,
+            }],
+            ..Default::default()
+        };
+
+        let created_name = "nested/dir/created.txt";
+        let create = Patch {
+            preamble: None,
+            old_file: "/dev/null".to_string(),
+            new_file: created_name.to_string(),
+            chunks: vec![crate::Chunk {
+                old_start: 0,
+                old_lines: 0,
+                new_start: 0,
+                new_lines: 1,
+                operations: vec![crate::Operation::Add("hello".to_string())],
+                section_header: None,
+            }],
+            ..Default::default()
+        };
+
+        let multipatch = MultifilePatch::new(vec![modify, delete, create]);
+        let patcher = MultifilePatcher::with_root(multipatch, temp_path);
+        let planned = patcher.plan(false)?;
+
+        // Nothing on disk changed
+        assert_eq!(
+            fs::read_to_string(temp_path.join(modified_name))?,
+            "line1\nline2\n"
+        );
+        assert!(temp_path.join(deleted_name).exists());
+        assert!(!temp_path.join("nested").exists());
+
+        // The classification matches a real run
+        let written = patcher.apply_and_write(false)?;
+        assert_eq!(planned.len(), written.len());
+        for (plan, real) in planned.iter().zip(&written) {
+            assert_eq!(
+                std::mem::discriminant(plan),
+                std::mem::discriminant(real),
+                "plan {:?} differs from real run {:?}",
+                plan,
+                real
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_reports_write_failure() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        let file_name = "target_file.txt";
+        fs::create_dir(temp_path.join(file_name))?;
+
+        let patch = Patch {
+            preamble: None,
+            old_file: "/dev/null".to_string(),
+            new_file: file_name.to_string(),
+            chunks: vec![crate::Chunk {
+                old_start: 0,
+                old_lines: 0,
+                new_start: 0,
+                new_lines: 1,
+                operations: vec![crate::Operation::Add("b".to_string())],
+                section_header: None,
+            }],
+            ..Default::default()
+        };
+
+        let patcher = MultifilePatcher::with_root(MultifilePatch::new(vec![patch]), temp_path);
+        let results = patcher.plan(false)?;
+        assert_eq!(results.len(), 1);
+        assert!(matches!(
+            results[0],
+            ApplyResult::Failed(_, Error::IoError { .. })
+        ));
+        assert!(temp_path.join(file_name).is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_git_diff_format() {
+        let patch_content = "diff --git a/src/main.rs b/src/main.rs
+index 123..456 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,4 @@
+ use std::fs;
+
  fn main() {
 +    println!(\"Hello\");
  }
@@ -546,7 +1498,9 @@ index def..000
                 new_start: 0,
                 new_lines: 1,
                 operations: vec![crate::Operation::Add(new_content.to_string())],
+                section_header: None,
             }],
+            ..Default::default()
         };
 
         let multipatch = MultifilePatch::new(vec![patch]);
@@ -571,6 +1525,39 @@ index def..000
         Ok(())
     }
 
+    #[test]
+    fn test_apply_creation_over_existing_file() -> Result<(), Error> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::write(temp_path.join("exists.txt"), "old content\n")?;
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("", "new content\n")
+                .file_names("/dev/null", "exists.txt")
+                .generate(),
+        ]);
+
+        let results =
+            MultifilePatcher::with_root(multipatch.clone(), temp_path).apply_and_write(false)?;
+        assert!(matches!(
+            &results[0],
+            ApplyResult::Failed(_, Error::TargetExists { .. })
+        ));
+        assert_eq!(
+            fs::read_to_string(temp_path.join("exists.txt"))?,
+            "old content\n"
+        );
+
+        let results = MultifilePatcher::with_root(multipatch, temp_path)
+            .force(true)
+            .apply_and_write(false)?;
+        assert!(matches!(&results[0], ApplyResult::Applied(_)));
+        assert_eq!(
+            fs::read_to_string(temp_path.join("exists.txt"))?,
+            "new content\n"
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_apply_file_deletion() -> Result<(), Box<dyn std::error::Error>> {
         let temp_dir = tempdir()?;
@@ -589,6 +1576,7 @@ index def..000
             old_file: file_to_delete_name.to_string(), // Relative path
             new_file: "/dev/null".to_string(),
             chunks: vec![],
+            ..Default::default()
         };
         patch.chunks.push(crate::Chunk {
             old_start: 0, // 1-based in header, 0-based internally
@@ -596,6 +1584,7 @@ index def..000
             new_start: 0,
             new_lines: 0,
             operations: vec![crate::Operation::Remove(content.trim_end().to_string())],
+            section_header: None,
         });
 
         let multipatch = MultifilePatch::new(vec![patch]);
@@ -615,6 +1604,104 @@ index def..000
         Ok(())
     }
 
+    #[test]
+    fn test_reverse_deletion_restores_file() -> Result<(), Error> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        let content = "first\nsecond\nthird";
+        fs::write(temp_path.join("gone.txt"), content)?;
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new(content, "")
+                .file_names("gone.txt", "/dev/null")
+                .generate(),
+        ]);
+        let patcher = MultifilePatcher::with_root(multipatch, temp_path);
+
+        let results = patcher.apply_and_write(false)?;
+        assert!(matches!(&results[0], ApplyResult::Deleted(_)));
+        assert!(!temp_path.join("gone.txt").exists());
+
+        let results = patcher.apply_and_write(true)?;
+        assert!(matches!(&results[0], ApplyResult::Applied(file) if file.is_new));
+        assert_eq!(fs::read_to_string(temp_path.join("gone.txt"))?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_copy_keeps_source() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::create_dir_all(temp_path.join("src"))?;
+        fs::write(temp_path.join("src/a.rs"), "fn a() {}\nfn helper() {}\n")?;
+
+        let patch_content = "\
+diff --git a/src/a.rs b/src/b.rs
+similarity index 90%
+copy from src/a.rs
+copy to src/b.rs
+--- a/src/a.rs
++++ b/src/b.rs
+@@ -1,2 +1,2 @@
+ fn a() {}
+-fn helper() {}
++fn helper_b() {}
+";
+        let multipatch = MultifilePatch::parse(patch_content)?;
+        let patcher = MultifilePatcher::with_root(multipatch, temp_path);
+        let results = patcher.apply_and_write(false)?;
+
+        assert_eq!(results.len(), 1);
+        match &results[0] {
+            ApplyResult::Applied(file) => assert!(file.is_new),
+            other => panic!("Expected ApplyResult::Applied, got {:?}", other),
+        }
+        assert_eq!(
+            fs::read_to_string(temp_path.join("src/a.rs"))?,
+            "fn a() {}\nfn helper() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(temp_path.join("src/b.rs"))?,
+            "fn a() {}\nfn helper_b() {}\n"
+        );
+
+        // Reverting the copy removes the copied file only
+        let results = patcher.apply_and_write(true)?;
+        assert!(matches!(results[0], ApplyResult::Deleted(_)));
+        assert!(!temp_path.join("src/b.rs").exists());
+        assert!(temp_path.join("src/a.rs").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_reports_hunk_offsets() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "a\nb\nC\nd\ne\n";
+        // Two extra lines were inserted at the top since the patch was made
+        fs::write(temp_path.join("file.txt"), format!("x\ny\n{}", old))?;
+
+        let mut patch = Differ::new(old, new).context_lines(1).generate();
+        patch.old_file = "file.txt".to_string();
+        patch.new_file = "file.txt".to_string();
+
+        let patcher = MultifilePatcher::with_root(MultifilePatch::new(vec![patch]), temp_path)
+            .algorithm(PatcherAlgorithm::Similar);
+        let results = patcher.apply(false)?;
+        match &results[0] {
+            ApplyResult::Applied(file) => {
+                assert_eq!(file.content, format!("x\ny\n{}", new));
+                assert_eq!(file.hunks.len(), 1);
+                assert_eq!(file.hunks[0].expected_start, 1);
+                assert_eq!(file.hunks[0].actual_start, 3);
+                assert_eq!(file.hunks[0].offset(), 2);
+                assert!(!file.hunks[0].fuzzy);
+            }
+            other => panic!("Expected ApplyResult::Applied, got {:?}", other),
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_apply_with_root_directory() -> Result<(), Box<dyn std::error::Error>> {
         let temp_dir = tempdir()?;
@@ -650,6 +1737,33 @@ index def..000
         Ok(())
     }
 
+    #[test]
+    fn test_apply_strip_components() -> Result<(), Error> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::create_dir_all(temp_path.join("src"))?;
+        fs::write(temp_path.join("src/foo.rs"), "a\nb\n")?;
+        fs::write(temp_path.join("old.txt"), "gone\n")?;
+
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("a\nb\n", "a\nB\n")
+                .file_names("build/a/src/foo.rs", "build/b/src/foo.rs")
+                .generate(),
+            Differ::new("gone\n", "")
+                .file_names("build/a//old.txt", "/dev/null")
+                .generate(),
+        ]);
+        let results = MultifilePatcher::with_root(multipatch, temp_path)
+            .strip_components(2)
+            .apply_and_write(false)?;
+
+        assert!(matches!(&results[0], ApplyResult::Applied(_)));
+        assert!(matches!(&results[1], ApplyResult::Deleted(path) if path == "old.txt"));
+        assert_eq!(fs::read_to_string(temp_path.join("src/foo.rs"))?, "a\nB\n");
+        assert!(!temp_path.join("old.txt").exists());
+        Ok(())
+    }
+
     #[test]
     fn test_apply_fails_file_not_found() -> Result<(), Box<dyn std::error::Error>> {
         let temp_dir = tempdir()?;
@@ -708,7 +1822,9 @@ index def..000
                 new_start: 0,
                 new_lines: 1,
                 operations: vec![crate::Operation::Add(new_content.to_string())],
+                section_header: None,
             }],
+            ..Default::default()
         };
 
         let multipatch = MultifilePatch::new(vec![patch]);
@@ -726,7 +1842,7 @@ index def..000
                     "The path in the Failed result should match the target filename"
                 );
                 assert!(
-                    matches!(err, Error::IoError(_)),
+                    matches!(err, Error::IoError { path: Some(path), .. } if *path == file_path),
                     "Error should be an IoError, got: {:?}",
                     err
                 );
@@ -739,4 +1855,803 @@ index def..000
 
         Ok(())
     }
+
+    #[test]
+    fn test_from_dirs_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let old_dir = tempdir()?;
+        let new_dir = tempdir()?;
+        let (old_path, new_path) = (old_dir.path(), new_dir.path());
+
+        fs::write(old_path.join("same.txt"), "unchanged\n")?;
+        fs::write(new_path.join("same.txt"), "unchanged\n")?;
+        fs::write(old_path.join("changed.txt"), "one\ntwo\nthree\n")?;
+        fs::write(new_path.join("changed.txt"), "one\n2\nthree\n")?;
+        fs::create_dir_all(old_path.join("sub"))?;
+        fs::write(old_path.join("sub/removed.txt"), "bye\n")?;
+        fs::create_dir_all(new_path.join("sub/deeper"))?;
+        fs::write(new_path.join("sub/deeper/added.txt"), "hello\n")?;
+
+        let multipatch = MultifilePatch::from_dirs(old_path, new_path, 1)?;
+        let files: Vec<(&str, &str)> = multipatch
+            .patches
+            .iter()
+            .map(|p| (p.old_file.as_str(), p.new_file.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("changed.txt", "changed.txt"),
+                ("/dev/null", "sub/deeper/added.txt"),
+                ("sub/removed.txt", "/dev/null"),
+            ]
+        );
+        assert_eq!(multipatch.patches[0].chunks[0].operations.len(), 4);
+
+        // The output parses back and turns the old tree into the new one
+        let parsed = MultifilePatch::parse(&multipatch.to_string())?;
+        assert_eq!(parsed.patches.len(), 3);
+        let results = MultifilePatcher::with_root(parsed, old_path).apply_and_write(false)?;
+        assert!(
+            results
+                .iter()
+                .all(|r| !matches!(r, ApplyResult::Failed(..)))
+        );
+        assert_eq!(
+            fs::read_to_string(old_path.join("changed.txt"))?,
+            "one\n2\nthree\n"
+        );
+        assert_eq!(
+            fs::read_to_string(old_path.join("sub/deeper/added.txt"))?,
+            "hello\n"
+        );
+        assert!(!old_path.join("sub/removed.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_dirs_pure_rename() -> Result<(), Box<dyn std::error::Error>> {
+        let old_dir = tempdir()?;
+        let new_dir = tempdir()?;
+        let (old_path, new_path) = (old_dir.path(), new_dir.path());
+        fs::write(old_path.join("old.txt"), "moved\ncontent\n")?;
+        fs::create_dir_all(new_path.join("sub"))?;
+        fs::write(new_path.join("sub/new.txt"), "moved\ncontent\n")?;
+
+        let multipatch = MultifilePatch::from_dirs(old_path, new_path, 3)?;
+        assert_eq!(
+            multipatch.patches,
+            vec![Patch::rename("old.txt", "sub/new.txt")]
+        );
+        assert_eq!(
+            multipatch.patches[0].to_git_format(),
+            "diff --git a/old.txt b/sub/new.txt\n\
+             similarity index 100%\n\
+             rename from old.txt\n\
+             rename to sub/new.txt\n"
+        );
+
+        let parsed = MultifilePatch::parse(&multipatch.to_string())?;
+        MultifilePatcher::with_root(parsed, old_path).apply_and_write(false)?;
+        assert!(!old_path.join("old.txt").exists());
+        assert_eq!(
+            fs::read_to_string(old_path.join("sub/new.txt"))?,
+            "moved\ncontent\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_metadata_only_patches() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("run.sh"), "echo hi\n")?;
+        fs::write(dir.path().join("old.txt"), "moved\n")?;
+        let text = "\
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/old.txt b/new.txt
+similarity index 100%
+rename from old.txt
+rename to new.txt
+";
+        let multipatch = MultifilePatch::parse(text)?;
+        assert_eq!(multipatch.patches.len(), 2);
+        assert!(multipatch.patches.iter().all(Patch::is_metadata_only));
+        assert_eq!(multipatch.patches[0].old_file, "run.sh");
+        assert_eq!(multipatch.to_git_format(), text);
+
+        let results = MultifilePatcher::with_root(multipatch, dir.path()).apply(false)?;
+        let applied: Vec<&PatchedFile> = results
+            .iter()
+            .filter_map(|result| match result {
+                ApplyResult::Applied(file) => Some(file),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].content, "echo hi\n");
+        assert_eq!(applied[1].content, "moved\n");
+        assert!(
+            applied
+                .iter()
+                .all(|file| file.metadata_only && !file.is_deleted)
+        );
+        assert!(matches!(&results[2], ApplyResult::Deleted(path) if path == "old.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_directory_diff() -> Result<(), Box<dyn std::error::Error>> {
+        let old_dir = tempdir()?;
+        let new_dir = tempdir()?;
+        let (old_path, new_path) = (old_dir.path(), new_dir.path());
+        fs::create_dir_all(old_path.join("src"))?;
+        fs::create_dir_all(new_path.join("src"))?;
+        fs::write(old_path.join("src/lib.rs"), "fn a() {}\n")?;
+        fs::write(new_path.join("src/lib.rs"), "fn b() {}\n")?;
+        fs::write(new_path.join("README"), "docs\n")?;
+
+        let patcher = MultifilePatcher::from_directory_diff(old_path, new_path, 3)?;
+        assert_eq!(patcher.multifile_patch().patches.len(), 2);
+        patcher.apply_and_write(false)?;
+        assert_eq!(
+            fs::read_to_string(old_path.join("src/lib.rs"))?,
+            "fn b() {}\n"
+        );
+        assert_eq!(fs::read_to_string(old_path.join("README"))?, "docs\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_io_error_names_the_file() {
+        use std::error::Error as _;
+
+        let path = Path::new("no/such/patch.diff");
+        let err = MultifilePatch::parse_from_file(path).unwrap_err();
+        assert!(matches!(&err, Error::IoError { path: Some(p), .. } if p == path));
+        assert!(err.to_string().contains("no/such/patch.diff"));
+        let source = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(source.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_parse_from_reader() -> Result<(), Error> {
+        let patch_text = "\
+diff --git a/file.txt b/file.txt
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-old
++new
+";
+        let multipatch = MultifilePatch::parse_from_reader(patch_text.as_bytes())?;
+        assert_eq!(multipatch, MultifilePatch::parse(patch_text)?);
+        assert_eq!(multipatch, patch_text.parse()?);
+
+        let invalid: &[u8] = b"\xff\xfe";
+        assert!(matches!(
+            MultifilePatch::parse_from_reader(invalid),
+            Err(Error::IoError { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_renames() -> Result<(), Box<dyn std::error::Error>> {
+        let body = "fn main() {\n    let answer = 42;\n    println!(\"{}\", answer);\n}\n";
+        let moved = body.replace("42", "43");
+        let mut multipatch = MultifilePatch::new(vec![
+            Differ::new(body, "")
+                .file_names("src/old.rs", "/dev/null")
+                .generate(),
+            Differ::new("", "unrelated\n")
+                .file_names("/dev/null", "notes.txt")
+                .generate(),
+            Differ::new("", &moved)
+                .file_names("/dev/null", "src/new.rs")
+                .generate(),
+        ]);
+
+        multipatch.detect_renames(0.9);
+        assert_eq!(multipatch.patches.len(), 2);
+        let rename = &multipatch.patches[0];
+        assert!(rename.is_rename());
+        assert_eq!(rename.old_file, "src/old.rs");
+        assert_eq!(rename.new_file, "src/new.rs");
+        assert!(rename.similarity.is_some_and(|s| (90..100).contains(&s)));
+        assert_eq!(multipatch.patches[1].new_file, "notes.txt");
+
+        // The rename survives a round trip and moves the file when applied
+        let parsed = MultifilePatch::parse(&multipatch.to_string())?;
+        assert_eq!(parsed.patches[0].similarity, rename.similarity);
+        let temp_dir = tempdir()?;
+        fs::create_dir_all(temp_dir.path().join("src"))?;
+        fs::write(temp_dir.path().join("src/old.rs"), body)?;
+        MultifilePatcher::with_root(parsed, temp_dir.path()).apply_and_write(false)?;
+        assert!(!temp_dir.path().join("src/old.rs").exists());
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("src/new.rs"))?,
+            moved
+        );
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("notes.txt"))?,
+            "unrelated\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_renames_respects_threshold() {
+        let mut multipatch = MultifilePatch::new(vec![
+            Differ::new("alpha\nbeta\n", "")
+                .file_names("a.txt", "/dev/null")
+                .generate(),
+            Differ::new("", "1234\n5678\n")
+                .file_names("/dev/null", "b.txt")
+                .generate(),
+        ]);
+        multipatch.detect_renames(0.5);
+        assert_eq!(multipatch.patches.len(), 2);
+        assert!(multipatch.patches.iter().all(|p| !p.is_rename()));
+    }
+
+    #[test]
+    fn test_rename_candidates_match_scoring_every_pair() {
+        let mut rng = fastrand::Rng::with_seed(7);
+        let contents: Vec<String> = (0..40)
+            .map(|_| {
+                let words = rng.usize(0..12);
+                (0..words)
+                    .map(|_| ["a", "bb", "ccc", "  ", "\t", "\n"][rng.usize(0..6)])
+                    .collect()
+            })
+            .collect();
+        let (deletions, creations): (Vec<usize>, Vec<usize>) = (0..40).partition(|i| i % 2 == 0);
+
+        for threshold in [0.0, 0.3, 0.6, 0.9, 0.95, 1.0] {
+            let mut expected = Vec::new();
+            for &d in &deletions {
+                for &c in &creations {
+                    let score = similarity_score(&contents[d], &contents[c]);
+                    if score >= threshold {
+                        expected.push((d, c));
+                    }
+                }
+            }
+            let mut found: Vec<(usize, usize)> =
+                rename_candidates(&contents, &deletions, &creations, threshold)
+                    .into_iter()
+                    .map(|(_, d, c)| (d, c))
+                    .collect();
+            found.sort_unstable();
+            assert_eq!(found, expected, "threshold {threshold}");
+        }
+    }
+
+    #[test]
+    fn test_parse_combined_diff() -> Result<(), Error> {
+        let content = "\
+diff --git a/one.txt b/one.txt
+--- a/one.txt
++++ b/one.txt
+@@ -1 +1 @@
+-one
++ONE
+diff --cc file.txt
+index 1111111,2222222..3333333
+--- a/file.txt
++++ b/file.txt
+@@@ -1,4 -1,3 +1,4 @@@ fn merged()
+  shared
+- ours
+ -theirs
+++merged
+ +added
+  end
+";
+        let multipatch = MultifilePatch::parse(content)?;
+        assert_eq!(multipatch.patches.len(), 2);
+        let combined = &multipatch.patches[1];
+        assert!(combined.chunks.is_empty());
+        assert_eq!(combined.new_file, "file.txt");
+
+        let chunk = &combined.combined_chunks[0];
+        assert_eq!(chunk.parent_ranges, vec![(0, 4), (0, 3)]);
+        assert_eq!((chunk.new_start, chunk.new_lines), (0, 4));
+        assert_eq!(chunk.section_header.as_deref(), Some("fn merged()"));
+        assert_eq!(chunk.lines.len(), 6);
+        assert_eq!(chunk.lines[2].markers, " -");
+        assert_eq!(chunk.lines[2].content, "theirs");
+
+        // Written back unchanged
+        assert!(
+            combined
+                .to_string()
+                .ends_with(&content[content.find("@@@").unwrap()..])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_concatenated_bare_patches() -> Result<(), Error> {
+        let content = "\
+--- a/one.txt
++++ b/one.txt
+@@ -1,2 +1,2 @@
+--- removed line that looks like a header
++++ added line that looks like a header
+ keep
+--- a/two.txt
++++ b/two.txt
+@@ -1 +1 @@
+-old
++new
+";
+        let multipatch = MultifilePatch::parse(content)?;
+        assert_eq!(multipatch.patches.len(), 2);
+        assert_eq!(multipatch.patches[0].old_file, "one.txt");
+        assert_eq!(
+            multipatch.patches[0].chunks[0].operations[0],
+            crate::Operation::Remove("-- removed line that looks like a header".to_string())
+        );
+        assert_eq!(multipatch.patches[1].old_file, "two.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_by_path() {
+        let patch = |old: &str, new: &str| Patch {
+            old_file: old.to_string(),
+            new_file: new.to_string(),
+            ..Default::default()
+        };
+        let multipatch = MultifilePatch::new(vec![
+            patch("src/lib.rs", "src/lib.rs"),
+            patch("README.md", "README.md"),
+            patch("src/old.rs", "/dev/null"),
+        ]);
+
+        let filtered = multipatch.filter(|path| path.starts_with("src/"));
+        let files: Vec<&str> = filtered
+            .patches
+            .iter()
+            .map(|p| p.old_file.as_str())
+            .collect();
+        assert_eq!(files, vec!["src/lib.rs", "src/old.rs"]);
+        assert!(
+            multipatch
+                .filter(|path| path == "/dev/null")
+                .patches
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_merge() -> Result<(), Error> {
+        let diff = |old: &str, new: &str, path: &str| {
+            Differ::new(old, new).file_names(path, path).generate()
+        };
+        let mut merged = MultifilePatch::new(vec![
+            diff("a\nb\nc\n", "a\nB\nc\n", "one.txt"),
+            diff("x\n", "y\n", "two.txt"),
+        ]);
+        merged.merge(MultifilePatch::new(vec![
+            diff("a\nB\nc\n", "a\nB\nC\n", "one.txt"),
+            diff("1\n", "2\n", "three.txt"),
+        ]))?;
+
+        let files: Vec<&str> = merged.patches.iter().map(|p| p.new_file.as_str()).collect();
+        assert_eq!(files, vec!["one.txt", "two.txt", "three.txt"]);
+        let reparsed = MultifilePatch::parse(&merged.to_string())?;
+        assert_eq!(reparsed.patches.len(), 3);
+        let patcher = Patcher::new(reparsed.patches[0].clone());
+        assert_eq!(patcher.apply("a\nb\nc\n", false)?, "a\nB\nC\n");
+
+        // The second change to one.txt expects a line the first one did not produce
+        let conflicting = MultifilePatch::new(vec![diff("a\nb\nc\n", "A\nb\nc\n", "one.txt")]);
+        assert!(merged.merge(conflicting).is_err());
+        assert_eq!(merged.patches.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_rename_with_edit() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("old.txt"), "a\nb\n")?;
+        let mut merged = MultifilePatch::parse(
+            "diff --git a/old.txt b/new.txt\nsimilarity index 100%\nrename from old.txt\nrename to new.txt\n",
+        )?;
+        merged.merge(MultifilePatch::new(vec![
+            Differ::new("a\nb\n", "a\nB\n")
+                .file_names("new.txt", "new.txt")
+                .generate(),
+        ]))?;
+
+        assert_eq!(merged.patches.len(), 1);
+        let patch = &merged.patches[0];
+        assert!(patch.is_rename());
+        assert_eq!(patch.similarity, Some(100));
+        let reparsed = MultifilePatch::parse(&merged.to_git_format())?;
+        assert!(reparsed.patches[0].is_rename());
+        assert_eq!(reparsed.patches[0].chunks, patch.chunks);
+
+        MultifilePatcher::with_root(merged, dir.path()).apply_and_write(false)?;
+        assert!(!dir.path().join("old.txt").exists());
+        assert_eq!(fs::read_to_string(dir.path().join("new.txt"))?, "a\nB\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diffstat() {
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("a\nb\nc\n", "a\nB\nc\nd\n")
+                .file_names("src/lib.rs", "src/lib.rs")
+                .generate(),
+            Differ::new("x\n", "")
+                .file_names("old.txt", "/dev/null")
+                .generate(),
+        ]);
+        assert_eq!(
+            multipatch.diffstat(80),
+            concat!(
+                " src/lib.rs | 3 ++-\n",
+                " old.txt    | 1 -\n",
+                " 2 files changed, 2 insertions(+), 2 deletions(-)\n",
+            )
+        );
+
+        // 100 insertions and 50 deletions scaled into a 10 column graph
+        let old: String = (0..50).map(|i| format!("{i}\n")).collect();
+        let new: String = (0..100).map(|i| format!("new {i}\n")).collect();
+        let wide = MultifilePatch::new(vec![
+            Differ::new(&old, &new).file_names("f", "f").generate(),
+        ]);
+        let stat = wide.diffstat(19);
+        assert_eq!(stat.lines().next(), Some(" f | 150 +++++++---"));
+        assert!(stat.ends_with(" 1 file changed, 100 insertions(+), 50 deletions(-)\n"));
+    }
+
+    #[test]
+    fn test_write_to() -> Result<(), Error> {
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("a\nb\n", "a\nB\n")
+                .file_names("one.txt", "one.txt")
+                .generate(),
+            Differ::new("", "new\n")
+                .file_names("/dev/null", "two.txt")
+                .generate(),
+        ]);
+        let mut out = Vec::new();
+        multipatch.write_to(&mut io::BufWriter::new(&mut out))?;
+        assert_eq!(String::from_utf8(out).unwrap(), multipatch.to_string());
+
+        let mut out = Vec::new();
+        multipatch.patches[0].write_to(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            multipatch.patches[0].to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_git_format_round_trip() -> Result<(), Error> {
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("a\nb\n", "a\nB\n")
+                .file_names("one.txt", "one.txt")
+                .generate(),
+            Differ::new("", "new\n")
+                .file_names("/dev/null", "two.txt")
+                .generate(),
+            Differ::new("gone\n", "")
+                .file_names("three.txt", "/dev/null")
+                .generate(),
+            Patch::rename("four.txt", "five.txt"),
+        ]);
+        let git = multipatch.to_git_format();
+        assert_eq!(git.matches("diff --git ").count(), 4);
+        assert!(git.starts_with("diff --git a/one.txt b/one.txt\n"));
+
+        let parsed = MultifilePatch::parse(&git)?;
+        assert_eq!(parsed.patches.len(), 4);
+        assert!(parsed.patches[3].is_rename());
+        assert_eq!(parsed.to_git_format(), git);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_by_path() {
+        let patch = |old: &str, new: &str| Patch {
+            old_file: old.to_string(),
+            new_file: new.to_string(),
+            ..Default::default()
+        };
+        let mut multipatch = MultifilePatch::new(vec![
+            patch("src/main.rs", "src/main.rs"),
+            patch("/dev/null", "b.txt"),
+            patch("src/lib.rs", "/dev/null"),
+            patch("a.txt", "src/a.txt"),
+        ]);
+
+        multipatch.sort_by_path();
+        let files: Vec<(&str, &str)> = multipatch
+            .patches
+            .iter()
+            .map(|p| (p.old_file.as_str(), p.new_file.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("/dev/null", "b.txt"),
+                ("a.txt", "src/a.txt"),
+                ("src/lib.rs", "/dev/null"),
+                ("src/main.rs", "src/main.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_remap_paths() {
+        let mut multipatch = MultifilePatch::new(vec![
+            Patch {
+                old_file: "a.txt".to_string(),
+                new_file: "a.txt".to_string(),
+                ..Default::default()
+            },
+            Patch {
+                old_file: "/dev/null".to_string(),
+                new_file: "b.txt".to_string(),
+                ..Default::default()
+            },
+            Patch::rename("c.txt", "d.txt"),
+        ]);
+
+        multipatch.remap_paths(|path| format!("vendor/{path}"));
+        let files: Vec<(&str, &str)> = multipatch
+            .patches
+            .iter()
+            .map(|p| (p.old_file.as_str(), p.new_file.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("vendor/a.txt", "vendor/a.txt"),
+                ("/dev/null", "vendor/b.txt"),
+                ("vendor/c.txt", "vendor/d.txt"),
+            ]
+        );
+        let rename = &multipatch.patches[2];
+        assert_eq!(rename.rename_from.as_deref(), Some("vendor/c.txt"));
+        assert_eq!(rename.rename_to.as_deref(), Some("vendor/d.txt"));
+    }
+
+    #[test]
+    fn test_apply_and_write_atomic_writes_nothing_on_failure() -> Result<(), Error> {
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::write(temp_path.join("good.txt"), "a\nb\nc\n")?;
+        fs::write(temp_path.join("bad.txt"), "unrelated\n")?;
+
+        let multipatch = create_test_multifile_patch(
+            temp_path,
+            &[
+                ("good.txt", "a\nb\nc\n", "a\nB\nc\n"),
+                ("bad.txt", "x\ny\nz\n", "x\nY\nz\n"),
+            ],
+        );
+        let results = MultifilePatcher::new(multipatch.clone()).apply_and_write_atomic(false)?;
+        assert!(matches!(results[1], ApplyResult::Failed(_, _)));
+        assert_eq!(fs::read_to_string(temp_path.join("good.txt"))?, "a\nb\nc\n");
+
+        fs::write(temp_path.join("bad.txt"), "x\ny\nz\n")?;
+        let results = MultifilePatcher::new(multipatch).apply_and_write_atomic(false)?;
+        assert!(
+            results
+                .iter()
+                .all(|result| matches!(result, ApplyResult::Applied(_)))
+        );
+        assert_eq!(fs::read_to_string(temp_path.join("good.txt"))?, "a\nB\nc\n");
+        assert_eq!(fs::read_to_string(temp_path.join("bad.txt"))?, "x\nY\nz\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_and_write_atomic_rollback_tries_every_file() -> Result<(), Error> {
+        /// The disk, except that `locked.txt` can never be written
+        struct LockedFile;
+
+        impl FileSource for LockedFile {
+            fn read(&self, path: &Path) -> io::Result<String> {
+                DiskFileSource.read(path)
+            }
+
+            fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+                if path.ends_with("locked.txt") {
+                    return Err(io::Error::from(io::ErrorKind::PermissionDenied));
+                }
+                DiskFileSource.write(path, content)
+            }
+
+            fn delete(&self, path: &Path) -> io::Result<()> {
+                DiskFileSource.delete(path)
+            }
+
+            fn exists(&self, path: &Path) -> bool {
+                DiskFileSource.exists(path)
+            }
+
+            fn remove_dir(&self, path: &Path) -> io::Result<()> {
+                DiskFileSource.remove_dir(path)
+            }
+        }
+
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::write(temp_path.join("edited.txt"), "a\nb\nc\n")?;
+        fs::write(temp_path.join("locked.txt"), "x\ny\nz\n")?;
+        let created = temp_path.join("new/dir/created.txt");
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("", "new\n")
+                .file_names("/dev/null", created.to_str().unwrap())
+                .generate(),
+            Differ::new("a\nb\nc\n", "a\nB\nc\n")
+                .file_names(
+                    temp_path.join("edited.txt").to_str().unwrap(),
+                    temp_path.join("edited.txt").to_str().unwrap(),
+                )
+                .generate(),
+            Differ::new("x\ny\nz\n", "x\nY\nz\n")
+                .file_names(
+                    temp_path.join("locked.txt").to_str().unwrap(),
+                    temp_path.join("locked.txt").to_str().unwrap(),
+                )
+                .generate(),
+        ]);
+
+        let error = MultifilePatcher::with_source(multipatch, LockedFile)
+            .apply_and_write_atomic(false)
+            .unwrap_err();
+        // Restoring locked.txt fails too, but the other files are still restored
+        assert!(error.to_string().contains("could not restore"), "{error}");
+        assert!(error.to_string().contains("locked.txt"), "{error}");
+        assert_eq!(
+            fs::read_to_string(temp_path.join("edited.txt"))?,
+            "a\nb\nc\n"
+        );
+        assert!(!temp_path.join("new").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_over_in_memory_source() -> Result<(), Error> {
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct MemorySource(Arc<Mutex<HashMap<String, String>>>);
+
+        impl FileSource for MemorySource {
+            fn read(&self, path: &Path) -> io::Result<String> {
+                let files = self.0.lock().unwrap();
+                files
+                    .get(&path.display().to_string())
+                    .cloned()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+            }
+
+            fn write(&self, path: &Path, content: &str) -> io::Result<()> {
+                let mut files = self.0.lock().unwrap();
+                files.insert(path.display().to_string(), content.to_string());
+                Ok(())
+            }
+
+            fn delete(&self, path: &Path) -> io::Result<()> {
+                let mut files = self.0.lock().unwrap();
+                files.remove(&path.display().to_string());
+                Ok(())
+            }
+
+            fn exists(&self, path: &Path) -> bool {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .contains_key(&path.display().to_string())
+            }
+        }
+
+        let source = MemorySource::default();
+        source.0.lock().unwrap().extend([
+            ("kept.txt".to_string(), "a\nb\nc\n".to_string()),
+            ("gone.txt".to_string(), "x\n".to_string()),
+        ]);
+        let multipatch = MultifilePatch::new(vec![
+            Differ::new("a\nb\nc\n", "a\nB\nc\n")
+                .file_names("kept.txt", "kept.txt")
+                .generate(),
+            Differ::new("", "new\n")
+                .file_names("/dev/null", "added.txt")
+                .generate(),
+            Differ::new("x\n", "")
+                .file_names("gone.txt", "/dev/null")
+                .generate(),
+        ]);
+
+        let results =
+            MultifilePatcher::with_source(multipatch, source.clone()).apply_and_write(false)?;
+        assert!(
+            results
+                .iter()
+                .all(|result| !matches!(result, ApplyResult::Failed(_, _)))
+        );
+        let files = source.0.lock().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files["kept.txt"], "a\nB\nc\n");
+        assert_eq!(files["added.txt"], "new\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_multifile_patcher_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MultifilePatcher>();
+    }
+
+    #[test]
+    fn test_progress_callback() -> Result<(), Error> {
+        use std::sync::{Arc, Mutex};
+
+        let temp_dir = tempdir()?;
+        let temp_path = temp_dir.path();
+        fs::write(temp_path.join("one.txt"), "a\n")?;
+        fs::write(temp_path.join("two.txt"), "b\n")?;
+        let multipatch = create_test_multifile_patch(
+            temp_path,
+            &[("one.txt", "a\n", "A\n"), ("two.txt", "b\n", "B\n")],
+        );
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&seen);
+        let patcher = MultifilePatcher::new(multipatch).on_progress(Box::new(
+            move |index, total, path: &str| {
+                let name = Path::new(path).file_name().unwrap().to_string_lossy();
+                recorder
+                    .lock()
+                    .unwrap()
+                    .push((index, total, name.into_owned()));
+            },
+        ));
+        patcher.apply_and_write(false)?;
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(0, 2, "one.txt".to_string()), (1, 2, "two.txt".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unchanged_files_are_omitted() -> Result<(), Error> {
+        let old_dir = tempdir()?;
+        let new_dir = tempdir()?;
+        fs::write(old_dir.path().join("same.txt"), "a\nb\n")?;
+        fs::write(new_dir.path().join("same.txt"), "a\nb\n")?;
+        fs::write(old_dir.path().join("eol.txt"), "a\nb\n")?;
+        fs::write(new_dir.path().join("eol.txt"), "a\r\nb\r\n")?;
+
+        let multipatch = MultifilePatch::from_dirs(old_dir.path(), new_dir.path(), 3)?;
+        assert!(multipatch.patches.is_empty());
+        assert!(multipatch.is_empty());
+
+        let unchanged = Differ::new("x\n", "x\n")
+            .file_names("x.txt", "x.txt")
+            .generate();
+        let changed = Differ::new("y\n", "z\n")
+            .file_names("y.txt", "y.txt")
+            .generate();
+        let multipatch = MultifilePatch::new(vec![unchanged.clone(), changed.clone()]);
+        assert!(!multipatch.is_empty());
+        assert!(MultifilePatch::new(vec![unchanged]).is_empty());
+        assert_eq!(multipatch.to_string(), format!("{}\n", changed));
+        Ok(())
+    }
 }
@@ -1,5 +1,9 @@
-use crate::Error;
-use std::fmt;
+use crate::{ChunkHeaderProblem, Error, Patcher, PatcherAlgorithm};
+use std::borrow::Cow;
+use std::fmt::{self, Write as _};
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Represents a change operation in the patch
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,13 +25,81 @@ impl Operation {
         }
     }
 
-    pub(crate) fn line(&self) -> &str {
+    /// The text of the line, without the `+`/`-`/` ` prefix.
+    pub fn line(&self) -> &str {
         match self {
             Operation::Add(line) => line,
             Operation::Remove(line) => line,
             Operation::Context(line) => line,
         }
     }
+
+    /// Consumes the operation and returns its line.
+    pub fn into_line(self) -> String {
+        match self {
+            Operation::Add(line) | Operation::Remove(line) | Operation::Context(line) => line,
+        }
+    }
+
+    pub fn is_add(&self) -> bool {
+        matches!(self, Operation::Add(_))
+    }
+
+    pub fn is_remove(&self) -> bool {
+        matches!(self, Operation::Remove(_))
+    }
+
+    pub fn is_context(&self) -> bool {
+        matches!(self, Operation::Context(_))
+    }
+
+    /// Whether the operation adds or removes a line.
+    pub fn is_change(&self) -> bool {
+        !self.is_context()
+    }
+
+    /// Transforms the line with `f`, keeping the kind of operation.
+    pub fn map_line<F: FnOnce(&str) -> String>(&self, f: F) -> Operation {
+        let line = f(self.line());
+        match self {
+            Operation::Add(_) => Operation::Add(line),
+            Operation::Remove(_) => Operation::Remove(line),
+            Operation::Context(_) => Operation::Context(line),
+        }
+    }
+}
+
+/// Caps on the size of a patch accepted by [`Patch::parse_with_limits`].
+///
+/// The default imposes no limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Maximum number of operations (added, removed and context lines) in all chunks
+    pub max_operations: usize,
+    /// Maximum number of chunks
+    pub max_chunks: usize,
+    /// Maximum line count a chunk header may declare for either side
+    pub max_chunk_lines: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: usize::MAX,
+            max_chunks: usize::MAX,
+            max_chunk_lines: usize::MAX,
+        }
+    }
+}
+
+/// Where a line of the old file ends up in the new file, as returned by
+/// [`Patch::line_map`]. Line numbers are 0-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineMapping {
+    /// The line is unchanged and moves to `new`.
+    Kept { old: usize, new: usize },
+    /// The line is removed by the patch.
+    Deleted { old: usize },
 }
 
 /// A chunk represents a continuous section of changes in a file
@@ -43,21 +115,252 @@ pub struct Chunk {
     pub new_lines: usize,
     /// The operations in this chunk
     pub operations: Vec<Operation>,
+    /// Text shown after the closing `@@` of the header, usually the enclosing function
+    pub section_header: Option<String>,
+}
+
+/// A chunk of a combined diff, as git shows for merge commits, with a range in
+/// each parent: `@@@ -1,2 -1,2 +1,3 @@@` for two parents.
+///
+/// Combined chunks are parsed so they can be inspected and written back; they
+/// are not applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedChunk {
+    /// Starting line (0-based) and number of lines in each parent, in order
+    pub parent_ranges: Vec<(usize, usize)>,
+    /// Starting line in the merge result (0-based)
+    pub new_start: usize,
+    /// Number of lines in the merge result
+    pub new_lines: usize,
+    /// The lines of the chunk
+    pub lines: Vec<CombinedLine>,
+    /// Text shown after the closing `@@@` of the header
+    pub section_header: Option<String>,
+}
+
+/// A line of a [`CombinedChunk`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CombinedLine {
+    /// One character per parent: `-` for a line of that parent that the result
+    /// drops, `+` for a line of the result that parent lacks. A line with a `-`
+    /// is only in the parents marked `-`; otherwise a space means the parent has it.
+    pub markers: String,
+    /// The line, without the markers
+    pub content: String,
+}
+
+impl fmt::Display for CombinedChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = "@".repeat(self.parent_ranges.len() + 1);
+        write!(f, "{}", marker)?;
+        for &(start, lines) in &self.parent_ranges {
+            write!(f, " -{}", format_range(start, lines))?;
+        }
+        write!(
+            f,
+            " +{} {}",
+            format_range(self.new_start, self.new_lines),
+            marker
+        )?;
+        match &self.section_header {
+            Some(header) => writeln!(f, " {}", header)?,
+            None => writeln!(f)?,
+        }
+        for line in &self.lines {
+            writeln!(f, "{}{}", line.markers, line.content)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the lines the operations take from the old and the new file.
+fn count_lines(operations: &[Operation]) -> (usize, usize) {
+    operations.iter().fold((0, 0), |(o, n), op| match op {
+        Operation::Add(_) => (o, n + 1),
+        Operation::Remove(_) => (o + 1, n),
+        Operation::Context(_) => (o + 1, n + 1),
+    })
+}
+
+/// Formats one side of a chunk header range with a 1-based start. Like GNU diff
+/// and git, the count is omitted when it is 1, and an empty range shows the line it follows.
+fn format_range(start: usize, lines: usize) -> String {
+    match lines {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, lines),
+    }
 }
 
+/// Lengths of the runs of `Context` operations at the start and at the end of
+/// `operations`. Both cover everything if there are only context lines.
+pub(crate) fn context_run_lengths(operations: &[Operation]) -> (usize, usize) {
+    let leading = operations.iter().take_while(|op| op.is_context()).count();
+    let trailing = operations
+        .iter()
+        .rev()
+        .take_while(|op| op.is_context())
+        .count();
+    (leading, trailing)
+}
+
+/// Marker line git emits after a line that is not terminated by a newline.
+const NO_NEWLINE_MARKER: &str = "\\ No newline at end of file";
+
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(
+        self.write_with_markers(f, false, false)
+    }
+}
+
+impl Chunk {
+    /// Creates a chunk at the given 0-based start lines, with `old_lines` and
+    /// `new_lines` counted from `operations` so they always agree.
+    pub fn from_operations(
+        old_start: usize,
+        new_start: usize,
+        operations: Vec<Operation>,
+    ) -> Chunk {
+        let (old_lines, new_lines) = count_lines(&operations);
+        Chunk {
+            old_start,
+            old_lines,
+            new_start,
+            new_lines,
+            operations,
+            section_header: None,
+        }
+    }
+
+    /// The context lines before the first addition or removal.
+    pub fn leading_context(&self) -> &[Operation] {
+        &self.operations[..context_run_lengths(&self.operations).0]
+    }
+
+    /// The context lines after the last addition or removal.
+    pub fn trailing_context(&self) -> &[Operation] {
+        let (_, trailing) = context_run_lengths(&self.operations);
+        &self.operations[self.operations.len() - trailing..]
+    }
+
+    /// The operations from the first to the last addition or removal, including
+    /// any context between them. Empty if the chunk only holds context.
+    pub fn core(&self) -> &[Operation] {
+        let (leading, trailing) = context_run_lengths(&self.operations);
+        if leading == self.operations.len() {
+            return &[];
+        }
+        &self.operations[leading..self.operations.len() - trailing]
+    }
+
+    /// Applies just this chunk to `source` and returns the patched region with
+    /// up to two lines of the surrounding file on each side, e.g. to preview a
+    /// hunk in a review UI.
+    ///
+    /// The chunk is located like [`PatcherAlgorithm::Similar`](crate::PatcherAlgorithm::Similar)
+    /// does, so it may have moved or its context may differ slightly.
+    pub fn preview(&self, source: &str) -> Result<String, Error> {
+        const PREVIEW_CONTEXT: usize = 2;
+        let patch = Patch {
+            chunks: vec![self.clone()],
+            ..Default::default()
+        };
+        let (patched, hunks) = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar)
+            .apply_tracked(source, false)?;
+        let start = hunks[0].actual_start;
+        let lines: Vec<&str> = patched.lines().collect();
+        let from = start.saturating_sub(PREVIEW_CONTEXT).min(lines.len());
+        let to = (start + self.new_lines + PREVIEW_CONTEXT).min(lines.len());
+        Ok(lines[from..to]
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect())
+    }
+
+    /// Splits the chunk wherever a run of context lines is longer than
+    /// `2 * context_lines`, so that each piece keeps at most `context_lines` of
+    /// context around its changes. Start lines and counts are recomputed for each
+    /// piece. A chunk without any additions or removals yields no pieces.
+    pub fn split(&self, context_lines: usize) -> Vec<Chunk> {
+        let changes: Vec<usize> = self
+            .operations
+            .iter()
+            .enumerate()
+            .filter(|(_, op)| !matches!(op, Operation::Context(_)))
+            .map(|(i, _)| i)
+            .collect();
+
+        // Group changes separated by at most 2 * context_lines of context
+        let mut groups: Vec<(usize, usize)> = Vec::new();
+        for &i in &changes {
+            match groups.last_mut() {
+                Some((_, last)) if i - *last - 1 <= context_lines.saturating_mul(2) => *last = i,
+                _ => groups.push((i, i)),
+            }
+        }
+
+        groups
+            .into_iter()
+            .map(|(first, last)| {
+                let start = first.saturating_sub(context_lines);
+                let end = last
+                    .saturating_add(context_lines)
+                    .min(self.operations.len() - 1);
+                let (old_skipped, new_skipped) = count_lines(&self.operations[..start]);
+                let operations = self.operations[start..=end].to_vec();
+                let (old_lines, new_lines) = count_lines(&operations);
+                Chunk {
+                    old_start: self.old_start + old_skipped,
+                    old_lines,
+                    new_start: self.new_start + new_skipped,
+                    new_lines,
+                    operations,
+                    // Only the first piece starts where the enclosing section was found
+                    section_header: if start == 0 {
+                        self.section_header.clone()
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Writes the chunk, adding the no-newline marker after the last old-side
+    /// line if `old_missing_newline` and after the last new-side line if `new_missing_newline`.
+    fn write_with_markers(
+        &self,
+        f: &mut impl fmt::Write,
+        old_missing_newline: bool,
+        new_missing_newline: bool,
+    ) -> fmt::Result {
+        write!(
             f,
-            "@@ -{},{} +{},{} @@",
-            self.old_start + 1, // Display as 1-based index
-            self.old_lines,
-            self.new_start + 1, // Display as 1-based index
-            self.new_lines
+            "@@ -{} +{} @@",
+            format_range(self.old_start, self.old_lines),
+            format_range(self.new_start, self.new_lines)
         )?;
+        match &self.section_header {
+            Some(header) => writeln!(f, " {}", header)?,
+            None => writeln!(f)?,
+        }
+
+        let last_old = self
+            .operations
+            .iter()
+            .rposition(|op| !matches!(op, Operation::Add(_)));
+        let last_new = self
+            .operations
+            .iter()
+            .rposition(|op| !matches!(op, Operation::Remove(_)));
 
-        for op in &self.operations {
+        for (i, op) in self.operations.iter().enumerate() {
             writeln!(f, "{}{}", op.to_char(), op.line())?;
+            if (old_missing_newline && last_old == Some(i))
+                || (new_missing_newline && last_new == Some(i))
+            {
+                writeln!(f, "{}", NO_NEWLINE_MARKER)?;
+            }
         }
 
         Ok(())
@@ -65,7 +368,7 @@ impl fmt::Display for Chunk {
 }
 
 /// A patch represents all the changes between two versions of a file
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Patch {
     /// Preemble of the patch, something like "diff -u a/file.txt b/file.txt"
     pub preamble: Option<String>,
@@ -73,13 +376,536 @@ pub struct Patch {
     pub old_file: String,
     /// New file path, often prefixed with `b/`
     pub new_file: String,
+    /// Source path from a git `copy from` header, set when the new file is a copy
+    pub copy_from: Option<String>,
+    /// Destination path from a git `copy to` header
+    pub copy_to: Option<String>,
+    /// Source path from a git `rename from` header, set when the file was moved
+    pub rename_from: Option<String>,
+    /// Destination path from a git `rename to` header
+    pub rename_to: Option<String>,
+    /// Percentage from a git `similarity index` header of a rename or copy
+    pub similarity: Option<u8>,
+    /// Other git extended header lines kept verbatim, e.g. `index 123..456 100644`
+    /// or `old mode 100644`
+    pub extended_headers: Vec<String>,
+    /// Prefix written before `old_file` in the `---` header (`a/` by default, may be empty)
+    pub old_prefix: String,
+    /// Prefix written before `new_file` in the `+++` header (`b/` by default, may be empty)
+    pub new_prefix: String,
+    /// Timestamp written after `old_file` in the `---` header, e.g. `2023-01-01 10:00:00.000000000 +0000`
+    pub old_timestamp: Option<String>,
+    /// Timestamp written after `new_file` in the `+++` header
+    pub new_timestamp: Option<String>,
+    /// The last line of the last chunk is the end of the original file and has no trailing newline
+    pub old_missing_newline: bool,
+    /// The last line of the last chunk is the end of the new file and has no trailing newline
+    pub new_missing_newline: bool,
     /// Chunks of changes
     pub chunks: Vec<Chunk>,
+    /// Chunks of a combined diff (`diff --cc`) of a merge, which are not applied
+    pub combined_chunks: Vec<CombinedChunk>,
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self {
+            preamble: None,
+            old_file: String::new(),
+            new_file: String::new(),
+            copy_from: None,
+            copy_to: None,
+            rename_from: None,
+            rename_to: None,
+            similarity: None,
+            extended_headers: Vec::new(),
+            old_prefix: "a/".to_string(),
+            new_prefix: "b/".to_string(),
+            old_timestamp: None,
+            new_timestamp: None,
+            old_missing_newline: false,
+            new_missing_newline: false,
+            chunks: Vec::new(),
+            combined_chunks: Vec::new(),
+        }
+    }
 }
 
 impl Patch {
+    /// Returns `true` if this patch creates `new_file` as a copy of `old_file`
+    /// (git `copy from`/`copy to` headers). The source file is left in place.
+    pub fn is_copy(&self) -> bool {
+        self.copy_from.is_some() && self.copy_to.is_some()
+    }
+
+    /// Returns `true` if this patch moves `old_file` to `new_file`
+    /// (git `rename from`/`rename to` headers).
+    pub fn is_rename(&self) -> bool {
+        self.rename_from.is_some() && self.rename_to.is_some()
+    }
+
+    /// Returns `true` if the patch changes only metadata and no content: it is a
+    /// pure copy or rename, or only changes the file mode (git `old mode`/`new mode`
+    /// headers), so it has no chunks.
+    pub fn is_metadata_only(&self) -> bool {
+        self.chunks.is_empty()
+            && self.combined_chunks.is_empty()
+            && (self.is_copy()
+                || self.is_rename()
+                || self
+                    .extended_headers
+                    .iter()
+                    .any(|header| header.starts_with("new mode ")))
+    }
+
+    /// A patch that moves `from` to `to` without changing its content: git
+    /// `rename from`/`rename to` headers with 100% similarity and no chunks.
+    pub fn rename(from: &str, to: &str) -> Self {
+        Patch {
+            preamble: Some(format!("diff --git a/{} b/{}", from, to)),
+            old_file: from.to_string(),
+            new_file: to.to_string(),
+            rename_from: Some(from.to_string()),
+            rename_to: Some(to.to_string()),
+            similarity: Some(100),
+            ..Default::default()
+        }
+    }
+
+    /// Rewrites the paths of the patch with `f`, e.g. to apply it in a different
+    /// directory layout: `old_file` and `new_file`, and the copy and rename paths
+    /// if present. `/dev/null` is left alone. The preamble is kept as it is.
+    pub fn remap_paths<F: Fn(&str) -> String>(&mut self, f: F) {
+        let paths = [
+            Some(&mut self.old_file),
+            Some(&mut self.new_file),
+            self.copy_from.as_mut(),
+            self.copy_to.as_mut(),
+            self.rename_from.as_mut(),
+            self.rename_to.as_mut(),
+        ];
+        for path in paths.into_iter().flatten() {
+            if path != "/dev/null" {
+                *path = f(path);
+            }
+        }
+    }
+
+    /// Counts the lines the patch adds and removes, as `(insertions, deletions)`.
+    pub fn stats(&self) -> (usize, usize) {
+        self.chunks.iter().flat_map(|chunk| &chunk.operations).fold(
+            (0, 0),
+            |(insertions, deletions), op| match op {
+                Operation::Add(_) => (insertions + 1, deletions),
+                Operation::Remove(_) => (insertions, deletions + 1),
+                Operation::Context(_) => (insertions, deletions),
+            },
+        )
+    }
+
+    /// Renders the patch like [`Display`](fmt::Display), with ANSI colors for
+    /// terminals: bold file headers, cyan chunk headers, red removals and green additions.
+    ///
+    /// Deciding whether to color (TTY detection, `NO_COLOR`) is left to the caller.
+    pub fn to_colored_string(&self) -> String {
+        const BOLD: &str = "\x1b[1m";
+        const RED: &str = "\x1b[31m";
+        const GREEN: &str = "\x1b[32m";
+        const CYAN: &str = "\x1b[36m";
+        const RESET: &str = "\x1b[0m";
+
+        let plain = self.to_string();
+        let mut colored = String::with_capacity(plain.len() * 2);
+        let mut in_chunks = false;
+        for line in plain.lines() {
+            in_chunks |= line.starts_with("@@ ");
+            let color = if !in_chunks {
+                Some(BOLD)
+            } else if line.starts_with("@@ ") {
+                Some(CYAN)
+            } else if line.starts_with('+') {
+                Some(GREEN)
+            } else if line.starts_with('-') {
+                Some(RED)
+            } else {
+                None
+            };
+            match color {
+                Some(color) => {
+                    colored.push_str(color);
+                    colored.push_str(line);
+                    colored.push_str(RESET);
+                }
+                None => colored.push_str(line),
+            }
+            colored.push('\n');
+        }
+        colored
+    }
+
+    /// The length in characters of the longest added, removed or context line,
+    /// without its `+`, `-` or space prefix. `0` for a patch without chunks.
+    pub fn max_line_width(&self) -> usize {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| &chunk.operations)
+            .map(|op| op.line().chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Renders the patch like [`Display`](fmt::Display), with added, removed and
+    /// context lines longer than `width` characters hard-wrapped for display.
+    ///
+    /// Each continuation line repeats the line's `+`, `-` or space prefix followed
+    /// by `↪`, so wrapped lines keep their kind. The result is not meant to be
+    /// parsed back.
+    pub fn to_string_wrapped(&self, width: usize) -> String {
+        const CONTINUATION: char = '↪';
+        let width = width.max(1);
+
+        let plain = self.to_string();
+        let mut wrapped = String::with_capacity(plain.len());
+        let mut in_chunks = false;
+        for line in plain.lines() {
+            in_chunks |= line.starts_with("@@ ");
+            let prefix = line.chars().next().filter(|c| matches!(c, '+' | '-' | ' '));
+            match prefix {
+                Some(prefix) if in_chunks && line.chars().count() > width + 1 => {
+                    let content: Vec<char> = line.chars().skip(1).collect();
+                    for (i, piece) in content.chunks(width).enumerate() {
+                        wrapped.push(prefix);
+                        if i > 0 {
+                            wrapped.push(CONTINUATION);
+                        }
+                        wrapped.extend(piece);
+                        wrapped.push('\n');
+                    }
+                }
+                _ => {
+                    wrapped.push_str(line);
+                    wrapped.push('\n');
+                }
+            }
+        }
+        wrapped
+    }
+
+    /// Renders the operations as JSON Lines, one object per operation, e.g. to
+    /// stream them to a diff viewer: `{"op":"add","old":null,"new":41,"text":"..."}`.
+    ///
+    /// `op` is `context`, `add` or `remove`; `old` and `new` are the 0-based line
+    /// numbers as in [`iter_operations`](Self::iter_operations), `null` on the side
+    /// the line is not in. Every line ends with `\n`.
+    pub fn to_ndjson(&self) -> String {
+        let mut json = String::new();
+        for (old, new, op) in self.iter_operations() {
+            let (kind, old, new) = match op {
+                Operation::Context(_) => ("context", Some(old), Some(new)),
+                Operation::Add(_) => ("add", None, Some(new)),
+                Operation::Remove(_) => ("remove", Some(old), None),
+            };
+            json.push_str("{\"op\":\"");
+            json.push_str(kind);
+            json.push_str("\",\"old\":");
+            push_json_number(&mut json, old);
+            json.push_str(",\"new\":");
+            push_json_number(&mut json, new);
+            json.push_str(",\"text\":");
+            push_json_string(&mut json, op.line());
+            json.push_str("}\n");
+        }
+        json
+    }
+
+    /// Iterates over every operation in the patch together with its position,
+    /// yielding `(old_line, new_line, op)` with 0-based line numbers.
+    ///
+    /// `Context` and `Remove` operations advance the old line counter, `Context`
+    /// and `Add` operations advance the new one.
+    pub fn iter_operations(&self) -> impl Iterator<Item = (usize, usize, &Operation)> {
+        self.chunks.iter().flat_map(|chunk| {
+            chunk
+                .operations
+                .iter()
+                .scan((chunk.old_start, chunk.new_start), |(old, new), op| {
+                    let position = (*old, *new, op);
+                    match op {
+                        Operation::Add(_) => *new += 1,
+                        Operation::Remove(_) => *old += 1,
+                        Operation::Context(_) => {
+                            *old += 1;
+                            *new += 1;
+                        }
+                    }
+                    Some(position)
+                })
+        })
+    }
+
+    /// Maps each line of the old file to its line in the new file, or marks it
+    /// as deleted, in old line order with 0-based line numbers.
+    ///
+    /// Lines before and between chunks are unchanged. The patch does not record
+    /// the length of the old file, so the map ends with the last chunk; any
+    /// later line `old` moves to `old + new_len - old_len` of the last entry.
+    pub fn line_map(&self) -> Vec<LineMapping> {
+        let mut map = Vec::new();
+        let mut next_old = 0;
+        for chunk in &self.chunks {
+            for old in next_old..chunk.old_start {
+                let new = (chunk.new_start + old).saturating_sub(chunk.old_start);
+                map.push(LineMapping::Kept { old, new });
+            }
+            let (mut old, mut new) = (chunk.old_start, chunk.new_start);
+            for op in &chunk.operations {
+                match op {
+                    Operation::Add(_) => new += 1,
+                    Operation::Remove(_) => {
+                        map.push(LineMapping::Deleted { old });
+                        old += 1;
+                    }
+                    Operation::Context(_) => {
+                        map.push(LineMapping::Kept { old, new });
+                        old += 1;
+                        new += 1;
+                    }
+                }
+            }
+            next_old = next_old.max(old);
+        }
+        map
+    }
+
+    /// Lists the whitespace errors the patch introduces, like `git diff --check`,
+    /// as `(line, message)` pairs with 1-based new-file line numbers.
+    ///
+    /// Only `Add` lines are checked, for trailing spaces or tabs and for a space
+    /// followed by a tab in the indentation. A line with both errors is listed twice.
+    pub fn whitespace_warnings(&self) -> Vec<(usize, String)> {
+        let mut warnings = Vec::new();
+        for (_, new, op) in self.iter_operations() {
+            let Operation::Add(line) = op else {
+                continue;
+            };
+            if line.ends_with([' ', '\t']) {
+                warnings.push((new + 1, "trailing whitespace".to_string()));
+            }
+            let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+            if indent.contains(" \t") {
+                warnings.push((new + 1, "space before tab in indent".to_string()));
+            }
+        }
+        warnings
+    }
+
+    /// Whether the target of applying this patch (the new file, or the old one
+    /// when `reverse`) ends with a newline, if the patch records it.
+    ///
+    /// Returns `None` when neither side carries a `\ No newline at end of file`
+    /// marker, in which case the trailing newline state of the input should be kept.
+    pub(crate) fn newline_at_eof(&self, reverse: bool) -> Option<bool> {
+        if !self.old_missing_newline && !self.new_missing_newline {
+            return None;
+        }
+        Some(if reverse {
+            !self.old_missing_newline
+        } else {
+            !self.new_missing_newline
+        })
+    }
+
+    /// Returns the pairs `(i, j)` of a chunk of this patch and a chunk of `other`,
+    /// both against the same original, whose old-side line ranges intersect,
+    /// context included. An empty range (a pure insertion) intersects a range that
+    /// it falls strictly inside of, or another insertion at the same line.
+    ///
+    /// No such pairs means the patches change separate regions of the file.
+    pub fn conflicts_with(&self, other: &Patch) -> Vec<(usize, usize)> {
+        let intersects = |a: &Chunk, b: &Chunk| {
+            let (a_end, b_end) = (a.old_start + a.old_lines, b.old_start + b.old_lines);
+            match (a.old_lines, b.old_lines) {
+                (0, 0) => a.old_start == b.old_start,
+                (0, _) => b.old_start < a.old_start && a.old_start < b_end,
+                (_, 0) => a.old_start < b.old_start && b.old_start < a_end,
+                _ => a.old_start < b_end && b.old_start < a_end,
+            }
+        };
+        let mut conflicts = Vec::new();
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            for (j, other_chunk) in other.chunks.iter().enumerate() {
+                if intersects(chunk, other_chunk) {
+                    conflicts.push((i, j));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Returns the chunk that removes (or replaces) the 0-based line `old_line` of
+    /// the original file, or `None` if the patch keeps that line as it is.
+    pub fn touches_line(&self, old_line: usize) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| {
+            (chunk.old_start..chunk.old_start + chunk.old_lines).contains(&old_line)
+                && chunk
+                    .operations
+                    .iter()
+                    .filter(|op| !op.is_add())
+                    .nth(old_line - chunk.old_start)
+                    .is_some_and(Operation::is_remove)
+        })
+    }
+
+    /// Merges chunks whose old-side ranges touch or overlap into a single chunk.
+    ///
+    /// Overlapping lines must be context in both chunks; they are kept once and
+    /// `old_lines`/`new_lines` are recomputed from the merged operations. Chunks
+    /// that overlap on added or removed lines are left as they are.
+    pub fn coalesce(&mut self) {
+        let mut merged: Vec<Chunk> = Vec::with_capacity(self.chunks.len());
+        for chunk in self.chunks.drain(..) {
+            if let Some(prev) = merged.last_mut() {
+                let prev_end = prev.old_start + prev.old_lines;
+                if chunk.old_start <= prev_end {
+                    let overlap = prev_end - chunk.old_start;
+                    let is_context = |op: &Operation| matches!(op, Operation::Context(_));
+                    let prev_tail_is_context = prev.operations.len() >= overlap
+                        && prev.operations[prev.operations.len() - overlap..]
+                            .iter()
+                            .all(is_context);
+                    let head_is_context = chunk.operations.len() >= overlap
+                        && chunk.operations[..overlap].iter().all(is_context);
+                    if prev_tail_is_context && head_is_context {
+                        prev.operations
+                            .extend(chunk.operations.into_iter().skip(overlap));
+                        (prev.old_lines, prev.new_lines) = count_lines(&prev.operations);
+                        continue;
+                    }
+                }
+            }
+            merged.push(chunk);
+        }
+        self.chunks = merged;
+    }
+
+    /// Rewrites the patch into a canonical form, so that patches making the same
+    /// changes compare equal whatever produced them.
+    ///
+    /// Chunks are sorted by `old_start` and coalesced, then split again with at
+    /// most `context_lines` of context around their changes, as with
+    /// [`Chunk::split`]. Context a chunk does not record cannot be added back, so
+    /// compare patches canonicalized with a size no larger than their smallest
+    /// context. Within each run of changes, removed lines are put before added
+    /// lines, and section headers are dropped.
+    pub fn canonicalize(&mut self, context_lines: usize) {
+        let end = |chunks: &[Chunk]| {
+            chunks.last().map(|chunk| {
+                (
+                    chunk.old_start + chunk.old_lines,
+                    chunk.new_start + chunk.new_lines,
+                )
+            })
+        };
+        self.chunks.sort_by_key(|chunk| chunk.old_start);
+        self.coalesce();
+        let old_end = end(&self.chunks);
+        self.chunks = self
+            .chunks
+            .iter()
+            .flat_map(|chunk| chunk.split(context_lines))
+            .collect();
+        if end(&self.chunks) != old_end {
+            // The unchanged last line of the file is no longer part of the patch
+            self.old_missing_newline = false;
+            self.new_missing_newline = false;
+        }
+
+        for chunk in &mut self.chunks {
+            chunk.section_header = None;
+            for run in chunk
+                .operations
+                .chunk_by_mut(|a, b| a.is_change() == b.is_change())
+            {
+                run.sort_by_key(Operation::is_add);
+            }
+        }
+    }
+
+    /// Checks the internal consistency of the patch.
+    ///
+    /// Verifies that every chunk's `old_lines`/`new_lines` match its operations
+    /// (`Remove` + `Context` and `Add` + `Context` respectively), and that chunk
+    /// ranges are monotonically increasing and non-overlapping on both sides.
+    /// Returns an `Error::InvalidPatchFormat` describing the first violation.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            let (old_count, new_count) = count_lines(&chunk.operations);
+            if old_count != chunk.old_lines || new_count != chunk.new_lines {
+                return Err(Error::InvalidPatchFormat(format!(
+                    "Chunk {} line count mismatch: header declares (-{}, +{}), operations contain (-{}, +{})",
+                    i + 1,
+                    chunk.old_lines,
+                    chunk.new_lines,
+                    old_count,
+                    new_count
+                )));
+            }
+        }
+
+        for (i, pair) in self.chunks.windows(2).enumerate() {
+            let (prev, next) = (&pair[0], &pair[1]);
+            if prev.old_start + prev.old_lines > next.old_start {
+                return Err(Error::InvalidPatchFormat(format!(
+                    "Chunk {} (old lines {}-{}) overlaps or precedes chunk {} starting at old line {}",
+                    i + 2,
+                    next.old_start + 1,
+                    next.old_start + next.old_lines,
+                    i + 1,
+                    prev.old_start + 1
+                )));
+            }
+            if prev.new_start + prev.new_lines > next.new_start {
+                return Err(Error::InvalidPatchFormat(format!(
+                    "Chunk {} (new lines {}-{}) overlaps or precedes chunk {} starting at new line {}",
+                    i + 2,
+                    next.new_start + 1,
+                    next.new_start + next.new_lines,
+                    i + 1,
+                    prev.new_start + 1
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse a patch from a reader, e.g. `io::stdin()`.
+    pub fn parse_from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut content = String::new();
+        reader.read_to_string(&mut content)?;
+        Self::parse(&content)
+    }
+
     /// Parse a patch from a string following the unified diff format.
     pub fn parse(content: &str) -> Result<Self, Error> {
+        Self::parse_with_limits(content, ParseLimits::default())
+    }
+
+    /// Parse a patch like [`parse`](Self::parse), rejecting input that exceeds
+    /// `limits` with an `Error::InvalidPatchFormat`. Use this for untrusted patches.
+    pub fn parse_with_limits(content: &str, limits: ParseLimits) -> Result<Self, Error> {
+        Self::parse_with(content, limits, false)
+    }
+
+    /// Parse a patch like [`parse`](Self::parse), but take the line counts of a
+    /// chunk from its operations when its header has a count that cannot be
+    /// parsed or does not match them, e.g. for patches edited by hand or written
+    /// by imperfect generators. A range starting at line 0 may also have lines.
+    pub fn parse_lenient(content: &str) -> Result<Self, Error> {
+        Self::parse_with(content, ParseLimits::default(), true)
+    }
+
+    fn parse_with(content: &str, limits: ParseLimits, lenient: bool) -> Result<Self, Error> {
         let lines: Vec<&str> = content.lines().collect();
         let mut line_iter = lines.iter().peekable();
         let mut current_line_num = 0;
@@ -88,6 +914,16 @@ impl Patch {
         let mut preamble: Option<String> = None;
         let mut old_file: Option<String> = None;
         let mut new_file: Option<String> = None;
+        let mut old_prefix = "a/".to_string();
+        let mut new_prefix = "b/".to_string();
+        let mut old_timestamp: Option<String> = None;
+        let mut new_timestamp: Option<String> = None;
+        let mut copy_from: Option<String> = None;
+        let mut copy_to: Option<String> = None;
+        let mut rename_from: Option<String> = None;
+        let mut rename_to: Option<String> = None;
+        let mut similarity: Option<u8> = None;
+        let mut extended_headers = Vec::new();
 
         while let Some(line) = line_iter.peek() {
             current_line_num += 1;
@@ -108,7 +944,10 @@ impl Patch {
                         current_line_num
                     )));
                 }
-                old_file = Some(parse_file_header_line(line, "---")?);
+                let header = parse_file_header_line(line, "---")?;
+                old_prefix = header.prefix;
+                old_timestamp = header.timestamp;
+                old_file = Some(header.path);
                 line_iter.next(); // Consume the old file header line
             } else if line.starts_with("+++ ") {
                 if new_file.is_some() {
@@ -123,22 +962,62 @@ impl Patch {
                         current_line_num
                     )));
                 }
-                new_file = Some(parse_file_header_line(line, "+++")?);
+                let header = parse_file_header_line(line, "+++")?;
+                new_prefix = header.prefix;
+                new_timestamp = header.timestamp;
+                new_file = Some(header.path);
                 line_iter.next(); // Consume the new file header line
                 break; // Headers found, move to parsing chunks
+            } else if let Some(path) = line.strip_prefix("copy from ") {
+                copy_from = Some(path.to_string());
+                line_iter.next();
+            } else if let Some(path) = line.strip_prefix("copy to ") {
+                copy_to = Some(path.to_string());
+                line_iter.next();
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                rename_from = Some(path.to_string());
+                line_iter.next();
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                rename_to = Some(path.to_string());
+                line_iter.next();
+            } else if let Some(value) = line.strip_prefix("similarity index ") {
+                similarity = value.trim_end_matches('%').parse().ok();
+                line_iter.next();
             } else {
-                // Skip other potential header lines like "index", "mode", etc.
+                // Keep other git extended headers like "index" and "old mode";
+                // anything before the "diff" line (e.g. a commit message) is skipped
+                if preamble.is_some() {
+                    extended_headers.push(line.to_string());
+                }
                 line_iter.next();
             }
         }
 
+        // A copy or rename without content changes has no '---'/'+++' headers, only the
+        // paths; a mode change only has them on the "diff --git" line
+        let mode_change_paths = extended_headers
+            .iter()
+            .any(|header| header.starts_with("new mode "))
+            .then(|| preamble.as_deref().and_then(git_diff_paths))
+            .flatten();
+        let (mode_old_file, mode_new_file) = mode_change_paths.unzip();
         let old_file = old_file
+            .or_else(|| copy_from.clone())
+            .or_else(|| rename_from.clone())
+            .or(mode_old_file)
             .ok_or_else(|| Error::InvalidPatchFormat("Missing '---' header".to_string()))?;
         let new_file = new_file
+            .or_else(|| copy_to.clone())
+            .or_else(|| rename_to.clone())
+            .or(mode_new_file)
             .ok_or_else(|| Error::InvalidPatchFormat("Missing '+++' header".to_string()))?;
 
         // --- Parse Chunks ---
         let mut chunks = Vec::new();
+        let mut combined_chunks = Vec::new();
+        let mut total_operations = 0;
+        let mut old_missing_newline = false;
+        let mut new_missing_newline = false;
         while let Some(line) = line_iter.peek() {
             let line_content = line.trim_end();
             if line_content.is_empty() {
@@ -148,11 +1027,71 @@ impl Patch {
                 continue;
             }
 
-            if line_content.starts_with("@@ ") {
+            if line_content.starts_with("@@@") {
+                line_iter.next();
+                current_line_num += 1;
+                let mut chunk = parse_combined_chunk_header(line_content)?;
+                if combined_chunks.len() >= limits.max_chunks {
+                    return Err(Error::InvalidPatchFormat(format!(
+                        "Patch has more than {} chunks",
+                        limits.max_chunks
+                    )));
+                }
+                let parents = chunk.parent_ranges.len();
+                while let Some(line) = line_iter.peek() {
+                    if line.starts_with("@@") {
+                        break;
+                    }
+                    let line = **line;
+                    line_iter.next();
+                    current_line_num += 1;
+                    if line.is_empty() || line.starts_with('\\') {
+                        continue;
+                    }
+                    let split = line
+                        .char_indices()
+                        .nth(parents)
+                        .map_or(line.len(), |(index, _)| index);
+                    let (markers, content) = line.split_at(split);
+                    if markers.chars().count() < parents
+                        || !markers.chars().all(|c| matches!(c, ' ' | '+' | '-'))
+                    {
+                        return Err(Error::InvalidPatchFormat(format!(
+                            "Line {}: Line without {} combined diff markers found in chunk body: \"{}\"",
+                            current_line_num, parents, line
+                        )));
+                    }
+                    chunk.lines.push(CombinedLine {
+                        markers: markers.to_string(),
+                        content: content.to_string(),
+                    });
+                    if total_operations + chunk.lines.len() > limits.max_operations {
+                        return Err(Error::InvalidPatchFormat(format!(
+                            "Patch has more than {} operations",
+                            limits.max_operations
+                        )));
+                    }
+                }
+                total_operations += chunk.lines.len();
+                check_combined_counts(&chunk, line_content)?;
+                combined_chunks.push(chunk);
+            } else if line_content.starts_with("@@ ") {
                 line_iter.next(); // Consume chunk header line
                 current_line_num += 1;
-                let (old_start, old_lines, new_start, new_lines) =
-                    parse_chunk_header(line_content)?;
+                let [(old_start, old_lines), (new_start, new_lines)] =
+                    parse_chunk_ranges(line_content, lenient)?;
+                if chunks.len() >= limits.max_chunks {
+                    return Err(Error::InvalidPatchFormat(format!(
+                        "Patch has more than {} chunks",
+                        limits.max_chunks
+                    )));
+                }
+                if old_lines.max(new_lines).unwrap_or(0) > limits.max_chunk_lines {
+                    return Err(Error::InvalidPatchFormat(format!(
+                        "Chunk header declares more than {} lines: {}",
+                        limits.max_chunk_lines, line_content
+                    )));
+                }
 
                 let mut operations = Vec::new();
                 let mut actual_old_lines = 0;
@@ -164,7 +1103,8 @@ impl Patch {
                         break; // Stop reading for this chunk
                     }
 
-                    let op_line = op_line_peek.trim_end();
+                    // Keep trailing whitespace, it is part of the line content
+                    let op_line = **op_line_peek;
                     line_iter.next(); // Consume the line
                     current_line_num += 1;
 
@@ -179,9 +1119,20 @@ impl Patch {
                         operations.push(Operation::Context(content.to_string()));
                         actual_old_lines += 1;
                         actual_new_lines += 1;
-                    } else if op_line == "\\ No newline at end of file" || op_line.is_empty() {
-                        // Ignore NOEOL marker and skip truly empty lines within chunk body
-                        continue; // Ignore this marker
+                    } else if op_line.starts_with('\\') {
+                        // No-newline marker (possibly localized), applies to the preceding line
+                        match operations.last() {
+                            Some(Operation::Remove(_)) => old_missing_newline = true,
+                            Some(Operation::Add(_)) => new_missing_newline = true,
+                            Some(Operation::Context(_)) => {
+                                old_missing_newline = true;
+                                new_missing_newline = true;
+                            }
+                            None => {}
+                        }
+                    } else if op_line.is_empty() {
+                        // Skip truly empty lines within chunk body
+                        continue;
                     } else {
                         // Strict: No prefix is an error
                         return Err(Error::InvalidPatchFormat(format!(
@@ -189,22 +1140,36 @@ impl Patch {
                             current_line_num, op_line
                         )));
                     }
+                    if total_operations + operations.len() > limits.max_operations {
+                        return Err(Error::InvalidPatchFormat(format!(
+                            "Patch has more than {} operations",
+                            limits.max_operations
+                        )));
+                    }
                 }
+                total_operations += operations.len();
 
                 // Validate counts AFTER reading the whole chunk
-                if actual_old_lines != old_lines || actual_new_lines != new_lines {
+                let counts_match =
+                    old_lines == Some(actual_old_lines) && new_lines == Some(actual_new_lines);
+                if !counts_match && !lenient {
                     return Err(Error::InvalidPatchFormat(format!(
                         "Chunk line count mismatch: Header expected (-{}, +{}), Parsed content counts (-{}, +{}). Chunk Header: {}",
-                        old_lines, new_lines, actual_old_lines, actual_new_lines, line_content
+                        old_lines.unwrap_or_default(),
+                        new_lines.unwrap_or_default(),
+                        actual_old_lines,
+                        actual_new_lines,
+                        line_content
                     )));
                 }
 
                 chunks.push(Chunk {
-                    old_start,
-                    old_lines,
-                    new_start,
-                    new_lines,
+                    old_start: to_index(old_start, actual_old_lines),
+                    old_lines: actual_old_lines,
+                    new_start: to_index(new_start, actual_new_lines),
+                    new_lines: actual_new_lines,
                     operations,
+                    section_header: parse_section_header(line_content),
                 });
             } else {
                 // Line doesn't start with @@, and we are outside a chunk
@@ -220,14 +1185,115 @@ impl Patch {
             preamble,
             old_file,
             new_file,
+            copy_from,
+            copy_to,
+            rename_from,
+            rename_to,
+            similarity,
+            extended_headers,
+            old_prefix,
+            new_prefix,
+            old_timestamp,
+            new_timestamp,
+            old_missing_newline,
+            new_missing_newline,
             chunks,
+            combined_chunks,
+        })
+    }
+}
+
+/// Appends `line` to `json` as a JSON number, or `null` if there is none.
+fn push_json_number(json: &mut String, line: Option<usize>) {
+    match line {
+        Some(line) => {
+            let _ = write!(json, "{line}");
+        }
+        None => json.push_str("null"),
+    }
+}
+
+/// Appends `text` to `json` as a quoted JSON string.
+fn push_json_string(json: &mut String, text: &str) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Chains the [`line_map`](Patch::line_map)s of `patches`, applied one after
+/// the other to the same file, e.g. to follow an annotation through history.
+///
+/// The returned function maps a 0-based line of the original file to its line
+/// in the final version, or to `None` if one of the patches deletes it.
+pub fn compose_line_maps(patches: &[Patch]) -> impl Fn(usize) -> Option<usize> + use<> {
+    let maps: Vec<(Vec<Option<usize>>, isize)> = patches
+        .iter()
+        .map(|patch| {
+            let map = patch
+                .line_map()
+                .into_iter()
+                .map(|mapping| match mapping {
+                    LineMapping::Kept { new, .. } => Some(new),
+                    LineMapping::Deleted { .. } => None,
+                })
+                .collect();
+            // How far the lines after the last chunk move
+            let shift = patch
+                .chunks
+                .iter()
+                .map(|chunk| chunk.new_lines as isize - chunk.old_lines as isize)
+                .sum();
+            (map, shift)
         })
+        .collect();
+    move |line| {
+        maps.iter()
+            .try_fold(line, |line, (map, shift)| match map.get(line) {
+                Some(mapped) => *mapped,
+                None => Some(line.saturating_add_signed(*shift)),
+            })
     }
 }
 
+/// The parts of a `---` or `+++` header line.
+struct FileHeader {
+    /// The stripped `a/` or `b/` prefix, empty if none
+    prefix: String,
+    path: String,
+    /// Tab-separated timestamp following the path, if any
+    timestamp: Option<String>,
+}
+
+/// The old and new path of a `diff --git a/old b/new` line, without prefixes.
+/// Paths containing spaces are only split reliably when both are the same.
+pub(crate) fn git_diff_paths(line: &str) -> Option<(String, String)> {
+    let paths = line.strip_prefix("diff --git ")?.strip_prefix("a/")?;
+    // "x b/x": both halves are equally long
+    let half = paths.len().checked_sub(3).map(|len| len / 2)?;
+    if let Some((old, new)) = paths.split_at_checked(half)
+        && new.strip_prefix(" b/") == Some(old)
+    {
+        return Some((old.to_string(), old.to_string()));
+    }
+    let (old, new) = paths.split_once(" b/")?;
+    Some((old.to_string(), new.to_string()))
+}
+
 /// Parses the file path from a `---` or `+++` header line.
 /// Handles optional `a/` or `b/` prefixes and potential timestamp info.
-fn parse_file_header_line(line: &str, prefix: &str) -> Result<String, Error> {
+fn parse_file_header_line(line: &str, prefix: &str) -> Result<FileHeader, Error> {
     let content = line
         .strip_prefix(prefix)
         .ok_or_else(|| {
@@ -236,101 +1302,218 @@ fn parse_file_header_line(line: &str, prefix: &str) -> Result<String, Error> {
         .trim_start(); // Remove leading space after `---` or `+++`
 
     // Git format often includes a/ or b/
-    let path_part = content
-        .strip_prefix("a/")
-        .or_else(|| content.strip_prefix("b/"))
-        .unwrap_or(content);
+    let (path_prefix, path_part) = ["a/", "b/"]
+        .iter()
+        .find_map(|p| content.strip_prefix(p).map(|rest| (*p, rest)))
+        .unwrap_or(("", content));
 
     // Strip potential timestamp/mode info separated by tabs or multiple spaces
-    Ok(path_part
-        .split(['\t', ' '])
-        .next()
-        .unwrap_or("")
-        .to_string())
+    let path = path_part.split(['\t', ' ']).next().unwrap_or("");
+    let timestamp = path_part
+        .split_once('\t')
+        .map(|(_, ts)| ts.trim())
+        .filter(|ts| !ts.is_empty())
+        .map(str::to_string);
+    Ok(FileHeader {
+        prefix: path_prefix.to_string(),
+        path: path.to_string(),
+        timestamp,
+    })
+}
+
+/// Returns the text after the closing `@@` of a chunk header, e.g. the function
+/// name in `@@ -1,5 +1,6 @@ fn main()`, or `None` if there is none.
+fn parse_section_header(header: &str) -> Option<String> {
+    let (_, rest) = header.strip_prefix("@@ ")?.split_once(" @@")?;
+    let text = rest.strip_prefix(' ').unwrap_or(rest);
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Parses a chunk header like `@@ -1,5 +1,6 @@ fn main()` into the 0-based
+/// start and the line count of the old and the new range.
+pub(crate) fn parse_chunk_header(header: &str) -> Result<(usize, usize, usize, usize), Error> {
+    let [(old_start, old_lines), (new_start, new_lines)] = parse_chunk_ranges(header, false)?;
+    let (old_lines, new_lines) = (
+        old_lines.expect("strict ranges have a count"),
+        new_lines.expect("strict ranges have a count"),
+    );
+    Ok((
+        to_index(old_start, old_lines),
+        old_lines,
+        to_index(new_start, new_lines),
+        new_lines,
+    ))
 }
 
-/// Parse a chunk header with more flexibility to handle various Git diff formats
-/// Returns (old_start, old_lines, new_start, new_lines) - 0-based start index.
-fn parse_chunk_header(header: &str) -> Result<(usize, usize, usize, usize), Error> {
+/// Parses the old and new range of a chunk header, with 1-based starts. In
+/// `lenient` mode a count that cannot be parsed is `None`, and a range starting
+/// at line 0 may have lines.
+fn parse_chunk_ranges(header: &str, lenient: bool) -> Result<[(usize, Option<usize>); 2], Error> {
+    let invalid = |reason| Error::InvalidChunkHeader {
+        header: header.to_string(),
+        reason,
+    };
     // Example: @@ -1,5 +1,6 @@ optional context
     let parts: Vec<&str> = header.split(" @@").collect();
     if !parts[0].starts_with("@@ ") || parts.len() < 2 {
-        return Err(Error::InvalidChunkHeader {
-            header: header.to_string(),
-        });
+        return Err(invalid(ChunkHeaderProblem::Malformed));
     }
 
     let range_part = parts[0].strip_prefix("@@ ").unwrap().trim(); // "-1,5 +1,6"
     let range_parts: Vec<&str> = range_part.split_whitespace().collect();
-
-    if range_parts.len() != 2
-        || !range_parts[0].starts_with('-')
-        || !range_parts[1].starts_with('+')
-    {
-        return Err(Error::InvalidChunkHeader {
-            header: header.to_string(),
-        });
+    if range_parts.len() != 2 {
+        return Err(invalid(ChunkHeaderProblem::Malformed));
     }
+    let old_range = range_parts[0]
+        .strip_prefix('-')
+        .ok_or_else(|| invalid(ChunkHeaderProblem::MissingOldRange))?;
+    let new_range = range_parts[1]
+        .strip_prefix('+')
+        .ok_or_else(|| invalid(ChunkHeaderProblem::MissingNewRange))?;
+
+    Ok([
+        parse_range(old_range, "old", lenient).map_err(invalid)?,
+        parse_range(new_range, "new", lenient).map_err(invalid)?,
+    ])
+}
 
-    // Parse old range: "-1,5"
-    let old_range_str = range_parts[0].strip_prefix('-').unwrap();
-    let (old_start, old_lines) = parse_range(old_range_str, header)?;
+/// Adjusts a 1-based range start to 0-based indexing. An empty range names the
+/// line *after which* the change happens, which is already the 0-based start.
+fn to_index(start: usize, count: usize) -> usize {
+    if count == 0 {
+        start
+    } else {
+        start.saturating_sub(1)
+    }
+}
 
-    // Parse new range: "+1,6"
-    let new_range_str = range_parts[1].strip_prefix('+').unwrap();
-    let (new_start, new_lines) = parse_range(new_range_str, header)?;
+/// Parses a combined chunk header like `@@@ -1,2 -1,2 +1,3 @@@ fn main()` into
+/// an empty chunk, with one range per parent and one `@` more than parents.
+fn parse_combined_chunk_header(header: &str) -> Result<CombinedChunk, Error> {
+    let problem = |reason| Error::InvalidChunkHeader {
+        header: header.to_string(),
+        reason,
+    };
+    let invalid = || problem(ChunkHeaderProblem::Malformed);
+    let marker_len = header.chars().take_while(|&c| c == '@').count();
+    let marker = &header[..marker_len];
+    let (ranges, section) = header[marker_len..]
+        .strip_prefix(' ')
+        .and_then(|rest| rest.split_once(&format!(" {marker}")))
+        .ok_or_else(invalid)?;
+    let ranges: Vec<&str> = ranges.split_whitespace().collect();
+    let Some((new_range, parent_ranges)) = ranges.split_last() else {
+        return Err(invalid());
+    };
+    if marker_len < 3 || parent_ranges.len() != marker_len - 1 {
+        return Err(invalid());
+    }
 
-    // Adjust to 0-based indexing for start lines
-    Ok((
-        old_start.saturating_sub(1),
-        old_lines,
-        new_start.saturating_sub(1),
+    let parent_ranges = parent_ranges
+        .iter()
+        .map(|range| {
+            let range = range
+                .strip_prefix('-')
+                .ok_or_else(|| problem(ChunkHeaderProblem::MissingOldRange))?;
+            let (start, lines) = parse_strict_range(range, "parent").map_err(problem)?;
+            Ok((to_index(start, lines), lines))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let new_range = new_range
+        .strip_prefix('+')
+        .ok_or_else(|| problem(ChunkHeaderProblem::MissingNewRange))?;
+    let (new_start, new_lines) = parse_strict_range(new_range, "new").map_err(problem)?;
+    let section = section.strip_prefix(' ').unwrap_or(section);
+    Ok(CombinedChunk {
+        parent_ranges,
+        new_start: to_index(new_start, new_lines),
         new_lines,
-    ))
+        lines: Vec::new(),
+        section_header: (!section.is_empty()).then(|| section.to_string()),
+    })
 }
 
-/// Parses a range string like "1,5" or "1" into (start, count).
-fn parse_range(range_str: &str, header: &str) -> Result<(usize, usize), Error> {
-    let parts: Vec<&str> = range_str.splitn(2, ',').collect();
-    let start_str = parts[0];
-    let start = parse_number(start_str, "range start")?;
-
-    let count = match parts.len() {
-        1 => {
-            // Format like "-1" or "+1" means 1 line affected unless start is 0
-            if start == 0 { 0 } else { 1 }
+/// Checks that the lines of a combined chunk match the counts in its header: a
+/// parent has the lines marked `-` for it and, among the lines without any `-`,
+/// those not marked `+` for it. The result has every line without a `-`.
+fn check_combined_counts(chunk: &CombinedChunk, header: &str) -> Result<(), Error> {
+    let mismatch = || {
+        Error::InvalidPatchFormat(format!(
+            "Combined chunk line count mismatch. Chunk Header: {}",
+            header
+        ))
+    };
+    for (parent, &(_, lines)) in chunk.parent_ranges.iter().enumerate() {
+        let count = chunk
+            .lines
+            .iter()
+            .filter(|line| match line.markers.as_bytes()[parent] {
+                b'-' => true,
+                b' ' => !line.markers.contains('-'),
+                _ => false,
+            })
+            .count();
+        if count != lines {
+            return Err(mismatch());
         }
-        2 => {
-            // Format like "-1,5" or "+1,6"
-            let count_str = parts[1];
-            parse_number(count_str, "range count")?
+    }
+    let new_count = chunk
+        .lines
+        .iter()
+        .filter(|line| !line.markers.contains('-'))
+        .count();
+    if new_count != chunk.new_lines {
+        return Err(mismatch());
+    }
+    Ok(())
+}
+
+/// Parses a range like `1,5` of the `side` ("old", "new" or "parent") of a
+/// chunk header into its 1-based start and line count. A range without a count
+/// has one line, or none if it starts at 0.
+///
+/// In `lenient` mode a count that cannot be parsed is `None` instead of an
+/// error, and a range starting at line 0 may have lines.
+fn parse_range(
+    range_str: &str,
+    side: &str,
+    lenient: bool,
+) -> Result<(usize, Option<usize>), ChunkHeaderProblem> {
+    let number = |value: &str, what: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| ChunkHeaderProblem::InvalidNumber {
+                value: value.to_string(),
+                field: format!("{side} {what}"),
+            })
+    };
+    let (start, count) = match range_str.split_once(',') {
+        Some((start, count)) => (number(start, "start")?, count),
+        None => {
+            let start = number(range_str, "start")?;
+            return Ok((start, Some(usize::from(start != 0))));
         }
-        _ => unreachable!(), // splitn(2,...) ensures max 2 parts
+    };
+    let count = match number(count, "count") {
+        Ok(count) => Some(count),
+        Err(_) if lenient => None,
+        Err(problem) => return Err(problem),
     };
 
-    if start == 0 && count > 0 { // e.g. --- /dev/null, @@ -0,0 +1,5 @@
-        // Allow count > 0 only if start is also > 0, or if start is 0 (empty file case)
-        // This condition is slightly redundant with the start==0 check in match arms, but provides clarity
-    } else if start > 0 && count == 0 {
-        return Err(Error::InvalidChunkHeader {
-            header: header.to_string(),
+    // A zero count with a non-zero start is a pure insertion (or deletion) after
+    // line `start`, as produced with `-U0`; `@@ -0,0 +1,5 @@` is the empty file case.
+    if start == 0 && count.is_some_and(|count| count > 0) && !lenient {
+        return Err(ChunkHeaderProblem::ZeroStartWithLines {
+            range: range_str.to_string(),
         });
-        // If start > 0, count must be at least 1
-        //        return Err(Error::InvalidPatchFormat(format!(
-        //            "Invalid range format '{}': count cannot be 0 if start is non-zero", range_str
-        //        )));
     }
-
     Ok((start, count))
 }
 
-/// Parse a number from a string with better error handling
-fn parse_number(s: &str, field_name: &str) -> Result<usize, Error> {
-    s.parse::<usize>().map_err(|e| Error::InvalidNumberFormat {
-        value: s.to_string(),
-        field: field_name.to_string(),
-        source: e,
-    })
+/// Parses a range like [`parse_range`], requiring a valid count.
+fn parse_strict_range(range_str: &str, side: &str) -> Result<(usize, usize), ChunkHeaderProblem> {
+    let (start, count) = parse_range(range_str, side, false)?;
+    Ok((start, count.expect("strict ranges have a count")))
 }
 
 impl fmt::Display for Patch {
@@ -338,24 +1521,265 @@ impl fmt::Display for Patch {
         if let Some(preamble) = &self.preamble {
             writeln!(f, "{}", preamble)?;
         } else {
-            writeln!(f, "diff --git a/{} b/{}", self.old_file, self.new_file)?;
+            writeln!(
+                f,
+                "diff --git {}{} {}{}",
+                self.old_prefix, self.old_file, self.new_prefix, self.new_file
+            )?;
         }
-        // Always use the a/ b/ prefixes for consistency, even if not present in parsed paths
-        writeln!(f, "--- a/{}", self.old_file)?;
-        writeln!(f, "+++ b/{}", self.new_file)?;
+        self.write_extended_headers(f)?;
+        self.write_body(f, true)
+    }
+}
 
-        for chunk in &self.chunks {
-            write!(f, "{}", chunk)?; // Chunk::fmt already includes newline
+impl FromStr for Patch {
+    type Err = Error;
+
+    /// Parses a patch like [`Patch::parse`], e.g. with `text.parse::<Patch>()`.
+    fn from_str(content: &str) -> Result<Self, Error> {
+        Patch::parse(content)
+    }
+}
+
+impl TryFrom<&str> for Patch {
+    type Error = Error;
+
+    fn try_from(content: &str) -> Result<Self, Error> {
+        Patch::parse(content)
+    }
+}
+
+impl Patch {
+    /// Writes the patch as [`Display`](fmt::Display) formats it to `writer`, piece
+    /// by piece, without building the whole text in memory first. Wrap files and
+    /// sockets in a `BufWriter`.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+
+    /// Formats the patch the way `git diff` does, so that `git apply` accepts it.
+    ///
+    /// The `diff --git` line is always synthesized (any preamble is ignored),
+    /// paths use the `a/` and `b/` prefixes, timestamps are dropped, and a
+    /// placeholder `index 0000000..0000000` line is added. Created and deleted
+    /// files get `new file mode`/`deleted file mode` lines.
+    pub fn to_git_format(&self) -> String {
+        let mut out = String::new();
+        self.write_git_format(&mut out)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    pub(crate) fn write_git_format(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        const MODE: &str = "100644";
+        let is_new = self.old_file == "/dev/null";
+        let is_deleted = self.new_file == "/dev/null";
+        let old_path = if is_new {
+            &self.new_file
+        } else {
+            &self.old_file
+        };
+        let new_path = if is_deleted {
+            &self.old_file
+        } else {
+            &self.new_file
+        };
+
+        writeln!(f, "diff --git a/{} b/{}", old_path, new_path)?;
+        let has_header = |prefix: &str| {
+            self.extended_headers
+                .iter()
+                .any(|header| header.starts_with(prefix))
+        };
+        if is_new && !has_header("new file mode ") {
+            writeln!(f, "new file mode {}", MODE)?;
+        } else if is_deleted && !has_header("deleted file mode ") {
+            writeln!(f, "deleted file mode {}", MODE)?;
+        }
+        self.write_extended_headers(f)?;
+        if !self.chunks.is_empty() && !has_header("index ") {
+            if is_new || is_deleted {
+                writeln!(f, "index 0000000..0000000")?;
+            } else {
+                writeln!(f, "index 0000000..0000000 {}", MODE)?;
+            }
+        }
+        Patch {
+            old_prefix: "a/".to_string(),
+            new_prefix: "b/".to_string(),
+            ..self.clone()
         }
+        .write_body(f, false)
+    }
 
+    /// Writes the git extended header: mode lines, then the similarity, copy and
+    /// rename lines, then any other preserved lines such as `index`.
+    fn write_extended_headers(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let (modes, others): (Vec<&String>, Vec<&String>) =
+            self.extended_headers.iter().partition(|header| {
+                [
+                    "old mode ",
+                    "new mode ",
+                    "new file mode ",
+                    "deleted file mode ",
+                ]
+                .iter()
+                .any(|prefix| header.starts_with(prefix))
+            });
+        for header in modes {
+            writeln!(f, "{}", header)?;
+        }
+        if let Some(similarity) = self.similarity {
+            writeln!(f, "similarity index {}%", similarity)?;
+        }
+        if let (Some(from), Some(to)) = (&self.copy_from, &self.copy_to) {
+            writeln!(f, "copy from {}", from)?;
+            writeln!(f, "copy to {}", to)?;
+        }
+        if let (Some(from), Some(to)) = (&self.rename_from, &self.rename_to) {
+            writeln!(f, "rename from {}", from)?;
+            writeln!(f, "rename to {}", to)?;
+        }
+        for header in others {
+            writeln!(f, "{}", header)?;
+        }
         Ok(())
     }
+
+    /// Writes the `---`/`+++` file headers and the chunks.
+    fn write_body(&self, f: &mut impl fmt::Write, timestamps: bool) -> fmt::Result {
+        if self.is_metadata_only() {
+            // Like git, a pure copy, rename or mode change carries no file headers
+            return Ok(());
+        }
+        let (old_timestamp, new_timestamp) = if timestamps {
+            (self.old_timestamp.as_deref(), self.new_timestamp.as_deref())
+        } else {
+            (None, None)
+        };
+        write!(f, "--- {}", header_path(&self.old_prefix, &self.old_file))?;
+        write_timestamp(f, old_timestamp)?;
+        write!(f, "+++ {}", header_path(&self.new_prefix, &self.new_file))?;
+        write_timestamp(f, new_timestamp)?;
+
+        if let Some((last, rest)) = self.chunks.split_last() {
+            for chunk in rest {
+                write!(f, "{}", chunk)?; // Chunk::fmt already includes newline
+            }
+            // The no-newline markers can only refer to the end of the file
+            last.write_with_markers(f, self.old_missing_newline, self.new_missing_newline)?;
+        }
+        for chunk in &self.combined_chunks {
+            write!(f, "{}", chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes a record split on a delimiter other than `\n` so it fits on one
+/// patch line: backslashes are doubled, and line feeds and carriage returns
+/// are written as `\n` and `\r`.
+pub(crate) fn escape_record(record: &[u8]) -> Cow<'_, [u8]> {
+    if !record.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r')) {
+        return Cow::Borrowed(record);
+    }
+    let mut escaped = Vec::with_capacity(record.len() + 2);
+    for &byte in record {
+        match byte {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            _ => escaped.push(byte),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Reverses [`escape_record`]. Other backslash sequences are kept as they are.
+pub(crate) fn unescape_record(record: &[u8]) -> Cow<'_, [u8]> {
+    if !record.contains(&b'\\') {
+        return Cow::Borrowed(record);
+    }
+    let mut unescaped = Vec::with_capacity(record.len());
+    let mut bytes = record.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        let escaped = match bytes.peek() {
+            Some(b'\\') if byte == b'\\' => b'\\',
+            Some(b'n') if byte == b'\\' => b'\n',
+            Some(b'r') if byte == b'\\' => b'\r',
+            _ => {
+                unescaped.push(byte);
+                continue;
+            }
+        };
+        bytes.next();
+        unescaped.push(escaped);
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Joins a header prefix and path; `/dev/null` is never prefixed.
+fn header_path(prefix: &str, path: &str) -> String {
+    if path == "/dev/null" {
+        path.to_string()
+    } else {
+        format!("{}{}", prefix, path)
+    }
+}
+
+/// Terminates a file header line, appending the tab-separated timestamp if present.
+fn write_timestamp(f: &mut impl fmt::Write, timestamp: Option<&str>) -> fmt::Result {
+    match timestamp {
+        Some(ts) => writeln!(f, "\t{}", ts),
+        None => writeln!(f),
+    }
+}
+
+/// Formats a time the way GNU `diff -u` does, in UTC:
+/// `2023-01-01 10:00:00.000000000 +0000`.
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    };
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09} +0000",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        nanos
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Operation; // Explicit import needed if `use super::*` isn't used fully
+    use crate::{DiffAlgorithm, Differ, PatchAlgorithm, Patcher};
 
     #[test]
     fn test_parse_simple_patch() {
@@ -389,6 +1813,123 @@ mod tests {
         assert_eq!(chunk.operations[2], Operation::Add("line2 modified".into()));
         assert_eq!(chunk.operations[3], Operation::Context("line3".into()));
         assert_eq!(chunk.operations[4], Operation::Context("line4".into()));
+
+        assert_eq!(patch_str.parse::<Patch>().unwrap(), patch);
+        assert_eq!(Patch::try_from(patch_str).unwrap(), patch);
+        assert!("@@ -1 +1 @@\n-a\n".parse::<Patch>().is_err());
+    }
+
+    #[test]
+    fn test_parse_with_limits() {
+        let text = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ a
+-b
++B
+@@ -10,1 +10,1 @@
+-x
++y
+";
+        let limits = ParseLimits {
+            max_operations: 5,
+            max_chunks: 2,
+            max_chunk_lines: 2,
+        };
+        assert!(Patch::parse_with_limits(text, limits).is_ok());
+
+        for limits in [
+            ParseLimits {
+                max_operations: 4,
+                ..limits
+            },
+            ParseLimits {
+                max_chunks: 1,
+                ..limits
+            },
+            ParseLimits {
+                max_chunk_lines: 1,
+                ..limits
+            },
+        ] {
+            assert!(matches!(
+                Patch::parse_with_limits(text, limits),
+                Err(Error::InvalidPatchFormat(_))
+            ));
+        }
+
+        let huge = "--- a/f\n+++ b/f\n@@ -1,99999999 +1,99999999 @@\n a\n";
+        assert!(Patch::parse_with_limits(huge, limits).is_err());
+    }
+
+    #[test]
+    fn test_operation_accessors() {
+        let add = Operation::Add("new".to_string());
+        let remove = Operation::Remove("old".to_string());
+        let context = Operation::Context("same".to_string());
+
+        assert!(add.is_add() && add.is_change() && !add.is_context());
+        assert!(remove.is_remove() && remove.is_change());
+        assert!(context.is_context() && !context.is_change());
+        assert_eq!(
+            remove.map_line(|line| line.to_uppercase()),
+            Operation::Remove("OLD".to_string())
+        );
+        assert_eq!(add.into_line(), "new");
+    }
+
+    #[test]
+    fn test_to_git_format() {
+        let patch = Differ::new("a\nb\n", "a\nB\n")
+            .file_names("src/lib.rs", "src/lib.rs")
+            .preamble(Some("diff -u old new".to_string()))
+            .path_prefixes("", "")
+            .generate();
+        let text = patch.to_git_format();
+        assert!(text.starts_with(
+            "diff --git a/src/lib.rs b/src/lib.rs\nindex 0000000..0000000 100644\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ "
+        ));
+
+        let created = Differ::new("", "new\n")
+            .file_names("/dev/null", "new.txt")
+            .generate();
+        assert_eq!(
+            created.to_git_format(),
+            "diff --git a/new.txt b/new.txt\nnew file mode 100644\nindex 0000000..0000000\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+new\n"
+        );
+
+        let deleted = Differ::new("old\n", "")
+            .file_names("old.txt", "/dev/null")
+            .generate();
+        assert!(
+            deleted
+                .to_git_format()
+                .starts_with("diff --git a/old.txt b/old.txt\ndeleted file mode 100644\n")
+        );
+    }
+
+    #[test]
+    fn test_section_header_round_trip() {
+        let text = "\
+diff --git a/src/main.rs b/src/main.rs
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -10,2 +10,2 @@ fn main() {
+     let a = 1;
+-    let b = 2;
++    let b = 3;
+@@ -20 +20 @@
+-x
++y
+";
+        let patch = Patch::parse(text).unwrap();
+        assert_eq!(
+            patch.chunks[0].section_header.as_deref(),
+            Some("fn main() {")
+        );
+        assert_eq!(patch.chunks[1].section_header, None);
+        assert_eq!(patch.to_string(), text);
     }
 
     #[test]
@@ -417,6 +1958,52 @@ index 12345..67890 100644
         assert_eq!(patch.chunks[0].operations.len(), 2);
     }
 
+    #[test]
+    fn test_extended_headers_round_trip() {
+        let patch_str = "\
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+index 12345..67890
+--- a/run.sh
++++ b/run.sh
+@@ -1 +1 @@
+-echo hello
++echo world
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert_eq!(
+            patch.extended_headers,
+            vec!["old mode 100644", "new mode 100755", "index 12345..67890"]
+        );
+        assert_eq!(patch.to_string(), patch_str);
+        // The preserved index line replaces the placeholder
+        assert_eq!(patch.to_git_format(), patch_str);
+
+        let bare = Patch::parse("--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n").unwrap();
+        assert!(bare.extended_headers.is_empty());
+    }
+
+    #[test]
+    fn test_round_trip_lines_that_look_like_markers() {
+        let cases = [
+            ("+foo\n-bar\n@@ baz\n", "+foo\n-bar!\n@@ baz\n@@ -1 +1 @@\n"),
+            // Rendered as "--- x" and "+++ y" inside the chunk
+            ("-- x\n++ y\nkeep\n", "++ y\nkeep\n-- z\n"),
+            // Blank and trailing-space context lines keep their content
+            ("a \n\nb\n", "a \n\nc\n"),
+        ];
+        for (old, new) in cases {
+            let text = Differ::new(old, new).generate().to_string();
+            let patch = Patch::parse(&text).unwrap();
+            assert_eq!(
+                Patcher::new(patch).apply(old, false).unwrap(),
+                new,
+                "{text}"
+            );
+        }
+    }
+
     #[test]
     fn test_parse_new_file() {
         let patch_str = "\
@@ -571,7 +2158,41 @@ index 12345..67890 100644
 +b
 ";
         let result = Patch::parse(patch_str);
-        assert!(matches!(result, Err(Error::InvalidChunkHeader { .. })));
+        assert!(matches!(
+            result,
+            Err(Error::InvalidChunkHeader {
+                reason: ChunkHeaderProblem::Malformed,
+                ..
+            })
+        ));
+
+        let reason = |header: &str| match Patch::parse(&format!(
+            "--- a/file.txt\n+++ b/file.txt\n{header}\n-a\n+b\n"
+        )) {
+            Err(Error::InvalidChunkHeader { reason, .. }) => reason,
+            other => panic!("expected InvalidChunkHeader, got {other:?}"),
+        };
+        assert_eq!(
+            reason("@@ 1,1 +1,1 @@"),
+            ChunkHeaderProblem::MissingOldRange
+        );
+        assert_eq!(
+            reason("@@ -1,1 1,1 @@"),
+            ChunkHeaderProblem::MissingNewRange
+        );
+        assert_eq!(
+            reason("@@ -1,x +1,1 @@"),
+            ChunkHeaderProblem::InvalidNumber {
+                value: "x".to_string(),
+                field: "old count".to_string(),
+            }
+        );
+        assert_eq!(
+            reason("@@ -0,1 +1,1 @@"),
+            ChunkHeaderProblem::ZeroStartWithLines {
+                range: "0,1".to_string(),
+            }
+        );
 
         let patch_str_2 = "\
 --- a/file.txt
@@ -637,12 +2258,37 @@ index 12345..67890 100644
         );
     }
 
+    #[test]
+    fn test_parse_lenient() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,x +2,5 @@
+ line2
+-line3
++line3 modified
+ line4
+";
+        assert!(Patch::parse(patch_str).is_err());
+
+        let patch = Patch::parse_lenient(patch_str).unwrap();
+        let chunk = &patch.chunks[0];
+        assert_eq!((chunk.old_start, chunk.old_lines), (1, 3));
+        assert_eq!((chunk.new_start, chunk.new_lines), (1, 3));
+        assert_eq!(
+            Patcher::new(patch)
+                .apply("line1\nline2\nline3\nline4\n", false)
+                .unwrap(),
+            "line1\nline2\nline3 modified\nline4\n"
+        );
+    }
+
     #[test]
     fn test_parse_line_without_prefix_in_chunk() {
         let patch_str = "\
 --- a/file.txt
 +++ b/file.txt
-@@ -0,0 +0,1 @@
+@@ -0,0 +1,1 @@
 invalid_line_without_prefix
 ";
         // Expect InvalidPatchFormat because strict prefix is required
@@ -652,6 +2298,123 @@ invalid_line_without_prefix
         );
     }
 
+    #[test]
+    fn test_parse_copy_headers() {
+        let patch_str = "\
+diff --git a/src/a.rs b/src/b.rs
+similarity index 90%
+copy from src/a.rs
+copy to src/b.rs
+--- a/src/a.rs
++++ b/src/b.rs
+@@ -1,2 +1,2 @@
+ fn a() {}
+-fn helper() {}
++fn helper_b() {}
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert!(patch.is_copy());
+        assert_eq!(patch.copy_from.as_deref(), Some("src/a.rs"));
+        assert_eq!(patch.copy_to.as_deref(), Some("src/b.rs"));
+        assert_eq!(patch.old_file, "src/a.rs");
+        assert_eq!(patch.new_file, "src/b.rs");
+        assert_eq!(patch.chunks.len(), 1);
+
+        // Display keeps the copy headers so the patch round-trips
+        let reparsed = Patch::parse(&patch.to_string()).unwrap();
+        assert_eq!(reparsed, patch);
+    }
+
+    #[test]
+    fn test_parse_pure_copy_without_file_headers() {
+        let patch_str = "\
+diff --git a/src/a.rs b/src/b.rs
+similarity index 100%
+copy from src/a.rs
+copy to src/b.rs
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert!(patch.is_copy());
+        assert_eq!(patch.old_file, "src/a.rs");
+        assert_eq!(patch.new_file, "src/b.rs");
+        assert!(patch.chunks.is_empty());
+        assert_eq!(patch.similarity, Some(100));
+        assert_eq!(patch.to_string(), patch_str);
+    }
+
+    #[test]
+    fn test_validate_accepts_parsed_patch() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+@@ -10,2 +10,3 @@
+ j
++k
+ l
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert!(patch.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_wrong_counts() {
+        let patch = Patch {
+            old_file: "file.txt".to_string(),
+            new_file: "file.txt".to_string(),
+            chunks: vec![Chunk {
+                old_start: 0,
+                old_lines: 1,
+                new_start: 0,
+                new_lines: 1,
+                operations: vec![Operation::Context("a".into()), Operation::Add("b".into())],
+                section_header: None,
+            }],
+            ..Default::default()
+        };
+        let err = patch.validate().unwrap_err();
+        assert!(
+            matches!(&err, Error::InvalidPatchFormat(s) if s.contains("Chunk 1 line count mismatch")),
+            "Unexpected error: {}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_overlapping_chunks() {
+        let chunk = |start: usize| Chunk {
+            old_start: start,
+            old_lines: 2,
+            new_start: start,
+            new_lines: 2,
+            operations: vec![
+                Operation::Context("a".into()),
+                Operation::Context("b".into()),
+            ],
+            section_header: None,
+        };
+        let patch = Patch {
+            old_file: "file.txt".to_string(),
+            new_file: "file.txt".to_string(),
+            chunks: vec![chunk(5), chunk(6)],
+            ..Default::default()
+        };
+        assert!(matches!(
+            patch.validate(),
+            Err(Error::InvalidPatchFormat(s)) if s.contains("overlaps")
+        ));
+
+        let unordered = Patch {
+            chunks: vec![chunk(10), chunk(0)],
+            ..patch
+        };
+        assert!(unordered.validate().is_err());
+    }
+
     #[test]
     fn test_display_patch() {
         let patch = Patch {
@@ -670,6 +2433,7 @@ invalid_line_without_prefix
                         Operation::Add("line2 mod".into()),
                         Operation::Add("line2.5".into()),
                     ],
+                    section_header: None,
                 },
                 Chunk {
                     old_start: 5,
@@ -677,8 +2441,10 @@ invalid_line_without_prefix
                     new_start: 7,
                     new_lines: 1,
                     operations: vec![Operation::Context("line6".into())],
+                    section_header: None,
                 },
             ],
+            ..Default::default()
         };
 
         let expected_str = "\
@@ -690,9 +2456,591 @@ diff -u a/old b/new
 -line2
 +line2 mod
 +line2.5
-@@ -6,1 +8,1 @@
+@@ -6 +8 @@
  line6
 ";
         assert_eq!(patch.to_string().trim(), expected_str.trim());
     }
+
+    #[test]
+    fn test_format_timestamp() {
+        use std::time::Duration;
+
+        let time = UNIX_EPOCH + Duration::new(1_672_567_200, 5);
+        assert_eq!(
+            format_timestamp(time),
+            "2023-01-01 10:00:00.000000005 +0000"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29 00:00:00.000000000 +0000"
+        );
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH - Duration::from_millis(500)),
+            "1969-12-31 23:59:59.500000000 +0000"
+        );
+    }
+
+    #[test]
+    fn test_parse_and_display_timestamps() {
+        let patch_str = "\
+--- a/file.txt\t2023-01-01 10:00:00.000000000 +0000
++++ b/file.txt\t2023-01-02 11:30:00.000000000 +0000
+@@ -1 +1 @@
+-old
++new
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert_eq!(patch.old_file, "file.txt");
+        assert_eq!(
+            patch.old_timestamp.as_deref(),
+            Some("2023-01-01 10:00:00.000000000 +0000")
+        );
+        assert_eq!(
+            patch.new_timestamp.as_deref(),
+            Some("2023-01-02 11:30:00.000000000 +0000")
+        );
+        let output = patch.to_string();
+        assert!(output.contains("--- a/file.txt\t2023-01-01 10:00:00.000000000 +0000\n"));
+        assert!(output.contains("+++ b/file.txt\t2023-01-02 11:30:00.000000000 +0000\n"));
+        let reparsed = Patch::parse(&output).unwrap();
+        assert_eq!(reparsed.old_timestamp, patch.old_timestamp);
+        assert_eq!(reparsed.new_timestamp, patch.new_timestamp);
+    }
+
+    #[test]
+    fn test_no_newline_markers_round_trip() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ line1
+-line2
+\\ No newline at end of file
++line2
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert!(patch.old_missing_newline);
+        assert!(!patch.new_missing_newline);
+        assert_eq!(patch.chunks[0].operations.len(), 3);
+        assert!(patch.to_string().ends_with(&patch_str[30..]));
+
+        let both = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-line1
++LINE1
+ line2
+\\ No newline at end of file
+";
+        let patch = Patch::parse(both).unwrap();
+        assert!(patch.old_missing_newline && patch.new_missing_newline);
+        assert!(patch.to_string().ends_with(&both[30..]));
+    }
+
+    #[test]
+    fn test_whitespace_warnings() {
+        let old = "keep \nold\n";
+        let new = "keep \nclean\ntrailing \n \tmixed\t\n";
+        let patch = Differ::new(old, new).generate();
+        assert_eq!(
+            patch.whitespace_warnings(),
+            vec![
+                (3, "trailing whitespace".to_string()),
+                (4, "trailing whitespace".to_string()),
+                (4, "space before tab in indent".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunk_context_accessors() {
+        let context = |line: &str| Operation::Context(line.to_string());
+        let chunk = Chunk {
+            old_start: 0,
+            old_lines: 5,
+            new_start: 0,
+            new_lines: 5,
+            operations: vec![
+                context("a"),
+                context("b"),
+                Operation::Remove("c".to_string()),
+                context("d"),
+                Operation::Add("e".to_string()),
+                context("f"),
+            ],
+            section_header: None,
+        };
+        assert_eq!(chunk.leading_context(), &chunk.operations[..2]);
+        assert_eq!(chunk.core(), &chunk.operations[2..5]);
+        assert_eq!(chunk.trailing_context(), &chunk.operations[5..]);
+
+        let only_context = Chunk {
+            operations: vec![context("a"), context("b")],
+            ..chunk
+        };
+        assert_eq!(only_context.leading_context().len(), 2);
+        assert_eq!(only_context.trailing_context().len(), 2);
+        assert!(only_context.core().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_from_operations() {
+        let chunk = Chunk::from_operations(
+            3,
+            4,
+            vec![
+                Operation::Context("a".to_string()),
+                Operation::Remove("b".to_string()),
+                Operation::Add("c".to_string()),
+                Operation::Add("d".to_string()),
+            ],
+        );
+        assert_eq!((chunk.old_start, chunk.old_lines), (3, 2));
+        assert_eq!((chunk.new_start, chunk.new_lines), (4, 3));
+        assert_eq!(chunk.section_header, None);
+    }
+
+    #[test]
+    fn test_chunk_preview() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+        let patch = Differ::new(old, new).context_lines(1).generate();
+        let chunk = &patch.chunks[0];
+
+        assert_eq!(chunk.preview(old).unwrap(), "2\n3\n4\nfive\n6\n7\n8\n");
+        // Located even after lines were inserted above it
+        let moved = format!("0\n{old}");
+        assert_eq!(chunk.preview(&moved).unwrap(), "2\n3\n4\nfive\n6\n7\n8\n");
+        // Near the start of the file there is less context to show
+        let patch = Differ::new(old, "one\n2\n3\n4\n5\n6\n7\n8\n")
+            .context_lines(1)
+            .generate();
+        assert_eq!(patch.chunks[0].preview(old).unwrap(), "one\n2\n3\n4\n");
+        assert!(chunk.preview("nothing alike\n").is_err());
+    }
+
+    #[test]
+    fn test_conflicts_with() {
+        let base: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let diff = |new: String| Differ::new(&base, &new).context_lines(1).generate();
+        let ours = diff(
+            base.replace("line 3\n", "three\n")
+                .replace("line 15\n", "fifteen\n"),
+        );
+        let theirs = diff(
+            base.replace("line 4\n", "four\n")
+                .replace("line 10\n", "ten\n"),
+        );
+        let elsewhere = diff(base.replace("line 8\n", "eight\n"));
+
+        // Line 3's chunk covers lines 2-4, line 4's covers 3-5
+        assert_eq!(ours.conflicts_with(&theirs), vec![(0, 0)]);
+        assert_eq!(theirs.conflicts_with(&ours), vec![(0, 0)]);
+        assert!(ours.conflicts_with(&elsewhere).is_empty());
+        assert_eq!(ours.conflicts_with(&ours), vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_touches_line() {
+        let base: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let new = base
+            .replace("line 3\n", "three\n")
+            .replace("line 15\n", "")
+            .replace("line 18\n", "line 18\nadded\n");
+        let patch = Differ::new(&base, &new).context_lines(1).generate();
+
+        assert_eq!(patch.touches_line(2), Some(&patch.chunks[0]));
+        assert_eq!(patch.touches_line(14), Some(&patch.chunks[1]));
+        // Context lines and lines outside any chunk are untouched, as is the
+        // line an insertion follows
+        for line in [1, 3, 10, 17, 19] {
+            assert_eq!(patch.touches_line(line), None);
+        }
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let base: String = (1..=20).map(|i| format!("line {i}\n")).collect();
+        let new = base
+            .replace("line 5\n", "five\n")
+            .replace("line 6\n", "six\n")
+            .replace("line 12\n", "twelve\n");
+        let mut wide = Differ::new(&base, &new).context_lines(5).generate();
+        let mut narrow = Differ::new(&base, &new).context_lines(1).generate();
+        assert_ne!(wide, narrow);
+
+        // Interleave the removals and additions of the first change
+        let ops = &mut narrow.chunks[0].operations;
+        let first_add = ops.iter().position(Operation::is_add).unwrap();
+        ops.swap(first_add - 1, first_add);
+        narrow.chunks[0].section_header = Some("fn header".to_string());
+
+        wide.canonicalize(1);
+        narrow.canonicalize(1);
+        assert_eq!(wide, narrow);
+        assert_eq!(wide.chunks.len(), 2);
+        assert_eq!(Patcher::new(wide).apply(&base, false).unwrap(), new);
+    }
+
+    #[test]
+    fn test_to_ndjson() {
+        let patch = Differ::new("a\n\"b\"\nc\n", "a\nb\\\tB\nc\n")
+            .context_lines(1)
+            .generate();
+        assert_eq!(
+            patch.to_ndjson(),
+            concat!(
+                "{\"op\":\"context\",\"old\":0,\"new\":0,\"text\":\"a\"}\n",
+                "{\"op\":\"remove\",\"old\":1,\"new\":null,\"text\":\"\\\"b\\\"\"}\n",
+                "{\"op\":\"add\",\"old\":null,\"new\":1,\"text\":\"b\\\\\\tB\"}\n",
+                "{\"op\":\"context\",\"old\":2,\"new\":2,\"text\":\"c\"}\n",
+            )
+        );
+        assert_eq!(Patch::default().to_ndjson(), "");
+    }
+
+    #[test]
+    fn test_to_string_wrapped() {
+        let patch = Differ::new("short\nkeep\n", "a much longer line\nkeep\n")
+            .file_names("file.txt", "file.txt")
+            .generate();
+        assert_eq!(patch.max_line_width(), 18);
+        assert_eq!(Patch::default().max_line_width(), 0);
+
+        assert_eq!(
+            patch.to_string_wrapped(8),
+            concat!(
+                "diff --git a/file.txt b/file.txt\n",
+                "--- a/file.txt\n",
+                "+++ b/file.txt\n",
+                "@@ -1,2 +1,2 @@\n",
+                "-short\n",
+                "+a much l\n",
+                "+↪onger li\n",
+                "+↪ne\n",
+                " keep\n",
+            )
+        );
+        assert_eq!(patch.to_string_wrapped(80), patch.to_string());
+    }
+
+    #[test]
+    fn test_compose_line_maps() {
+        let v1: String = (1..=10).map(|i| format!("line {i}\n")).collect();
+        let v2 = v1
+            .replace("line 2\n", "")
+            .replace("line 5\n", "line 5\nnew\n");
+        let v3 = v2
+            .replace("line 7\n", "")
+            .replace("line 1\n", "first\nline 1\n");
+        let patches = [
+            Differ::new(&v1, &v2).context_lines(1).generate(),
+            Differ::new(&v2, &v3).context_lines(1).generate(),
+        ];
+
+        let map = compose_line_maps(&patches);
+        let final_lines: Vec<&str> = v3.lines().collect();
+        for (line, text) in v1.lines().enumerate() {
+            match map(line) {
+                Some(mapped) => assert_eq!(final_lines[mapped], text),
+                None => assert!(["line 2", "line 7"].contains(&text), "{text}"),
+            }
+        }
+        assert_eq!(map(1), None);
+        assert_eq!(map(9), Some(9));
+        assert_eq!(compose_line_maps(&[])(4), Some(4));
+    }
+
+    #[test]
+    fn test_line_map() {
+        // a b c d e f g -> a b d e f X g
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,3 +2,2 @@
+ b
+-c
+ d
+@@ -6 +5,2 @@
+ f
++X
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert_eq!(
+            patch.line_map(),
+            vec![
+                LineMapping::Kept { old: 0, new: 0 },
+                LineMapping::Kept { old: 1, new: 1 },
+                LineMapping::Deleted { old: 2 },
+                LineMapping::Kept { old: 3, new: 2 },
+                LineMapping::Kept { old: 4, new: 3 },
+                LineMapping::Kept { old: 5, new: 4 },
+            ]
+        );
+        assert!(Patch::default().line_map().is_empty());
+    }
+
+    #[test]
+    fn test_iter_operations() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ one
+-two
++TWO
+ three
+@@ -10,2 +10,3 @@
+ ten
++ten and a half
+ eleven
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        let positions: Vec<(usize, usize, char)> = patch
+            .iter_operations()
+            .map(|(old, new, op)| (old, new, op.to_char()))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                (0, 0, ' '),
+                (1, 1, '-'),
+                (2, 1, '+'),
+                (2, 2, ' '),
+                (9, 9, ' '),
+                (10, 10, '+'),
+                (10, 11, ' '),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_colored_string() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ keep
+--- not a header
++++ not a header either
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        let colored = patch.to_colored_string();
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[1], "\x1b[1m--- a/file.txt\x1b[0m");
+        assert_eq!(lines[3], "\x1b[36m@@ -1,2 +1,2 @@\x1b[0m");
+        assert_eq!(lines[4], " keep");
+        assert_eq!(lines[5], "\x1b[31m--- not a header\x1b[0m");
+        assert_eq!(lines[6], "\x1b[32m+++ not a header either\x1b[0m");
+    }
+
+    #[test]
+    fn test_single_line_ranges_omit_count() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -5,1 +5,2 @@
+-e
++E
++F
+",
+        )
+        .unwrap();
+        assert!(patch.to_string().contains("\n@@ -5 +5,2 @@\n"));
+        let reparsed = Patch::parse(&patch.to_string()).unwrap();
+        assert_eq!(reparsed.chunks, patch.chunks);
+    }
+
+    #[test]
+    fn test_empty_range_round_trip() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,0 +3 @@
++inserted
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert_eq!(patch.chunks[0].old_start, 2);
+        assert_eq!(patch.chunks[0].new_start, 2);
+        assert!(patch.to_string().ends_with(&patch_str[30..]));
+    }
+
+    #[test]
+    fn test_parse_from_reader() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1 +1 @@
+-old
++new
+";
+        let patch = Patch::parse_from_reader(std::io::Cursor::new(patch_str)).unwrap();
+        assert_eq!(patch, Patch::parse(patch_str).unwrap());
+    }
+
+    #[test]
+    fn test_coalesce_merges_touching_and_overlapping_chunks() {
+        let content = "1\n2\n3\n4\n5\n6\n";
+        let touching = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+-1
++one
+ 2
+@@ -3,2 +3,2 @@
+ 3
+-4
++four
+";
+        let mut patch = Patch::parse(touching).unwrap();
+        let expected = Patcher::new(patch.clone()).apply(content, false).unwrap();
+        patch.coalesce();
+        assert_eq!(patch.chunks.len(), 1);
+        assert_eq!(
+            (patch.chunks[0].old_lines, patch.chunks[0].new_lines),
+            (4, 4)
+        );
+        assert!(patch.validate().is_ok());
+        let result = Patcher::new(patch).apply(content, false).unwrap();
+        assert_eq!(result, expected);
+
+        // Both chunks carry lines 2-3 as context
+        let overlapping = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+-1
++one
+ 2
+ 3
+@@ -2,4 +2,4 @@
+ 2
+ 3
+-4
++four
+ 5
+";
+        let mut patch = Patch::parse(overlapping).unwrap();
+        assert!(patch.validate().is_err());
+        patch.coalesce();
+        assert_eq!(patch.chunks.len(), 1);
+        assert_eq!(
+            (patch.chunks[0].old_lines, patch.chunks[0].new_lines),
+            (5, 5)
+        );
+        assert!(patch.validate().is_ok());
+        let result = Patcher::new(patch).apply(content, false).unwrap();
+        assert_eq!(result, "one\n2\n3\nfour\n5\n6\n");
+    }
+
+    #[test]
+    fn test_coalesce_keeps_conflicting_chunks() {
+        let conflicting = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,2 @@
+ 1
+-2
++two
+@@ -2,2 +2,2 @@
+-2
++TWO
+ 3
+";
+        let mut patch = Patch::parse(conflicting).unwrap();
+        patch.coalesce();
+        assert_eq!(patch.chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_chunk_split_on_long_context_runs() {
+        let patch_str = "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,9 +1,9 @@
+ 1
+-2
++two
+ 3
+ 4
+ 5
+ 6
+ 7
+-8
++eight
+ 9
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        let content = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let expected = Patcher::new(patch.clone()).apply(content, false).unwrap();
+
+        let pieces = patch.chunks[0].split(1);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(
+            (
+                pieces[0].old_start,
+                pieces[0].old_lines,
+                pieces[0].new_lines
+            ),
+            (0, 3, 3)
+        );
+        assert_eq!(
+            (
+                pieces[1].old_start,
+                pieces[1].new_start,
+                pieces[1].old_lines
+            ),
+            (6, 6, 3)
+        );
+        let split = Patch {
+            chunks: pieces,
+            ..patch.clone()
+        };
+        assert!(split.validate().is_ok());
+        assert_eq!(Patcher::new(split).apply(content, false).unwrap(), expected);
+
+        // A gap of 5 context lines is kept at context 3 (5 <= 2 * 3), only trimmed at the ends
+        let pieces = patch.chunks[0].split(3);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].operations.len(), 11);
+        assert_eq!(patch.chunks[0].split(0).len(), 2);
+        // Unlimited context never splits and keeps every line
+        assert_eq!(patch.chunks[0].split(usize::MAX), patch.chunks);
+    }
+
+    #[test]
+    fn test_parse_rename_headers() {
+        let patch_str = "\
+diff --git a/old/name.rs b/new/name.rs
+similarity index 92%
+rename from old/name.rs
+rename to new/name.rs
+--- a/old/name.rs
++++ b/new/name.rs
+@@ -1 +1 @@
+-fn old() {}
++fn new() {}
+";
+        let patch = Patch::parse(patch_str).unwrap();
+        assert!(patch.is_rename());
+        assert_eq!(patch.similarity, Some(92));
+        assert_eq!(patch.old_file, "old/name.rs");
+        assert_eq!(patch.new_file, "new/name.rs");
+        let headers = patch_str.split("@@").next().unwrap();
+        assert!(patch.to_string().starts_with(headers));
+
+        let pure = "\
+diff --git a/a.txt b/b.txt
+similarity index 100%
+rename from a.txt
+rename to b.txt
+";
+        let patch = Patch::parse(pure).unwrap();
+        assert!(patch.is_rename() && patch.chunks.is_empty());
+        assert_eq!(patch.to_string(), pure);
+    }
 }
@@ -0,0 +1,199 @@
+use crate::patch::{escape_record, unescape_record};
+use crate::patcher::HunkApplication;
+use crate::{Chunk, Error, Operation, Patch};
+use std::borrow::Cow;
+
+/// Applies a patch to raw bytes, comparing lines byte for byte.
+///
+/// Lines are split on `delimiter` (for `\n`, a trailing `\r` is ignored for
+/// matching, as with `str::lines`), so bytes that are not valid UTF-8 in lines the patch doesn't
+/// touch are copied through unchanged. Each chunk is tried at its expected
+/// position (shifted by the offset of the previous chunks); when `relocate`
+/// is set and the context doesn't match there, it is searched for forward
+/// from the end of the previous chunk.
+///
+/// Records split on a delimiter other than `\n` are compared and written in
+/// the escaped form of the patch lines, see [`escape_record`].
+pub(crate) fn apply_bytes(
+    patch: &Patch,
+    content: &[u8],
+    reverse: bool,
+    relocate: bool,
+    delimiter: u8,
+) -> Result<(Vec<u8>, Vec<HunkApplication>), Error> {
+    let escaped: Vec<Cow<[u8]>> = split_lines(content, delimiter)
+        .into_iter()
+        .map(|line| {
+            if delimiter == b'\n' {
+                Cow::Borrowed(line)
+            } else {
+                escape_record(line)
+            }
+        })
+        .collect();
+    let lines: Vec<&[u8]> = escaped.iter().map(AsRef::as_ref).collect();
+    let mut hunks = Vec::with_capacity(patch.chunks.len());
+    let mut output: Vec<&[u8]> = Vec::with_capacity(lines.len());
+    let mut current = 0;
+    let mut offset: isize = 0;
+
+    for (index, chunk) in patch.chunks.iter().enumerate() {
+        let expected = if reverse {
+            chunk.new_start
+        } else {
+            chunk.old_start
+        };
+        let (ops, source) = chunk_lines(chunk, reverse);
+
+        let shifted = expected.saturating_add_signed(offset).max(current);
+        let matches_at = |pos: usize| {
+            pos + source.len() <= lines.len()
+                && lines[pos..pos + source.len()]
+                    .iter()
+                    .zip(&source)
+                    .all(|(actual, expected)| trim_cr(actual, delimiter) == *expected)
+        };
+        let position = if source.is_empty() || matches_at(shifted) {
+            shifted
+        } else if relocate {
+            (current..=lines.len().saturating_sub(source.len()))
+                .find(|&pos| matches_at(pos))
+                .ok_or_else(|| {
+                    Error::ApplyError(format!(
+                        "Failed to find matching context for chunk expected at line {}",
+                        expected + 1
+                    ))
+                })?
+        } else {
+            return Err(Error::ApplyError(format!(
+                "Context mismatch for chunk at line {}",
+                shifted + 1
+            )));
+        };
+        if position > lines.len() {
+            return Err(Error::LineNotFound {
+                line_num: position + 1,
+            });
+        }
+        offset = position as isize - expected as isize;
+        hunks.push(HunkApplication {
+            index,
+            expected_start: expected,
+            actual_start: position,
+            fuzzy: false,
+            score: 1.0,
+        });
+
+        output.extend_from_slice(&lines[current..position]);
+        current = position;
+        for (is_source, is_target, line) in ops {
+            match (is_source, is_target) {
+                // Keep the original bytes (including any '\r') of context lines
+                (true, true) => {
+                    output.push(lines[current]);
+                    current += 1;
+                }
+                (true, false) => current += 1,
+                _ => output.push(line),
+            }
+        }
+    }
+
+    let reached_eof = current == lines.len();
+    output.extend_from_slice(&lines[current..]);
+
+    let trailing_newline = match patch.newline_at_eof(reverse) {
+        Some(newline) if reached_eof => newline,
+        // Nothing to preserve when the content is created from scratch
+        None if content.is_empty() => true,
+        _ => content.ends_with(&[delimiter]),
+    };
+    let mut result = if delimiter == b'\n' {
+        output.join(&delimiter)
+    } else {
+        let records: Vec<Cow<[u8]>> = output
+            .iter()
+            .map(|record| unescape_record(record))
+            .collect();
+        records.join(&delimiter)
+    };
+    if trailing_newline && !output.is_empty() {
+        result.push(delimiter);
+    }
+    Ok((result, hunks))
+}
+
+/// Splits content into lines on `delimiter`, without the terminators.
+fn split_lines(content: &[u8], delimiter: u8) -> Vec<&[u8]> {
+    let body = content.strip_suffix(&[delimiter]).unwrap_or(content);
+    if content.is_empty() {
+        Vec::new()
+    } else {
+        body.split(|&b| b == delimiter).collect()
+    }
+}
+
+/// A chunk line as `(is_source_line, is_target_line, text)`.
+pub(super) type ChunkLine<'a> = (bool, bool, &'a [u8]);
+
+/// Returns the chunk's lines, with the source and target roles swapped when
+/// reversing, and the source lines the content must match.
+pub(super) fn chunk_lines(chunk: &Chunk, reverse: bool) -> (Vec<ChunkLine<'_>>, Vec<&[u8]>) {
+    let ops: Vec<ChunkLine> = chunk
+        .operations
+        .iter()
+        .map(|op| {
+            let line = op.line().as_bytes();
+            match (op, reverse) {
+                (Operation::Context(_), _) => (true, true, line),
+                (Operation::Remove(_), false) | (Operation::Add(_), true) => (true, false, line),
+                (Operation::Add(_), false) | (Operation::Remove(_), true) => (false, true, line),
+            }
+        })
+        .collect();
+    let source = ops
+        .iter()
+        .filter(|(is_source, _, _)| *is_source)
+        .map(|(_, _, line)| *line)
+        .collect();
+    (ops, source)
+}
+
+/// Strips the `\r` of a CRLF line ending when splitting on `\n`.
+pub(super) fn trim_cr(line: &[u8], delimiter: u8) -> &[u8] {
+    match line.strip_suffix(b"\r") {
+        Some(stripped) if delimiter == b'\n' => stripped,
+        _ => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::differ::{DiffAlgorithm, Differ};
+
+    #[test]
+    fn test_apply_bytes_preserves_invalid_utf8() {
+        let patch = Differ::new("x\nb\nc\nd\ne\n", "x\nb\nc\nD\ne\n")
+            .context_lines(1)
+            .generate();
+        // Latin-1 encoded "café" in a line the patch doesn't touch
+        let content = b"caf\xe9\nb\nc\nd\ne\n";
+
+        let (result, _) = apply_bytes(&patch, content, false, false, b'\n').unwrap();
+        assert_eq!(result, b"caf\xe9\nb\nc\nD\ne\n");
+        let (reverted, _) = apply_bytes(&patch, &result, true, false, b'\n').unwrap();
+        assert_eq!(reverted, content);
+    }
+
+    #[test]
+    fn test_apply_bytes_relocates_chunk() {
+        let patch = Differ::new("a\nb\nc\n", "a\nB\nc\n").generate();
+        let content = b"\xff\xfe\nextra\na\nb\nc\n";
+
+        assert!(apply_bytes(&patch, content, false, false, b'\n').is_err());
+        let (result, hunks) = apply_bytes(&patch, content, false, true, b'\n').unwrap();
+        assert_eq!(hunks[0].offset(), 2);
+        assert_eq!(result, b"\xff\xfe\nextra\na\nB\nc\n");
+    }
+}
@@ -1,15 +1,91 @@
+mod bytes;
 mod naive;
 mod similar;
+mod stream;
 
-use crate::{Error, Patch};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use tempfile::NamedTempFile;
+
+use crate::{BOM, Error, Operation, Patch};
 
 pub use naive::NaivePatcher;
 pub use similar::SimilarPatcher;
+pub(crate) use similar::{normalize_whitespace, similarity_score};
+
+/// How far from its expected line `apply_stream` looks for a relocated chunk
+const STREAM_SEARCH_WINDOW: usize = 50;
+
+/// Picks the context lines of a patch that match any line
+type ContextPredicate = dyn Fn(&str) -> bool + Send + Sync;
 
 #[derive(Clone)]
 pub struct Patcher {
     patch: Patch,
     algorithm: PatcherAlgorithm,
+    fuzz: Option<usize>,
+    similarity: Arc<dyn LineSimilarity>,
+    delimiter: u8,
+    normalize_context: bool,
+    tab_width: Option<usize>,
+    verify_context: usize,
+    ignore_context: Option<Arc<ContextPredicate>>,
+}
+
+/// Scores how similar two lines are, from `0.0` (unrelated) to `1.0` (identical).
+///
+/// Used by the `Similar` and `Anchored` algorithms to match chunk context
+/// against content that has drifted from the original.
+pub trait LineSimilarity: Send + Sync {
+    fn score(&self, a: &str, b: &str) -> f64;
+}
+
+impl<F> LineSimilarity for F
+where
+    F: Fn(&str, &str) -> f64 + Send + Sync,
+{
+    fn score(&self, a: &str, b: &str) -> f64 {
+        self(a, b)
+    }
+}
+
+/// The default line similarity: normalized Levenshtein distance, with lines
+/// that differ only in whitespace scoring `0.95`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultSimilarity;
+
+impl LineSimilarity for DefaultSimilarity {
+    fn score(&self, a: &str, b: &str) -> f64 {
+        similarity_score(a, b)
+    }
+}
+
+/// Describes where a single chunk of a patch was applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HunkApplication {
+    /// Index of the chunk within the patch
+    pub index: usize,
+    /// Line (0-based) where the chunk header says it applies
+    pub expected_start: usize,
+    /// Line (0-based) where the chunk was actually applied
+    pub actual_start: usize,
+    /// Whether fuzzy (non-exact) matching was needed to locate the chunk
+    pub fuzzy: bool,
+    /// Similarity of the matched context, from `0.0` to `1.0` (exact)
+    pub score: f64,
+}
+
+impl HunkApplication {
+    /// Signed distance between the actual and the expected start line.
+    pub fn offset(&self) -> isize {
+        self.actual_start as isize - self.expected_start as isize
+    }
 }
 
 impl Patcher {
@@ -18,10 +94,599 @@ impl Patcher {
     }
 
     pub fn new_with_algorithm(patch: Patch, algorithm: PatcherAlgorithm) -> Self {
-        Self { patch, algorithm }
+        Self {
+            patch,
+            algorithm,
+            fuzz: None,
+            similarity: Arc::new(DefaultSimilarity),
+            delimiter: b'\n',
+            normalize_context: false,
+            tab_width: None,
+            verify_context: 0,
+            ignore_context: None,
+        }
+    }
+
+    /// Match chunks like GNU `patch --fuzz=N`: up to `fuzz` leading and trailing
+    /// context lines of a chunk may be ignored, the remaining lines must match
+    /// exactly. This replaces the similarity-based matching of the `Similar` and
+    /// `Anchored` algorithms; the `Naive` algorithm always requires exact context.
+    pub fn fuzz(mut self, fuzz: usize) -> Self {
+        self.fuzz = Some(fuzz);
+        self
+    }
+
+    /// Uses a custom line similarity when matching chunk context approximately.
+    pub fn similarity(mut self, similarity: Arc<dyn LineSimilarity>) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    /// Writes the patch's own context lines into the result when the content only
+    /// matches them approximately, normalizing them to what the patch expects.
+    /// By default the content's lines are kept. Only affects the `Similar` and
+    /// `Anchored` algorithms; the `Naive` algorithm always requires exact context.
+    pub fn normalize_context(mut self, normalize: bool) -> Self {
+        self.normalize_context = normalize;
+        self
+    }
+
+    /// Matches context lines that only differ in tabs versus spaces, expanding tabs
+    /// to stops every `width` columns before comparing. The content keeps its own
+    /// indentation in the result. Only affects the `Similar` and `Anchored`
+    /// algorithms; the `Naive` algorithm always requires exact context.
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    /// Lets each context line of the patch for which `predicate` returns `true`
+    /// match whatever line the content has in its place, e.g. a license header
+    /// known to be stale. The line still takes up its position and the content's
+    /// line is kept. Only affects the `Similar` and `Anchored` algorithms; the
+    /// `Naive` algorithm always requires exact context.
+    pub fn ignore_context_matching<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.ignore_context = Some(Arc::new(predicate));
+        self
+    }
+
+    /// Checks `lines` more lines before and after each matched chunk than the chunk
+    /// carries as context, so a chunk with little context is not applied to
+    /// a lookalike region.
+    ///
+    /// A checked line is mapped back to the line it would be in the original
+    /// file, using the distance between the chunk's matched position and the one
+    /// its header expects. If the patch knows that line, from the context or
+    /// removed lines of any of its chunks, the content must have it there exactly.
+    /// The content must also have at least as many of the checked lines before
+    /// the chunk as the original file did. Only content split on `\n` is checked.
+    pub fn verify_context(mut self, lines: usize) -> Self {
+        self.verify_context = lines;
+        self
+    }
+
+    /// Split the content into records on `delimiter` instead of `\n`, matching
+    /// a patch generated with [`Differ::delimiter`](crate::Differ::delimiter).
+    ///
+    /// Records are compared byte for byte: the `Naive` algorithm requires each
+    /// chunk at its expected position, `Similar` and `Anchored` search forward
+    /// for its exact context. The patch lines hold the records escaped as by the
+    /// differ, with line breaks written as `\n`, and are unescaped on output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delimiter` is not ASCII.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        assert!(delimiter.is_ascii(), "delimiter must be an ASCII byte");
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Applies the patch to raw bytes, for files that are not valid UTF-8.
+    ///
+    /// Valid UTF-8 content is patched exactly like [`PatchAlgorithm::apply`].
+    /// Otherwise lines are split on `\n` and compared byte for byte, so bytes
+    /// outside the patched lines survive unchanged. In that case the `Naive`
+    /// algorithm requires each chunk at its expected line, while `Similar` and
+    /// `Anchored` fall back to an exact forward search for the chunk's context.
+    pub fn apply_bytes(&self, content: &[u8], reverse: bool) -> Result<Vec<u8>, Error> {
+        match std::str::from_utf8(content) {
+            Ok(text) => self
+                .apply_tracked(text, reverse)
+                .map(|(result, _)| result.into_bytes()),
+            Err(_) => self
+                .apply_records(content, reverse)
+                .map(|(result, _)| result),
+        }
+    }
+
+    /// Applies the patch with the byte-oriented machinery, splitting on the delimiter.
+    fn apply_records(
+        &self,
+        content: &[u8],
+        reverse: bool,
+    ) -> Result<(Vec<u8>, Vec<HunkApplication>), Error> {
+        bytes::apply_bytes(
+            &self.patch,
+            content,
+            reverse,
+            !matches!(self.algorithm, PatcherAlgorithm::Naive),
+            self.delimiter,
+        )
+    }
+
+    /// Returns `true` if the patch appears to be applied to `content` already:
+    /// its changes can be reverted but not applied.
+    ///
+    /// The direction is decided as by [`apply_auto`](Self::apply_auto).
+    pub fn is_applied(&self, content: &str) -> bool {
+        self.apply_auto(content).is_ok_and(|(_, reversed)| reversed)
+    }
+
+    /// Applies the patch in whichever direction matches `content`, like GNU
+    /// `patch` offering to reverse an already applied patch.
+    ///
+    /// Returns the patched content and whether the patch was applied in
+    /// reverse, or [`Error::NotApplicable`] if neither direction matches.
+    ///
+    /// Both directions are tried with this patcher's own algorithm and settings.
+    /// A direction in which every chunk matched exactly wins over one that needed
+    /// fuzzy matching, as `Similar` may fuzzily apply a patch to its own result.
+    pub fn apply_auto(&self, content: &str) -> Result<(String, bool), Error> {
+        // The result and whether every chunk matched exactly
+        let attempt = |reverse| {
+            self.apply_tracked(content, reverse)
+                .ok()
+                .map(|(result, hunks)| (result, hunks.iter().all(|hunk| !hunk.fuzzy)))
+        };
+        let forward = attempt(false);
+        if let Some((result, true)) = forward {
+            return Ok((result, false));
+        }
+        match (forward, attempt(true)) {
+            (_, Some((result, true))) => Ok((result, true)),
+            (Some((result, _)), _) => Ok((result, false)),
+            (None, Some((result, _))) => Ok((result, true)),
+            (None, None) => Err(Error::NotApplicable),
+        }
+    }
+
+    /// Applies the patch to content read line by line from `reader`, streaming the
+    /// result to `writer`, for files too large to hold in memory.
+    ///
+    /// Only the lines around the chunk being applied are buffered. Context must
+    /// match exactly: the `Naive` algorithm requires each chunk at its expected
+    /// line, `Similar` and `Anchored` accept the nearest match within 50 lines of it.
+    pub fn apply_stream<R: BufRead, W: Write>(
+        &self,
+        reader: R,
+        writer: W,
+        reverse: bool,
+    ) -> Result<(), Error> {
+        let window = match self.algorithm {
+            PatcherAlgorithm::Naive => 0,
+            PatcherAlgorithm::Similar | PatcherAlgorithm::Anchored => STREAM_SEARCH_WINDOW,
+        };
+        stream::apply_stream(&self.patch, reader, writer, reverse, window)
+    }
+
+    /// Applies the patch to the file at `path` in place.
+    ///
+    /// The result is written to a temporary file next to the target and then
+    /// renamed over it, so the file is never left half-written. A file whose
+    /// lines all end in CRLF keeps those endings. In a file with mixed endings,
+    /// lines are matched ignoring a trailing `\r` and the lines the patch doesn't
+    /// change keep their endings, as with [`apply_bytes`](Self::apply_bytes) on
+    /// invalid UTF-8. The presence of a trailing newline is preserved.
+    pub fn apply_to_file<P: AsRef<Path>>(&self, path: P, reverse: bool) -> Result<(), Error> {
+        self.patch_file(path.as_ref(), reverse, None)
+    }
+
+    /// Like [`apply_to_file`](Self::apply_to_file), but first copies the original
+    /// file to the path with `suffix` appended, e.g. `.orig` as GNU `patch -b` does.
+    ///
+    /// The backup is only written once the patch is known to apply, so a failed
+    /// application leaves no backup behind. An existing backup is overwritten.
+    pub fn apply_to_file_with_backup<P: AsRef<Path>>(
+        &self,
+        path: P,
+        reverse: bool,
+        suffix: &str,
+    ) -> Result<(), Error> {
+        self.patch_file(path.as_ref(), reverse, Some(suffix))
+    }
+
+    fn patch_file(
+        &self,
+        path: &Path,
+        reverse: bool,
+        backup_suffix: Option<&str>,
+    ) -> Result<(), Error> {
+        if !path.is_file() {
+            return Err(Error::FileNotFound {
+                path: path.display().to_string(),
+            });
+        }
+        let original = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+
+        let result = match original.matches("\r\n").count() {
+            0 => self.apply(&original, reverse)?,
+            crlf if crlf == original.matches('\n').count() => self
+                .apply(&original.replace("\r\n", "\n"), reverse)?
+                .replace('\n', "\r\n"),
+            // Mixed endings: copy the untouched lines through byte for byte
+            _ => {
+                let (result, _) = self.apply_records(original.as_bytes(), reverse)?;
+                String::from_utf8(result).expect("lines split on an ASCII byte")
+            }
+        };
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if let Some(suffix) = backup_suffix {
+            fs::copy(path, path.with_file_name(format!("{file_name}{suffix}")))
+                .map_err(|e| Error::io(path, e))?;
+        }
+        // A uniquely named temporary file, so concurrent writers never share one; it
+        // is removed again if anything fails before it is persisted
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let written = NamedTempFile::new_in(dir).and_then(|mut temp| {
+            temp.write_all(result.as_bytes())?;
+            fs::set_permissions(temp.path(), fs::metadata(path)?.permissions())?;
+            temp.persist(path).map_err(|e| e.error)?;
+            Ok(())
+        });
+        written.map_err(|e| Error::io(path, e))
+    }
+
+    /// Applies only the chunks at `indices`, as `git add -p` stages selected hunks.
+    ///
+    /// The other chunks are left out, and the start lines of the selected ones are
+    /// adjusted for the lines those would have added or removed. When `reverse` is
+    /// set, `content` is expected to contain every chunk and only the selected ones
+    /// are reverted. Duplicate indices are ignored; out-of-range indices are an error.
+    pub fn apply_hunks(
+        &self,
+        content: &str,
+        reverse: bool,
+        indices: &[usize],
+    ) -> Result<String, Error> {
+        let total = self.patch.chunks.len();
+        if let Some(&index) = indices.iter().find(|&&index| index >= total) {
+            return Err(Error::ApplyError(format!(
+                "Hunk index {} is out of range, the patch has {} hunks",
+                index, total
+            )));
+        }
+
+        let mut patch = Patch {
+            chunks: Vec::with_capacity(indices.len()),
+            ..self.patch.clone()
+        };
+        // Lines added minus lines removed by the skipped chunks so far
+        let mut skipped_delta: isize = 0;
+        for (index, chunk) in self.patch.chunks.iter().enumerate() {
+            if !indices.contains(&index) {
+                skipped_delta += chunk.new_lines as isize - chunk.old_lines as isize;
+                continue;
+            }
+            let mut chunk = chunk.clone();
+            if reverse {
+                // The skipped chunks stay applied in the result
+                chunk.old_start = chunk.old_start.saturating_add_signed(skipped_delta);
+            } else {
+                chunk.new_start = chunk.new_start.saturating_add_signed(-skipped_delta);
+            }
+            patch.chunks.push(chunk);
+        }
+        // The no-newline markers belong to the last chunk
+        if !indices.contains(&total.saturating_sub(1)) {
+            patch.old_missing_newline = false;
+            patch.new_missing_newline = false;
+        }
+
+        Patcher {
+            patch,
+            ..self.clone()
+        }
+        .apply(content, reverse)
+    }
+
+    /// Applies the patch like [`PatchAlgorithm::apply`], but borrows `content`
+    /// instead of copying it when the patch has no chunks or leaves the content
+    /// as it was, e.g. when batch-processing many files that are mostly unchanged.
+    pub fn apply_cow<'c>(&self, content: &'c str, reverse: bool) -> Result<Cow<'c, str>, Error> {
+        if self.patch.chunks.is_empty() {
+            return Ok(Cow::Borrowed(content));
+        }
+        let result = self.apply(content, reverse)?;
+        Ok(if result == content {
+            Cow::Borrowed(content)
+        } else {
+            Cow::Owned(result)
+        })
+    }
+
+    /// Applies the patch and reports how each chunk was matched.
+    pub fn apply_detailed(&self, content: &str, reverse: bool) -> Result<ApplyReport, Error> {
+        let (content, hunks) = self.apply_tracked(content, reverse)?;
+        Ok(ApplyReport {
+            content,
+            hunks: hunks.iter().map(HunkStatus::from).collect(),
+        })
+    }
+
+    /// Applies the patch and also returns the 0-based line ranges of the result that
+    /// the patch inserted, e.g. for an editor to highlight them. Adjacent inserted
+    /// lines form a single range; removed lines leave no trace in the result.
+    pub fn apply_with_ranges(
+        &self,
+        content: &str,
+        reverse: bool,
+    ) -> Result<(String, Vec<Range<usize>>), Error> {
+        let (result, hunks) = self.apply_tracked(content, reverse)?;
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        // Lines added minus lines removed by the chunks applied so far
+        let mut delta: isize = 0;
+        for hunk in &hunks {
+            let chunk = &self.patch.chunks[hunk.index];
+            let mut line = hunk.actual_start.saturating_add_signed(delta);
+            for op in &chunk.operations {
+                let (added, removed) = if reverse {
+                    (op.is_remove(), op.is_add())
+                } else {
+                    (op.is_add(), op.is_remove())
+                };
+                if added {
+                    match ranges.last_mut() {
+                        Some(range) if range.end == line => range.end += 1,
+                        _ => ranges.push(line..line + 1),
+                    }
+                    delta += 1;
+                } else if removed {
+                    delta -= 1;
+                    continue;
+                }
+                line += 1;
+            }
+        }
+        Ok((result, ranges))
+    }
+
+    /// Applies the patch and reports where each chunk was applied.
+    pub(crate) fn apply_tracked(
+        &self,
+        content: &str,
+        reverse: bool,
+    ) -> Result<(String, Vec<HunkApplication>), Error> {
+        if let Some(patcher) = self.without_overhanging_context_chunks(content, reverse) {
+            return patcher.apply_tracked(content, reverse);
+        }
+        if let Some(rest) = self.strip_bom(content) {
+            let (result, hunks) = self.apply_tracked(rest, reverse)?;
+            return Ok((format!("{BOM}{result}"), hunks));
+        }
+        if self.delimiter != b'\n' {
+            let (result, hunks) = self.apply_records(content.as_bytes(), reverse)?;
+            // Only whole records of valid UTF-8 are copied or inserted
+            let result = String::from_utf8(result).expect("records split on an ASCII byte");
+            return Ok((result, hunks));
+        }
+        let (result, hunks) = match self.algorithm {
+            PatcherAlgorithm::Naive => {
+                NaivePatcher::new(&self.patch).apply_tracked(content, reverse)
+            }
+            PatcherAlgorithm::Similar => self
+                .with_fuzz(SimilarPatcher::new(&self.patch))
+                .apply_tracked(content, reverse),
+            PatcherAlgorithm::Anchored => self
+                .with_fuzz(SimilarPatcher::new_anchored(&self.patch))
+                .apply_tracked(content, reverse),
+        }?;
+        self.check_surrounding_lines(content, reverse, &hunks)?;
+        Ok((result, hunks))
+    }
+
+    /// Checks the lines around each applied chunk against the lines the patch
+    /// knows of the original file, see [`verify_context`](Self::verify_context).
+    fn check_surrounding_lines(
+        &self,
+        content: &str,
+        reverse: bool,
+        hunks: &[HunkApplication],
+    ) -> Result<(), Error> {
+        let extra = self.verify_context;
+        if extra == 0 {
+            return Ok(());
+        }
+        // The lines of the original file that the chunks contain, by line number
+        let mut known: BTreeMap<usize, &str> = BTreeMap::new();
+        for chunk in &self.patch.chunks {
+            let start = if reverse {
+                chunk.new_start
+            } else {
+                chunk.old_start
+            };
+            let original = chunk.operations.iter().filter(|op| {
+                if reverse {
+                    !op.is_remove()
+                } else {
+                    !op.is_add()
+                }
+            });
+            for (offset, op) in original.enumerate() {
+                known.insert(start + offset, op.line());
+            }
+        }
+
+        let lines: Vec<&str> = content.lines().collect();
+        for hunk in hunks {
+            let chunk = &self.patch.chunks[hunk.index];
+            let len = if reverse {
+                chunk.new_lines
+            } else {
+                chunk.old_lines
+            };
+            if hunk.actual_start < hunk.expected_start.min(extra) {
+                return Err(Error::ApplyError(format!(
+                    "Hunk {} matched {} lines from the start of the content, expected at least {}",
+                    hunk.index + 1,
+                    hunk.actual_start,
+                    hunk.expected_start.min(extra)
+                )));
+            }
+            let end = hunk.actual_start + len;
+            let before = hunk.actual_start.saturating_sub(extra)..hunk.actual_start;
+            let after = end.min(lines.len())..(end + extra).min(lines.len());
+            for line_num in before.chain(after) {
+                let Some(expected) = line_num
+                    .checked_add_signed(-hunk.offset())
+                    .and_then(|original| known.get(&original))
+                else {
+                    continue;
+                };
+                if lines[line_num] != *expected {
+                    return Err(Error::ContextMismatch {
+                        line_num: line_num + 1,
+                        expected: expected.to_string(),
+                        actual: lines[line_num].to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The content after its UTF-8 byte order mark, if it has one that the patch
+    /// does not mention: patches of such files leave it out of the first line.
+    fn strip_bom<'c>(&self, content: &'c str) -> Option<&'c str> {
+        content.strip_prefix(BOM).filter(|_| {
+            !self
+                .patch
+                .iter_operations()
+                .any(|(_, _, op)| op.line().starts_with(BOM))
+        })
+    }
+
+    /// Leaves out the trailing chunks that have no added or removed lines and run
+    /// past the end of `content`, as some generators emit. They cannot change the
+    /// content, so they are skipped instead of failing to match. Returns `None`
+    /// if there are none; chunks without changes inside the content still have
+    /// their context checked.
+    fn without_overhanging_context_chunks(&self, content: &str, reverse: bool) -> Option<Patcher> {
+        let line_count = if content.is_empty() {
+            0
+        } else if self.delimiter == b'\n' {
+            content.lines().count()
+        } else {
+            let delimiter = self.delimiter as char;
+            content
+                .strip_suffix(delimiter)
+                .unwrap_or(content)
+                .split(delimiter)
+                .count()
+        };
+        let chunks = &self.patch.chunks;
+        // A change to the trailing newline is a change to the last chunk
+        let newline_changed = self.patch.old_missing_newline != self.patch.new_missing_newline;
+        let kept = chunks.len()
+            - chunks
+                .iter()
+                .enumerate()
+                .rev()
+                .take_while(|(index, chunk)| {
+                    let (start, len) = if reverse {
+                        (chunk.new_start, chunk.new_lines)
+                    } else {
+                        (chunk.old_start, chunk.old_lines)
+                    };
+                    let changes_newline = newline_changed && *index == chunks.len() - 1;
+                    let has_changes = chunk.operations.iter().any(Operation::is_change);
+                    !has_changes && !changes_newline && start + len > line_count
+                })
+                .count();
+        if kept == chunks.len() {
+            return None;
+        }
+        let mut patch = Patch {
+            chunks: chunks[..kept].to_vec(),
+            ..self.patch.clone()
+        };
+        // The no-newline markers belonged to the last chunk, which made no change
+        patch.old_missing_newline = false;
+        patch.new_missing_newline = false;
+        Some(Patcher {
+            patch,
+            ..self.clone()
+        })
+    }
+
+    /// Applies the configured similarity, context normalization and fuzz factor
+    /// to a `SimilarPatcher`.
+    fn with_fuzz<'a>(&'a self, patcher: SimilarPatcher<'a>) -> SimilarPatcher<'a> {
+        let mut patcher = patcher
+            .similarity(self.similarity.as_ref())
+            .normalize_context(self.normalize_context);
+        if let Some(width) = self.tab_width {
+            patcher = patcher.tab_width(width);
+        }
+        if let Some(predicate) = &self.ignore_context {
+            patcher = patcher.ignore_context_matching(predicate.as_ref());
+        }
+        match self.fuzz {
+            Some(fuzz) => patcher.fuzz(fuzz),
+            None => patcher,
+        }
     }
 }
 
+/// How a single chunk was matched against the content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HunkStatus {
+    /// The context matched exactly at the expected line
+    Exact,
+    /// The context only matched approximately, `offset` lines away from the
+    /// expected line
+    Fuzzy { offset: isize, score: f64 },
+    /// The context matched exactly, but at line `to` (0-based) rather than
+    /// the expected line
+    Relocated { to: usize },
+}
+
+impl From<&HunkApplication> for HunkStatus {
+    fn from(hunk: &HunkApplication) -> Self {
+        if hunk.fuzzy {
+            HunkStatus::Fuzzy {
+                offset: hunk.offset(),
+                score: hunk.score,
+            }
+        } else if hunk.offset() != 0 {
+            HunkStatus::Relocated {
+                to: hunk.actual_start,
+            }
+        } else {
+            HunkStatus::Exact
+        }
+    }
+}
+
+/// The result of [`Patcher::apply_detailed`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplyReport {
+    /// The patched content
+    pub content: String,
+    /// How each chunk was matched, in patch order
+    pub hunks: Vec<HunkStatus>,
+}
+
 /// Trait for different patching algorithms
 pub trait PatchAlgorithm {
     /// Applies the patch to the provided content.
@@ -39,19 +704,20 @@ pub trait PatchAlgorithm {
 }
 
 /// Enum to specify which patching algorithm to use
-#[derive(Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub enum PatcherAlgorithm {
     Naive,
     #[default]
     Similar,
+    /// Ignores chunk line numbers and locates each chunk by an exact,
+    /// forward-only search for its context
+    Anchored,
 }
 
 impl PatchAlgorithm for Patcher {
     fn apply(&self, content: &str, reverse: bool) -> Result<String, Error> {
-        match self.algorithm {
-            PatcherAlgorithm::Naive => NaivePatcher::new(&self.patch).apply(content, reverse),
-            PatcherAlgorithm::Similar => SimilarPatcher::new(&self.patch).apply(content, reverse),
-        }
+        self.apply_tracked(content, reverse)
+            .map(|(result, _)| result)
     }
 }
 
@@ -79,4 +745,521 @@ mod tests {
         let similar_result = similar_patcher.apply(old_content, false).unwrap();
         assert_eq!(similar_result, new_content);
     }
+
+    #[test]
+    fn test_bom_round_trip() {
+        let old = "\u{feff}first\nsecond\nthird\n";
+        let new = "\u{feff}FIRST\nsecond\nthird\n";
+        let patch = Differ::new(old, new).generate();
+        assert_eq!(
+            patch.chunks[0].operations[..2],
+            [
+                Operation::Remove("first".to_string()),
+                Operation::Add("FIRST".to_string())
+            ]
+        );
+
+        for algorithm in [PatcherAlgorithm::Naive, PatcherAlgorithm::Similar] {
+            let patcher = Patcher::new_with_algorithm(patch.clone(), algorithm);
+            assert_eq!(patcher.apply(old, false).unwrap(), new);
+            assert_eq!(patcher.apply(new, true).unwrap(), old);
+        }
+        // The patch still applies to the file without its BOM
+        let patcher = Patcher::new(patch);
+        assert_eq!(
+            patcher.apply("first\nsecond\nthird\n", false).unwrap(),
+            "FIRST\nsecond\nthird\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_bytes() {
+        let patch = Differ::new("a\nb\nc\n", "a\nB\nc\n").generate();
+        let patcher = Patcher::new(patch);
+
+        assert_eq!(
+            patcher.apply_bytes(b"a\nb\nc\n", false).unwrap(),
+            b"a\nB\nc\n"
+        );
+        let latin1 = b"a\nb\nc\nna\xefve\n";
+        assert_eq!(
+            patcher.apply_bytes(latin1, false).unwrap(),
+            b"a\nB\nc\nna\xefve\n"
+        );
+    }
+
+    #[test]
+    fn test_fuzz_drops_outer_context() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -2,5 +2,5 @@
+ b
+ c
+-d
++D
+ e
+ f
+",
+        )
+        .unwrap();
+        // The outermost context lines were edited
+        let target = "a\nB\nc\nd\ne\nF\ng";
+
+        let exact = Patcher::new_with_algorithm(patch.clone(), PatcherAlgorithm::Similar).fuzz(0);
+        assert!(exact.apply(target, false).is_err());
+
+        let fuzzy = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar).fuzz(1);
+        let (result, hunks) = fuzzy.apply_tracked(target, false).unwrap();
+        assert_eq!(result, "a\nB\nc\nD\ne\nF\ng");
+        assert_eq!(hunks[0].actual_start, hunks[0].expected_start);
+        assert!(hunks[0].fuzzy);
+        // Three of the five expected lines were still matched
+        assert_eq!(hunks[0].score, 0.6);
+    }
+
+    #[test]
+    fn test_nul_delimited_records() {
+        let old = "a.txt\0b\nc.txt\0d.txt\0C:\\e\0";
+        let new = "a.txt\0b\nc2.txt\0d.txt\0C:\\e\r\n\0";
+        let patch = Differ::new(old, new).delimiter(b'\0').generate();
+        // Records with line breaks are escaped to stay on one patch line
+        assert_eq!(
+            patch.chunks[0].operations[1],
+            crate::Operation::Remove("b\\nc.txt".to_string())
+        );
+        assert!(
+            patch
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.operations)
+                .any(|op| op.line() == "C:\\\\e\\r\\n")
+        );
+
+        // The patch survives a round trip through its text
+        let reparsed = Patch::parse(&patch.to_string()).unwrap();
+        assert_eq!(reparsed.chunks, patch.chunks);
+        let patcher = Patcher::new(reparsed).delimiter(b'\0');
+        assert_eq!(patcher.apply(old, false).unwrap(), new);
+        assert_eq!(patcher.apply(new, true).unwrap(), old);
+    }
+
+    #[test]
+    fn test_overlapping_hunks() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ alpha
+-bravo
++BRAVO
+ charlie
+@@ -3,2 +3,2 @@
+ charlie
+-delta
++DELTA
+",
+        )
+        .unwrap();
+        let content = "alpha\nbravo\ncharlie\ndelta\necho\n";
+
+        for algorithm in [PatcherAlgorithm::Naive, PatcherAlgorithm::Similar] {
+            let result =
+                Patcher::new_with_algorithm(patch.clone(), algorithm).apply(content, false);
+            assert!(
+                matches!(
+                    result,
+                    Err(Error::OverlappingHunks {
+                        first: 0,
+                        second: 1
+                    })
+                ),
+                "{result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_hunks() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,2 +1,3 @@
+ a
++a2
+ b
+@@ -5,2 +6,1 @@
+ e
+-f
+@@ -8,2 +8,2 @@
+ h
+-i
++I
+",
+        )
+        .unwrap();
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\n";
+        let patcher = Patcher::new(patch);
+
+        assert_eq!(
+            patcher.apply_hunks(old, false, &[0, 2]).unwrap(),
+            "a\na2\nb\nc\nd\ne\nf\ng\nh\nI\n"
+        );
+        assert_eq!(
+            patcher.apply_hunks(old, false, &[1]).unwrap(),
+            "a\nb\nc\nd\ne\ng\nh\ni\n"
+        );
+
+        let new = patcher.apply(old, false).unwrap();
+        assert_eq!(
+            patcher.apply_hunks(&new, true, &[1]).unwrap(),
+            "a\na2\nb\nc\nd\ne\nf\ng\nh\nI\n"
+        );
+        assert!(patcher.apply_hunks(old, false, &[3]).is_err());
+    }
+
+    #[test]
+    fn test_apply_auto() {
+        let old = "a\nb\nc\n";
+        let new = "a\nB\nc\n";
+        let patcher = Patcher::new_with_algorithm(
+            Differ::new(old, new).generate(),
+            PatcherAlgorithm::Similar,
+        );
+
+        assert!(!patcher.is_applied(old));
+        assert!(patcher.is_applied(new));
+        assert_eq!(patcher.apply_auto(old).unwrap(), (new.to_string(), false));
+        assert_eq!(patcher.apply_auto(new).unwrap(), (old.to_string(), true));
+        assert!(matches!(
+            patcher.apply_auto("x\ny\nz\n"),
+            Err(Error::NotApplicable)
+        ));
+    }
+
+    #[test]
+    fn test_apply_auto_uses_the_patcher_algorithm() {
+        // Similar would relocate the chunk to the second copy, Naive only tries line 1
+        let patcher = Patcher::new_with_algorithm(
+            Differ::new("a\nb\nc\n", "a\nB\nc\n").generate(),
+            PatcherAlgorithm::Naive,
+        );
+        let content = "a\nB\nc\na\nb\nc\n";
+        assert!(patcher.is_applied(content));
+        assert_eq!(
+            patcher.apply_auto(content).unwrap(),
+            ("a\nb\nc\na\nb\nc\n".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn test_apply_auto_with_delimiter() {
+        let old = "a\0b\nc\0d\0";
+        let new = "a\0B\nc\0d\0";
+        let patcher =
+            Patcher::new(Differ::new(old, new).delimiter(b'\0').generate()).delimiter(b'\0');
+
+        assert!(!patcher.is_applied(old));
+        assert!(patcher.is_applied(new));
+        assert_eq!(patcher.apply_auto(old).unwrap(), (new.to_string(), false));
+        assert_eq!(patcher.apply_auto(new).unwrap(), (old.to_string(), true));
+    }
+
+    #[test]
+    fn test_apply_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "a\r\nb\r\nc\r\n").unwrap();
+
+        let patcher = Patcher::new(Differ::new("a\nb\nc\n", "a\nB\nc\n").generate());
+        patcher.apply_to_file(&path, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\r\nB\r\nc\r\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let missing = patcher.apply_to_file(dir.path().join("missing.txt"), false);
+        assert!(matches!(missing, Err(Error::FileNotFound { .. })));
+    }
+
+    #[test]
+    fn test_apply_to_file_keeps_mixed_line_endings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "a\r\nb\nc\nd\ne\nf\ng\n").unwrap();
+
+        let patcher =
+            Patcher::new(Differ::new("a\nb\nc\nd\ne\nf\ng\n", "a\nb\nc\nd\ne\nf\nG\n").generate());
+        patcher.apply_to_file(&path, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "a\r\nb\nc\nd\ne\nf\nG\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_to_file_with_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "a\nb\nc\n").unwrap();
+
+        let patcher = Patcher::new(Differ::new("a\nb\nc\n", "a\nB\nc\n").generate());
+        patcher
+            .apply_to_file_with_backup(&path, false, ".orig")
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nB\nc\n");
+        let backup = dir.path().join("file.txt.orig");
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "a\nb\nc\n");
+
+        // Applying again fails, which must not leave a backup
+        fs::remove_file(&backup).unwrap();
+        assert!(
+            patcher
+                .apply_to_file_with_backup(&path, false, ".orig")
+                .is_err()
+        );
+        assert!(!backup.exists());
+    }
+
+    #[test]
+    fn test_custom_line_similarity() {
+        let patch = Differ::new("alpha\nbeta\ngamma\n", "alpha\nBETA\ngamma\n").generate();
+        let target = "ALPHA\nbeta\nGAMMA\n";
+
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar);
+        assert!(patcher.apply(target, false).is_err());
+
+        let case_insensitive = |a: &str, b: &str| {
+            if a.eq_ignore_ascii_case(b) { 1.0 } else { 0.0 }
+        };
+        let patcher = patcher.similarity(Arc::new(case_insensitive));
+        assert_eq!(
+            patcher.apply(target, false).unwrap(),
+            "ALPHA\nBETA\nGAMMA\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_with_ranges() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB1\nB2\nc\nd\ne\nf\ng\nh\ni\nJ\nk\n";
+        let patcher = Patcher::new_with_algorithm(
+            Differ::new(old, new).context_lines(1).generate(),
+            PatcherAlgorithm::Similar,
+        );
+
+        let (result, ranges) = patcher.apply_with_ranges(old, false).unwrap();
+        assert_eq!(result, new);
+        assert_eq!(ranges, vec![1..3, 10..12]);
+
+        // Reversing inserts the lines the patch removed
+        let (result, ranges) = patcher.apply_with_ranges(new, true).unwrap();
+        assert_eq!(result, old);
+        assert_eq!(ranges, vec![1..2, 9..10]);
+
+        // Found two lines further down, past two unrelated lines
+        let shifted = format!("x\ny\n{old}");
+        let (_, ranges) = patcher.apply_with_ranges(&shifted, false).unwrap();
+        assert_eq!(ranges, vec![3..5, 12..14]);
+    }
+
+    #[test]
+    fn test_normalize_context() {
+        let patch = Differ::new(
+            "fn main() {\n    old();\n}\n",
+            "fn main() {\n    new();\n}\n",
+        )
+        .generate();
+        // The context was reformatted after the patch was made
+        let content = "fn main()  {\n    old();\n}\n";
+
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar);
+        assert_eq!(
+            patcher.apply(content, false).unwrap(),
+            "fn main()  {\n    new();\n}\n"
+        );
+        assert_eq!(
+            patcher
+                .normalize_context(true)
+                .apply(content, false)
+                .unwrap(),
+            "fn main() {\n    new();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_cow() {
+        let content = "a\nb\nc";
+        let patcher = Patcher::new(Differ::new(content, content).generate());
+        assert!(matches!(
+            patcher.apply_cow(content, false).unwrap(),
+            Cow::Borrowed(borrowed) if borrowed == content
+        ));
+
+        let patcher = Patcher::new(Differ::new(content, "a\nB\nc").generate());
+        assert!(matches!(
+            patcher.apply_cow(content, false).unwrap(),
+            Cow::Owned(owned) if owned == "a\nB\nc"
+        ));
+        assert!(patcher.apply_cow("x\ny\nz", false).is_err());
+    }
+
+    #[test]
+    fn test_verify_context() {
+        let patch = Patch::parse(
+            "\
+--- a/file
++++ b/file
+@@ -2,3 +2,3 @@
+ {
+-one
++ONE
+ }
+@@ -7,3 +7,3 @@
+ {
+-two
++TWO
+ }
+",
+        )
+        .unwrap();
+        // The second chunk's region changed, and its context also fits further down
+        let content = "a\n{\none\n}\ngap\ngap\n{\nchanged\n}\nz\n{\ntwo\n}\n";
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Anchored).fuzz(0);
+        assert_eq!(
+            patcher.apply(content, false).unwrap(),
+            "a\n{\nONE\n}\ngap\ngap\n{\nchanged\n}\nz\n{\nTWO\n}\n"
+        );
+
+        // Moved 4 lines down, the line before it should be the first chunk's `}`
+        let verified = patcher.clone().verify_context(3);
+        assert!(matches!(
+            verified.apply(content, false),
+            Err(Error::ContextMismatch { line_num: 8, .. })
+        ));
+        // At its expected position the surrounding lines agree with the patch
+        let content = content.replace("changed", "two");
+        assert_eq!(
+            verified.apply(&content, false).unwrap(),
+            "a\n{\nONE\n}\ngap\ngap\n{\nTWO\n}\nz\n{\ntwo\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_ignore_context_matching() {
+        let patch = Differ::new(
+            "// Copyright 2023\nfn main() {\n    old();\n}\n",
+            "// Copyright 2023\nfn main() {\n    new();\n}\n",
+        )
+        .generate();
+        let content = "// Copyright 2025\nfn main() {\n    old();\n}\n";
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Anchored).fuzz(0);
+        assert!(patcher.apply(content, false).is_err());
+
+        let patcher = patcher.ignore_context_matching(|line| line.starts_with("// Copyright"));
+        assert_eq!(
+            patcher.apply(content, false).unwrap(),
+            "// Copyright 2025\nfn main() {\n    new();\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let patch = Differ::new(
+            "fn main() {\n\tif x {\n\t\told();\n\t}\n}\n",
+            "fn main() {\n\tif x {\n\t\tnew();\n\t}\n}\n",
+        )
+        .context_lines(2)
+        .generate();
+        // The same code, indented with four spaces
+        let content = "fn main() {\n    if x {\n        old();\n    }\n}\n";
+
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Anchored).fuzz(0);
+        assert!(patcher.apply(content, false).is_err());
+        assert_eq!(
+            patcher.tab_width(4).apply(content, false).unwrap(),
+            "fn main() {\n    if x {\n\t\tnew();\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_trailing_context_only_chunk() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+@@ -20,2 +20,2 @@
+ t
+ u
+",
+        )
+        .unwrap();
+        for algorithm in [PatcherAlgorithm::Naive, PatcherAlgorithm::Similar] {
+            let patcher = Patcher::new_with_algorithm(patch.clone(), algorithm);
+            assert_eq!(
+                patcher.apply("a\nb\nc\nd\n", false).unwrap(),
+                "a\nB\nc\nd\n"
+            );
+            assert_eq!(patcher.apply("a\nB\nc\nd\n", true).unwrap(), "a\nb\nc\nd\n");
+        }
+
+        // Inside the content, the context of such a chunk is still checked
+        let mut inside = patch.clone();
+        inside.chunks[1].old_start = 2;
+        inside.chunks[1].new_start = 2;
+        assert!(Patcher::new(inside).apply("a\nb\nc\nd\n", false).is_err());
+    }
+
+    #[test]
+    fn test_apply_detailed() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+@@ -6,3 +6,3 @@
+ the sixth line
+-g
++G
+ h
+",
+        )
+        .unwrap();
+        let patcher = Patcher::new_with_algorithm(patch, PatcherAlgorithm::Similar);
+
+        let report = patcher
+            .apply_detailed("a\nb\nc\nd\ne\nthe sixth line\ng\nh\n", false)
+            .unwrap();
+        assert_eq!(report.content, "a\nB\nc\nd\ne\nthe sixth line\nG\nh\n");
+        assert_eq!(report.hunks, vec![HunkStatus::Exact, HunkStatus::Exact]);
+
+        // Two extra lines before the second chunk
+        let report = patcher
+            .apply_detailed("a\nb\nc\nd\ne\nx\ny\nthe sixth line\ng\nh\n", false)
+            .unwrap();
+        assert_eq!(report.hunks[1], HunkStatus::Relocated { to: 7 });
+
+        // The context of the second chunk was edited
+        let report = patcher
+            .apply_detailed("a\nb\nc\nd\ne\nthe 6th line\ng\nh\n", false)
+            .unwrap();
+        assert_eq!(report.content, "a\nB\nc\nd\ne\nthe 6th line\nG\nh\n");
+        match report.hunks[1] {
+            HunkStatus::Fuzzy { offset, score } => {
+                assert_eq!(offset, 0);
+                assert!(score > 0.0 && score < 1.0);
+            }
+            status => panic!("expected a fuzzy match, got {status:?}"),
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::patcher::PatchAlgorithm;
+use crate::patcher::{HunkApplication, PatchAlgorithm};
 use crate::{Error, Operation, Patch};
 use std::borrow::Cow;
 
@@ -16,17 +16,44 @@ impl<'a> NaivePatcher<'a> {
 
 impl PatchAlgorithm for NaivePatcher<'_> {
     fn apply(&self, content: &str, reverse: bool) -> Result<String, Error> {
+        self.apply_tracked(content, reverse)
+            .map(|(result, _)| result)
+    }
+}
+
+impl NaivePatcher<'_> {
+    /// Applies the patch and reports where each chunk was applied.
+    /// The naive patcher never relocates chunks, so every chunk applies at its expected line.
+    pub(crate) fn apply_tracked(
+        &self,
+        content: &str,
+        reverse: bool,
+    ) -> Result<(String, Vec<HunkApplication>), Error> {
         let lines: Vec<&str> = content.lines().collect();
         let mut result = String::with_capacity(content.len());
+        let mut hunks = Vec::with_capacity(self.patch.chunks.len());
         let mut current_line_index = 0;
         let mut first_line = true;
 
-        for chunk in &self.patch.chunks {
+        for (index, chunk) in self.patch.chunks.iter().enumerate() {
             let start_line = if reverse {
                 chunk.new_start
             } else {
                 chunk.old_start
             };
+            if start_line < current_line_index {
+                return Err(Error::OverlappingHunks {
+                    first: index - 1,
+                    second: index,
+                });
+            }
+            hunks.push(HunkApplication {
+                index,
+                expected_start: start_line,
+                actual_start: start_line,
+                fuzzy: false,
+                score: 1.0,
+            });
             let operations = if reverse {
                 Cow::Owned(self.reverse_operations(&chunk.operations))
             } else {
@@ -63,12 +90,11 @@ impl PatchAlgorithm for NaivePatcher<'_> {
 
                         let actual_line = lines[current_line_index];
                         if actual_line != expected_line {
-                            return Err(Error::ApplyError(format!(
-                                "Context mismatch at line {}: expected '{}', got '{}'",
-                                current_line_index + 1,
-                                expected_line,
-                                actual_line
-                            )));
+                            return Err(Error::ContextMismatch {
+                                line_num: current_line_index + 1,
+                                expected: expected_line.clone(),
+                                actual: actual_line.to_string(),
+                            });
                         }
 
                         if !first_line {
@@ -100,12 +126,11 @@ impl PatchAlgorithm for NaivePatcher<'_> {
                         // matches what we expect to remove
                         let actual_line = lines[current_line_index];
                         if actual_line != expected_line {
-                            return Err(Error::ApplyError(format!(
-                                "Remove line mismatch at line {}: expected to remove '{}', but found '{}'",
-                                current_line_index + 1,
-                                expected_line,
-                                actual_line
-                            )));
+                            return Err(Error::RemovedLineMismatch {
+                                line_num: current_line_index + 1,
+                                expected: expected_line.clone(),
+                                actual: actual_line.to_string(),
+                            });
                         }
 
                         current_line_index += 1;
@@ -114,6 +139,8 @@ impl PatchAlgorithm for NaivePatcher<'_> {
             }
         }
 
+        let reached_eof = current_line_index == lines.len();
+
         // Copy remaining lines
         while current_line_index < lines.len() {
             if !first_line {
@@ -126,16 +153,21 @@ impl PatchAlgorithm for NaivePatcher<'_> {
             current_line_index += 1;
         }
 
-        // Ensure final newline if original content had one
-        if content.ends_with('\n') && !result.is_empty() && !result.ends_with('\n') {
+        // The patch decides the trailing newline when its last chunk reached the end
+        // of the file; otherwise keep the state of the original content
+        let trailing_newline = match self.patch.newline_at_eof(reverse) {
+            Some(newline) if reached_eof => newline,
+            // Nothing to preserve when the content is created from scratch
+            None if content.is_empty() => true,
+            _ => content.ends_with('\n'),
+        };
+        if trailing_newline && !first_line {
             result.push('\n');
         }
 
-        Ok(result)
+        Ok((result, hunks))
     }
-}
 
-impl NaivePatcher<'_> {
     /// Reverses the operations (Add -> Remove, Remove -> Add) for applying a patch in reverse.
     fn reverse_operations(&self, operations: &[Operation]) -> Vec<Operation> {
         operations
@@ -203,16 +235,37 @@ mod tests {
         let result = patcher.apply(bad_content, false);
         println!("result: {:?}", result);
 
-        assert!(result.is_err());
-        if let Err(Error::ApplyError(msg)) = result {
-            // Check for either type of mismatch message
-            assert!(
-                msg.contains("Context mismatch") || msg.contains("Remove line mismatch"),
-                "Error message '{}' doesn't contain expected mismatch text",
-                msg
-            );
-        } else {
-            panic!("Expected ApplyError");
+        match result {
+            Err(Error::RemovedLineMismatch {
+                line_num,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(line_num, 2);
+                assert_eq!(expected, "line2");
+                assert_eq!(actual, "bad line");
+            }
+            other => panic!("Expected RemovedLineMismatch, got {:?}", other),
+        }
+
+        // A changed context line is reported as a context mismatch
+        let result = patcher.apply(
+            "bad line
+line2
+line3",
+            false,
+        );
+        match result {
+            Err(Error::ContextMismatch {
+                line_num,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(line_num, 1);
+                assert_eq!(expected, "line1");
+                assert_eq!(actual, "bad line");
+            }
+            other => panic!("Expected ContextMismatch, got {:?}", other),
         }
     }
 }
@@ -1,4 +1,5 @@
-use crate::patcher::PatchAlgorithm;
+use crate::patch::context_run_lengths;
+use crate::patcher::{DefaultSimilarity, HunkApplication, LineSimilarity, PatchAlgorithm};
 use crate::{Error, Operation, Patch};
 use levenshtein::levenshtein;
 use std::borrow::Cow;
@@ -13,6 +14,19 @@ const LENIENT_MATCH_THRESHOLD: f64 = 0.6;
 /// location to apply patches when exact context doesn't match.
 pub struct SimilarPatcher<'a> {
     patch: &'a Patch,
+    /// Locate chunks only by an exact forward search for their context
+    anchored: bool,
+    /// GNU `patch --fuzz` style matching: at most this many leading/trailing
+    /// context lines may be ignored, the rest must match exactly
+    fuzz: Option<usize>,
+    /// Scores how closely an actual line matches an expected context line
+    similarity: &'a dyn LineSimilarity,
+    /// Write the patch's context lines instead of the matched lines of the content
+    normalize_context: bool,
+    /// Compare lines with tabs expanded to spaces at stops this many columns apart
+    tab_width: Option<usize>,
+    /// Context lines of the patch for which this returns `true` match any line
+    ignore_context: Option<&'a dyn Fn(&str) -> bool>,
 }
 
 /// Represents the result of a fuzzy match attempt.
@@ -24,45 +38,149 @@ struct MatchResult {
 
 impl<'a> SimilarPatcher<'a> {
     pub fn new(patch: &'a Patch) -> Self {
-        Self { patch }
+        Self {
+            patch,
+            anchored: false,
+            fuzz: None,
+            similarity: &DefaultSimilarity,
+            normalize_context: false,
+            tab_width: None,
+            ignore_context: None,
+        }
+    }
+
+    /// Creates a patcher that ignores the chunk header line numbers and locates
+    /// each chunk by scanning forward from the end of the previous one for an
+    /// exact match of its context. Fails if the context cannot be found.
+    pub fn new_anchored(patch: &'a Patch) -> Self {
+        Self {
+            patch,
+            anchored: true,
+            fuzz: None,
+            similarity: &DefaultSimilarity,
+            normalize_context: false,
+            tab_width: None,
+            ignore_context: None,
+        }
+    }
+
+    /// Replaces the similarity-based matching with GNU `patch --fuzz` semantics:
+    /// each chunk must match exactly after ignoring at most `fuzz` leading and
+    /// trailing context lines. Fuzz 0 only accepts exact context matches.
+    pub fn fuzz(mut self, fuzz: usize) -> Self {
+        self.fuzz = Some(fuzz);
+        self
+    }
+
+    /// Uses `similarity` instead of the default Levenshtein-based score when
+    /// matching context lines approximately.
+    pub fn similarity(mut self, similarity: &'a dyn LineSimilarity) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    /// When a context line only matches approximately, writes the line as the
+    /// patch expects it rather than as it is in the content.
+    pub fn normalize_context(mut self, normalize: bool) -> Self {
+        self.normalize_context = normalize;
+        self
+    }
+
+    /// Treats lines as equal when they are once tabs are expanded to spaces, with
+    /// tab stops every `width` columns, e.g. tab-indented context against
+    /// space-indented content. The content's own lines are written as they are.
+    pub fn tab_width(mut self, width: usize) -> Self {
+        self.tab_width = Some(width.max(1));
+        self
+    }
+
+    /// Lets each line of the patch for which `predicate` returns `true` match
+    /// whatever line the content has in its place, e.g. a volatile license header.
+    /// The line still takes up its position; the content's line is kept.
+    pub fn ignore_context_matching(mut self, predicate: &'a dyn Fn(&str) -> bool) -> Self {
+        self.ignore_context = Some(predicate);
+        self
     }
 }
 
 impl PatchAlgorithm for SimilarPatcher<'_> {
     fn apply(&self, content: &str, reverse: bool) -> Result<String, Error> {
+        self.apply_tracked(content, reverse)
+            .map(|(result, _)| result)
+    }
+}
+
+impl SimilarPatcher<'_> {
+    /// Applies the patch and reports where each chunk ended up.
+    pub(crate) fn apply_tracked(
+        &self,
+        content: &str,
+        reverse: bool,
+    ) -> Result<(String, Vec<HunkApplication>), Error> {
         let lines: Vec<&str> = content.lines().collect();
         let estimated_capacity = content
             .len()
             .saturating_add(self.estimate_patch_size_delta());
         let mut result = String::with_capacity(estimated_capacity);
+        let mut hunks = Vec::with_capacity(self.patch.chunks.len());
         let mut current_line_index = 0;
         let mut first_line_written = true;
 
-        for chunk in &self.patch.chunks {
-            let (expected_start_line_one_based, operations_cow) =
-                self.prepare_chunk_operations(chunk, reverse);
+        for (index, chunk) in self.patch.chunks.iter().enumerate() {
+            let (expected_start, operations_cow) = self.prepare_chunk_operations(chunk, reverse);
             let operations = operations_cow.as_ref();
 
-            // Ensure expected_start_line is 0-based for find_chunk_start_position
-            let expected_start_line_zero_based = expected_start_line_one_based.saturating_sub(1);
-
-            let actual_start_line = self.find_chunk_start_position(
-                &lines,
-                current_line_index,
-                expected_start_line_zero_based,
-                operations,
-            )?;
+            let locate = |search_start_index: usize| match self.fuzz {
+                Some(max_fuzz) => self.find_with_fuzz(
+                    &lines,
+                    search_start_index,
+                    expected_start,
+                    operations,
+                    max_fuzz,
+                ),
+                None => self
+                    .find_chunk_start_position(
+                        &lines,
+                        search_start_index,
+                        expected_start,
+                        operations,
+                    )
+                    .map(|found| (found, 0, operations)),
+            };
+            let (found, dropped, operations) = match locate(current_line_index) {
+                Ok(located) => located,
+                Err(e) => {
+                    // Report a chunk that only matches inside the previous one as an overlap
+                    let previous = hunks.last().map(|hunk: &HunkApplication| hunk.actual_start);
+                    return Err(match previous.map(locate) {
+                        Some(Ok((found, _, _))) if found.position < current_line_index => {
+                            Error::OverlappingHunks {
+                                first: index - 1,
+                                second: index,
+                            }
+                        }
+                        _ => e,
+                    });
+                }
+            };
+            hunks.push(HunkApplication {
+                index,
+                expected_start,
+                actual_start: found.position.saturating_sub(dropped),
+                fuzzy: found.score < 1.0,
+                score: found.score,
+            });
 
             self.append_lines_until(
                 &lines,
                 current_line_index,
-                actual_start_line,
+                found.position,
                 &mut result,
                 &mut first_line_written,
             )?;
 
             // Update current line index
-            current_line_index = actual_start_line;
+            current_line_index = found.position;
 
             current_line_index = self.apply_chunk_operations_to_string(
                 &lines,
@@ -73,6 +191,8 @@ impl PatchAlgorithm for SimilarPatcher<'_> {
             )?;
         }
 
+        let reached_eof = current_line_index == lines.len();
+
         self.append_remaining_lines(
             &lines,
             current_line_index,
@@ -80,16 +200,21 @@ impl PatchAlgorithm for SimilarPatcher<'_> {
             &mut first_line_written,
         );
 
-        // Ensure final newline is preserved if the original content had one
-        if content.ends_with('\n') && !result.is_empty() && !result.ends_with('\n') {
+        // The patch decides the trailing newline when its last chunk reached the end
+        // of the file; otherwise keep the state of the original content
+        let trailing_newline = match self.patch.newline_at_eof(reverse) {
+            Some(newline) if reached_eof => newline,
+            // Nothing to preserve when the content is created from scratch
+            None if content.is_empty() => true,
+            _ => content.ends_with('\n'),
+        };
+        if trailing_newline && !first_line_written {
             result.push('\n');
         }
 
-        Ok(result)
+        Ok((result, hunks))
     }
-}
 
-impl SimilarPatcher<'_> {
     /// Estimates the change in total content size based on Add/Remove operations.
     fn estimate_patch_size_delta(&self) -> usize {
         self.patch.chunks.iter().fold(0, |acc, c| {
@@ -206,24 +331,24 @@ impl SimilarPatcher<'_> {
                         });
                     }
                     let actual_line = lines[current_line_index];
-                    if !Self::lines_match_flexibly(
-                        actual_line,
-                        expected_line,
-                        FUZZY_MATCH_THRESHOLD,
-                    ) {
-                        return Err(Error::ApplyError(format!(
-                            "Context mismatch at line {}: Expected '{}', got '{}'",
-                            current_line_index + 1,
-                            expected_line,
-                            actual_line
-                        )));
+                    if !self.lines_match_flexibly(actual_line, expected_line, FUZZY_MATCH_THRESHOLD)
+                    {
+                        return Err(Error::ContextMismatch {
+                            line_num: current_line_index + 1,
+                            expected: expected_line.clone(),
+                            actual: actual_line.to_string(),
+                        });
                     }
                     if !*first_line_written {
                         result.push('\n');
                     } else {
                         *first_line_written = false;
                     }
-                    result.push_str(actual_line);
+                    result.push_str(if self.normalize_context {
+                        expected_line
+                    } else {
+                        actual_line
+                    });
                     current_line_index += 1;
                 }
                 Operation::Add(line_to_add) => {
@@ -247,10 +372,45 @@ impl SimilarPatcher<'_> {
         Ok(current_line_index)
     }
 
+    /// Whether two lines are the same, up to tab expansion with a
+    /// [`tab_width`](Self::tab_width).
+    fn same_line(&self, actual: &str, expected: &str) -> bool {
+        actual == expected
+            || self.is_ignored(expected)
+            || self
+                .tab_width
+                .is_some_and(|width| expand_tabs(actual, width) == expand_tabs(expected, width))
+    }
+
+    /// Whether `expected` is a line of the patch that matches any line, see
+    /// [`ignore_context_matching`](Self::ignore_context_matching).
+    fn is_ignored(&self, expected: &str) -> bool {
+        self.ignore_context
+            .is_some_and(|predicate| predicate(expected))
+    }
+
+    /// The configured similarity of two lines, `1.0` if `expected` is ignored.
+    fn score(&self, actual: &str, expected: &str) -> f64 {
+        if self.is_ignored(expected) {
+            1.0
+        } else {
+            self.similarity.score(actual, expected)
+        }
+    }
+
+    /// Whether `lines` starts with the `expected` lines, compared with `same_line`.
+    fn same_lines(&self, lines: &[&str], expected: &[&str]) -> bool {
+        lines.len() >= expected.len()
+            && lines
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| self.same_line(actual, expected))
+    }
+
     /// Determines if two lines match with some flexibility, allowing for whitespace differences.
-    fn lines_match_flexibly(actual: &str, expected: &str, fuzzy_threshold: f64) -> bool {
+    fn lines_match_flexibly(&self, actual: &str, expected: &str, fuzzy_threshold: f64) -> bool {
         // Check exact match first (common case, make it fast)
-        if actual == expected {
+        if self.same_line(actual, expected) {
             return true;
         }
 
@@ -262,29 +422,45 @@ impl SimilarPatcher<'_> {
         }
 
         // Finally check with similarity
-        similarity_score(actual, expected) >= fuzzy_threshold
+        self.score(actual, expected) >= fuzzy_threshold
     }
 
     /// Finds the best position to start applying a chunk.
+    ///
+    /// The chunk is located by the lines it expects to find in the content
+    /// (context and removed lines, in order). A score of `1.0` means every
+    /// line matched exactly; lower scores indicate fuzzy matching was needed.
+    ///
+    /// `expected_start_line` is the chunk's 0-based start line and is tried
+    /// first. The search around it includes a match that ends on the last line
+    /// of the content. Positions before `search_start_index`, i.e. lines an
+    /// earlier chunk already consumed, are never used, not even as a fallback.
     fn find_chunk_start_position(
         &self,
         lines: &[&str],
         search_start_index: usize,
         expected_start_line: usize,
         operations: &[Operation],
-    ) -> Result<usize, Error> {
-        // Extract context lines for better fuzzy matching
+    ) -> Result<MatchResult, Error> {
+        // The lines this chunk expects to find, in file order
         let context_lines: Vec<&str> = operations
             .iter()
             .filter_map(|op| match op {
-                Operation::Context(line) => Some(line.as_str()),
-                _ => None,
+                Operation::Context(line) | Operation::Remove(line) => Some(line.as_str()),
+                Operation::Add(_) => None,
             })
             .collect();
 
         if context_lines.is_empty() {
             // No context lines, just use the expected position
-            return Ok(expected_start_line);
+            return Ok(MatchResult {
+                position: expected_start_line.max(search_start_index),
+                score: 1.0,
+            });
+        }
+
+        if self.anchored {
+            return self.find_anchored_position(lines, search_start_index, &context_lines);
         }
 
         // Try to find the best match for this chunk
@@ -296,6 +472,94 @@ impl SimilarPatcher<'_> {
         )
     }
 
+    /// Locates a chunk with GNU `patch --fuzz` semantics.
+    ///
+    /// Tries fuzz levels `0..=max_fuzz`; at level `f` up to `f` leading and trailing
+    /// context operations are dropped and the remaining context and removed lines
+    /// must match exactly. The match nearest to the expected line wins (the first one
+    /// at or after `search_start_index` in anchored mode). Returns the match of the
+    /// trimmed operations, the number of dropped leading lines and the trimmed operations.
+    /// The score is the fraction of the context and removed lines left after trimming.
+    fn find_with_fuzz<'o>(
+        &self,
+        lines: &[&str],
+        search_start_index: usize,
+        expected_start_line: usize,
+        operations: &'o [Operation],
+        max_fuzz: usize,
+    ) -> Result<(MatchResult, usize, &'o [Operation]), Error> {
+        let (leading, trailing) = context_run_lengths(operations);
+        let all_lines = operations.iter().filter(|op| !op.is_add()).count();
+
+        for fuzz in 0..=max_fuzz {
+            let drop_front = fuzz.min(leading);
+            let drop_back = fuzz.min(trailing).min(operations.len() - drop_front);
+            let trimmed = &operations[drop_front..operations.len() - drop_back];
+            let expected_lines: Vec<&str> = trimmed
+                .iter()
+                .filter_map(|op| match op {
+                    Operation::Context(line) | Operation::Remove(line) => Some(line.as_str()),
+                    Operation::Add(_) => None,
+                })
+                .collect();
+            let expected_position = (expected_start_line + drop_front).max(search_start_index);
+            let last_start = (lines.len() + 1).saturating_sub(expected_lines.len());
+            let matches_at = |pos: usize| self.same_lines(&lines[pos..], &expected_lines);
+
+            let position = if expected_lines.is_empty() {
+                Some(expected_position.min(lines.len()))
+            } else if self.anchored {
+                (search_start_index..last_start).find(|&pos| matches_at(pos))
+            } else {
+                (search_start_index..last_start)
+                    .filter(|&pos| matches_at(pos))
+                    .min_by_key(|&pos| pos.abs_diff(expected_position))
+            };
+            if let Some(position) = position {
+                // The share of context and removed lines that is still matched
+                let score = if all_lines == 0 {
+                    1.0
+                } else {
+                    expected_lines.len() as f64 / all_lines as f64
+                };
+                return Ok((MatchResult { position, score }, drop_front, trimmed));
+            }
+            if drop_front < fuzz && drop_back < fuzz {
+                // Nothing more can be dropped at higher fuzz levels
+                break;
+            }
+        }
+
+        Err(Error::ApplyError(format!(
+            "Failed to find matching context for chunk expected at line {} with fuzz {}",
+            expected_start_line + 1,
+            max_fuzz
+        )))
+    }
+
+    /// Finds the first exact occurrence of the context lines at or after `search_start_index`.
+    fn find_anchored_position(
+        &self,
+        lines: &[&str],
+        search_start_index: usize,
+        context_lines: &[&str],
+    ) -> Result<MatchResult, Error> {
+        let last_start = (lines.len() + 1).saturating_sub(context_lines.len());
+        (search_start_index..last_start)
+            .find(|&i| self.same_lines(&lines[i..], context_lines))
+            .map(|position| MatchResult {
+                position,
+                score: 1.0,
+            })
+            .ok_or_else(|| {
+                Error::ApplyError(format!(
+                    "Failed to find context '{}' at or after line {}",
+                    context_lines[0],
+                    search_start_index + 1
+                ))
+            })
+    }
+
     /// Finds the best position to match the context lines.
     fn find_best_match_position(
         &self,
@@ -303,33 +567,35 @@ impl SimilarPatcher<'_> {
         search_start_index: usize,
         expected_start_line: usize,
         context_lines: &[&str],
-    ) -> Result<usize, Error> {
-        // Try exact match at the expected position first
-        if expected_start_line < lines.len() {
-            let expected_end = expected_start_line + context_lines.len();
-            if expected_end <= lines.len() {
-                let mut exact_match = true;
-                for (i, context) in context_lines.iter().enumerate() {
-                    let line_index = expected_start_line + i;
-                    if !Self::lines_match_flexibly(
-                        lines[line_index],
-                        context,
-                        FUZZY_MATCH_THRESHOLD,
-                    ) {
-                        exact_match = false;
-                        break;
-                    }
-                }
-                if exact_match {
-                    return Ok(expected_start_line);
-                }
+    ) -> Result<MatchResult, Error> {
+        // Try a match at the expected position first
+        if expected_start_line >= search_start_index
+            && expected_start_line + context_lines.len() <= lines.len()
+        {
+            let candidate = &lines[expected_start_line..expected_start_line + context_lines.len()];
+            if candidate
+                .iter()
+                .zip(context_lines)
+                .all(|(actual, expected)| {
+                    self.lines_match_flexibly(actual, expected, FUZZY_MATCH_THRESHOLD)
+                })
+            {
+                let total: f64 = candidate
+                    .iter()
+                    .zip(context_lines)
+                    .map(|(actual, expected)| self.score(actual, expected))
+                    .sum();
+                return Ok(MatchResult {
+                    position: expected_start_line,
+                    score: total / context_lines.len() as f64,
+                });
             }
         }
 
         // Define search range: try an expanding range around the expected position
         let min_search = search_start_index.max(expected_start_line.saturating_sub(SEARCH_RANGE));
         let max_search = min(
-            lines.len().saturating_sub(context_lines.len()),
+            (lines.len() + 1).saturating_sub(context_lines.len()),
             expected_start_line.saturating_add(SEARCH_RANGE),
         );
 
@@ -338,25 +604,31 @@ impl SimilarPatcher<'_> {
         if let Some(position) =
             self.find_exact_context_match(lines, context_lines, search_range.clone())
         {
-            return Ok(position);
+            return Ok(MatchResult {
+                position,
+                score: 1.0,
+            });
         }
 
         // Next, try fuzzy matching
-        if let Some(position) =
+        if let Some(found) =
             self.find_fuzzy_context_match(lines, context_lines, search_range.clone())
         {
-            return Ok(position);
+            return Ok(found);
         }
 
         // Finally, try partial matching on a subset of context
-        if let Some(position) = self.find_partial_context_match(lines, context_lines, search_range)
-        {
-            return Ok(position);
+        if let Some(found) = self.find_partial_context_match(lines, context_lines, search_range) {
+            return Ok(found);
         }
 
-        // If we still haven't found a good match, use the expected position but warn
-        if expected_start_line < lines.len() {
-            Ok(expected_start_line)
+        // If we still haven't found a good match, use the expected position and let
+        // the per-line verification decide
+        if expected_start_line < lines.len() && expected_start_line >= search_start_index {
+            Ok(MatchResult {
+                position: expected_start_line,
+                score: 0.0,
+            })
         } else {
             Err(Error::ApplyError(format!(
                 "Failed to find matching context for chunk expected at line {}",
@@ -377,15 +649,7 @@ impl SimilarPatcher<'_> {
                 continue;
             }
 
-            let mut match_found = true;
-            for (i, &context_line) in context_lines.iter().enumerate() {
-                if lines[start_idx + i] != context_line {
-                    match_found = false;
-                    break;
-                }
-            }
-
-            if match_found {
+            if self.same_lines(&lines[start_idx..], context_lines) {
                 return Some(start_idx);
             }
         }
@@ -398,7 +662,7 @@ impl SimilarPatcher<'_> {
         lines: &[&str],
         context_lines: &[&str],
         search_range: std::ops::Range<usize>,
-    ) -> Option<usize> {
+    ) -> Option<MatchResult> {
         let mut best_match: Option<MatchResult> = None;
 
         for start_idx in search_range {
@@ -411,7 +675,7 @@ impl SimilarPatcher<'_> {
 
             for (i, &context_line) in context_lines.iter().enumerate() {
                 let line_idx = start_idx + i;
-                let score = similarity_score(lines[line_idx], context_line);
+                let score = self.score(lines[line_idx], context_line);
 
                 if score < FUZZY_MATCH_THRESHOLD {
                     all_above_threshold = false;
@@ -439,7 +703,7 @@ impl SimilarPatcher<'_> {
             }
         }
 
-        best_match.map(|m| m.position)
+        best_match
     }
 
     /// Tries to find a partial match for a subset of the context lines.
@@ -448,7 +712,7 @@ impl SimilarPatcher<'_> {
         lines: &[&str],
         context_lines: &[&str],
         search_range: std::ops::Range<usize>,
-    ) -> Option<usize> {
+    ) -> Option<MatchResult> {
         // Try with just the first few and last few context lines for a strong partial match
         let context_len = context_lines.len();
         if context_len < 2 {
@@ -462,9 +726,12 @@ impl SimilarPatcher<'_> {
                     continue;
                 }
 
-                let score = similarity_score(lines[start_idx], context_lines[0]);
+                let score = self.score(lines[start_idx], context_lines[0]);
                 if score >= LENIENT_MATCH_THRESHOLD {
-                    return Some(start_idx);
+                    return Some(MatchResult {
+                        position: start_idx,
+                        score,
+                    });
                 }
             }
             return None;
@@ -482,7 +749,7 @@ impl SimilarPatcher<'_> {
             let mut begin_score = 0.0;
             let begin_count = 2.min(context_len);
             for i in 0..begin_count {
-                begin_score += similarity_score(lines[start_idx + i], context_lines[i]);
+                begin_score += self.score(lines[start_idx + i], context_lines[i]);
             }
             begin_score /= begin_count as f64;
 
@@ -492,7 +759,7 @@ impl SimilarPatcher<'_> {
             for i in 0..end_count {
                 let context_idx = context_len - 1 - i;
                 let line_idx = start_idx + context_len - 1 - i;
-                end_score += similarity_score(lines[line_idx], context_lines[context_idx]);
+                end_score += self.score(lines[line_idx], context_lines[context_idx]);
             }
             end_score /= end_count as f64;
 
@@ -515,13 +782,42 @@ impl SimilarPatcher<'_> {
             }
         }
 
-        best_match.map(|m| m.position)
+        best_match
     }
 }
 
-/// Normalizes whitespace in a string, collapsing multiple spaces into one.
-fn normalize_whitespace(text: &str) -> Cow<str> {
-    if !text.contains("  ") && !text.contains('\t') {
+/// Replaces each tab with the spaces up to the next multiple of `width` columns.
+fn expand_tabs(text: &str, width: usize) -> Cow<'_, str> {
+    if !text.contains('\t') {
+        return Cow::Borrowed(text);
+    }
+    let mut result = String::with_capacity(text.len() + width);
+    let mut column = 0;
+    for c in text.chars() {
+        if c == '\t' {
+            let spaces = width - column % width;
+            result.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            result.push(c);
+            column += 1;
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// Normalizes whitespace in a string, collapsing each run of Unicode
+/// whitespace (tabs, no-break and ideographic spaces, ...) into one space.
+pub(crate) fn normalize_whitespace(text: &str) -> Cow<'_, str> {
+    let mut chars = text.chars().peekable();
+    let mut normalized = true;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && (c != ' ' || chars.peek().is_some_and(|n| n.is_whitespace())) {
+            normalized = false;
+            break;
+        }
+    }
+    if normalized {
         return Cow::Borrowed(text);
     }
 
@@ -544,7 +840,7 @@ fn normalize_whitespace(text: &str) -> Cow<str> {
 }
 
 /// Calculates a similarity score between two strings based on Levenshtein distance.
-fn similarity_score(a: &str, b: &str) -> f64 {
+pub(crate) fn similarity_score(a: &str, b: &str) -> f64 {
     // Check for exact match
     if a == b {
         return 1.0;
@@ -636,6 +932,104 @@ mod tests {
         assert_eq!(result.unwrap(), "line1\nline2 modified\nlin3\nline4");
     }
 
+    #[test]
+    fn test_chunk_lands_at_recorded_line() {
+        // Every position matches the context; the recorded start line decides
+        let content = "x\nx\nx\nx\nx\n";
+        let patch = Differ::new(content, "x\nx\ny\nx\nx\n")
+            .context_lines(1)
+            .generate();
+        let (result, hunks) = SimilarPatcher::new(&patch)
+            .apply_tracked(content, false)
+            .unwrap();
+        assert_eq!(result, "x\nx\ny\nx\nx\n");
+        assert_eq!(hunks[0].offset(), 0);
+    }
+
+    #[test]
+    fn test_chunk_located_by_removed_lines() {
+        // The context alone also matches at the start; the removed line does not
+        let patch = Differ::new("a\ntarget\nb\n", "a\nT\nb\n").generate();
+        let content = "a\nother\nb\nfiller\na\ntarget\nb\n";
+        let (result, hunks) = SimilarPatcher::new(&patch)
+            .apply_tracked(content, false)
+            .unwrap();
+        assert_eq!(result, "a\nother\nb\nfiller\na\nT\nb\n");
+        assert_eq!(hunks[0].offset(), 4);
+    }
+
+    #[test]
+    fn test_chunk_found_at_end_of_content() {
+        let patch = Differ::new("a\nb\nc\n", "a\nb\nC\n").generate();
+        let (result, hunks) = SimilarPatcher::new(&patch)
+            .apply_tracked("new\nnew2\na\nb\nc\n", false)
+            .unwrap();
+        assert_eq!(result, "new\nnew2\na\nb\nC\n");
+        assert_eq!(hunks[0].offset(), 2);
+    }
+
+    #[test]
+    fn test_unmatched_chunk_does_not_fall_back_before_previous_chunk() {
+        let lines = ["a", "b", "c", "d", "e", "f"];
+        let operations = [
+            Operation::Context("nowhere".to_string()),
+            Operation::Remove("to be found".to_string()),
+        ];
+        let patch = Patch::default();
+        let patcher = SimilarPatcher::new(&patch);
+        // Line 2 is the recorded position, but lines up to 4 are already consumed
+        assert!(matches!(
+            patcher.find_chunk_start_position(&lines, 4, 2, &operations),
+            Err(Error::ApplyError(_))
+        ));
+        let found = patcher
+            .find_chunk_start_position(&lines, 1, 2, &operations)
+            .unwrap();
+        assert_eq!((found.position, found.score), (2, 0.0));
+    }
+
+    #[test]
+    fn test_apply_tracked_reports_fuzzy_hunk() {
+        let old_content = "line1\nline2\nline3\nline4";
+        let new_content = "line1\nline2 modified\nline3\nline4";
+        let similar_content = "line1\nlin2\nlin3\nline4";
+
+        let patch = Differ::new(old_content, new_content).generate();
+        let (result, hunks) = SimilarPatcher::new(&patch)
+            .apply_tracked(similar_content, false)
+            .unwrap();
+
+        assert_eq!(result, "line1\nline2 modified\nlin3\nline4");
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].offset(), 0);
+        assert!(hunks[0].fuzzy);
+    }
+
+    #[test]
+    fn test_normalize_unicode_whitespace() {
+        assert!(matches!(normalize_whitespace("a b c"), Cow::Borrowed(_)));
+        assert_eq!(normalize_whitespace("a\u{3000}b\u{a0}\tc"), "a b c");
+        assert_eq!(
+            similarity_score("// 注释\u{3000}说明", "// 注释 说明"),
+            0.95
+        );
+    }
+
+    #[test]
+    fn test_apply_with_multibyte_fuzzy_context() {
+        let old_content = "// 初始化配置文件\nlet x = 1;\n// 结束处理流程\n";
+        let new_content = "// 初始化配置文件\nlet x = 2;\n// 结束处理流程\n";
+        let patch = Differ::new(old_content, new_content).generate();
+
+        // One CJK character (three bytes) differs in each context line.
+        let drifted = "// 初始化配置文档\nlet x = 1;\n// 结束处理流序\n";
+        let result = SimilarPatcher::new(&patch)
+            .fuzz(3)
+            .apply(drifted, false)
+            .unwrap();
+        assert_eq!(result, "// 初始化配置文档\nlet x = 2;\n// 结束处理流序\n");
+    }
+
     #[test]
     fn test_apply_reverse_with_fuzzy_match() {
         let old_content = "line1\nline2\nline3\nline4";
@@ -655,4 +1049,44 @@ mod tests {
         // The result should have the original content structure but preserve the slight difference
         assert_eq!(result.unwrap(), "line1\nline2\nlin3\nline4");
     }
+
+    #[test]
+    fn test_anchored_ignores_line_numbers() {
+        let patch = Patch::parse(
+            "\
+--- a/file.txt
++++ b/file.txt
+@@ -40,3 +40,3 @@
+ alpha
+-beta
++BETA
+ gamma
+@@ -2,2 +2,2 @@
+ delta
+-epsilon
++EPSILON
+",
+        )
+        .unwrap();
+        let content = "alpha\nbeta\ngamma\ndelta\nepsilon\n";
+
+        let (result, hunks) = SimilarPatcher::new_anchored(&patch)
+            .apply_tracked(content, false)
+            .unwrap();
+        assert_eq!(result, "alpha\nBETA\ngamma\ndelta\nEPSILON\n");
+        assert_eq!(hunks[0].actual_start, 0);
+        assert_eq!(hunks[1].actual_start, 3);
+        assert!(hunks.iter().all(|h| !h.fuzzy));
+    }
+
+    #[test]
+    fn test_anchored_fails_when_context_missing() {
+        let old_content = "line1\nline2\nline3\nline4";
+        let new_content = "line1\nline2 modified\nline3\nline4";
+        let patch = Differ::new(old_content, new_content).generate();
+
+        // Fuzzy matching would accept this, anchored mode requires the exact context
+        let result = SimilarPatcher::new_anchored(&patch).apply("line1\nlin2\nlin3\nline4", false);
+        assert!(matches!(result, Err(Error::ApplyError(_))));
+    }
 }
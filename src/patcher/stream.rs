@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use super::bytes::{chunk_lines, trim_cr};
+use crate::{Error, Patch};
+
+/// Applies a patch while streaming the content from `reader` to `writer`.
+///
+/// Lines before a chunk's search window are copied through a single reusable
+/// buffer; only the lines inside the window of the chunk being applied are
+/// held in memory. Each chunk must match exactly (ignoring a trailing `\r`)
+/// at most `window` lines away from its expected position, the nearest match
+/// winning.
+pub(crate) fn apply_stream<R: BufRead, W: Write>(
+    patch: &Patch,
+    reader: R,
+    writer: W,
+    reverse: bool,
+    window: usize,
+) -> Result<(), Error> {
+    let mut input = LineReader::new(reader);
+    let mut output = LineWriter::new(writer);
+    let mut offset: isize = 0;
+
+    for chunk in &patch.chunks {
+        let expected = if reverse {
+            chunk.new_start
+        } else {
+            chunk.old_start
+        };
+        let (ops, source) = chunk_lines(chunk, reverse);
+
+        let shifted = expected.saturating_add_signed(offset).max(input.next_index);
+        let low = shifted.saturating_sub(window).max(input.next_index);
+        let high = shifted + window;
+        input.copy_until(low, &mut output)?;
+        // Lower than `low` only if the input ended before it
+        let base = input.next_index;
+        input.fill(high + source.len() - base)?;
+
+        let matches_at = |pos: usize| {
+            let start = pos - base;
+            start + source.len() <= input.pending.len()
+                && input
+                    .pending
+                    .range(start..start + source.len())
+                    .zip(&source)
+                    .all(|(actual, expected)| trim_cr(actual, b'\n') == *expected)
+        };
+        let position = if source.is_empty() {
+            (shifted <= base + input.pending.len()).then_some(shifted)
+        } else {
+            (base..=high)
+                .filter(|&pos| matches_at(pos))
+                .min_by_key(|&pos| pos.abs_diff(shifted))
+        }
+        .ok_or_else(|| {
+            Error::ApplyError(format!(
+                "Failed to find matching context for chunk expected at line {}",
+                expected + 1
+            ))
+        })?;
+        offset = position as isize - expected as isize;
+
+        input.copy_until(position, &mut output)?;
+        for (is_source, is_target, line) in ops {
+            match (is_source, is_target) {
+                // Keep the original bytes (including any '\r') of context lines
+                (true, true) => {
+                    let original = input.take();
+                    output.write_line(&original)?;
+                }
+                (true, false) => {
+                    input.take();
+                }
+                _ => output.write_line(line)?,
+            }
+        }
+    }
+
+    input.fill(1)?;
+    let reached_eof = input.pending.is_empty();
+    input.copy_until(usize::MAX, &mut output)?;
+
+    let trailing_newline = match patch.newline_at_eof(reverse) {
+        Some(newline) if reached_eof => newline,
+        // Nothing to preserve when the content is created from scratch
+        None if input.next_index == 0 => true,
+        _ => input.last_had_newline,
+    };
+    if trailing_newline && output.written_any {
+        output.writer.write_all(b"\n")?;
+    }
+    output.writer.flush()?;
+    Ok(())
+}
+
+/// Reads lines on demand, keeping only the lines of the current search window.
+struct LineReader<R> {
+    reader: R,
+    /// Lines read but not yet consumed, without their `\n`
+    pending: VecDeque<Vec<u8>>,
+    /// Index of the first pending line (or of the next line to read)
+    next_index: usize,
+    buffer: Vec<u8>,
+    eof: bool,
+    last_had_newline: bool,
+}
+
+impl<R: BufRead> LineReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+            next_index: 0,
+            buffer: Vec::new(),
+            eof: false,
+            last_had_newline: false,
+        }
+    }
+
+    /// Reads the next line into the reusable buffer; returns `false` at EOF.
+    fn read_line(&mut self) -> Result<bool, Error> {
+        self.buffer.clear();
+        if self.eof || self.reader.read_until(b'\n', &mut self.buffer)? == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.last_had_newline = self.buffer.last() == Some(&b'\n');
+        if self.last_had_newline {
+            self.buffer.pop();
+        }
+        Ok(true)
+    }
+
+    /// Buffers lines until `count` are pending or the input is exhausted.
+    fn fill(&mut self, count: usize) -> Result<(), Error> {
+        while self.pending.len() < count && self.read_line()? {
+            self.pending.push_back(self.buffer.clone());
+        }
+        Ok(())
+    }
+
+    /// Copies lines to `output` until line `index` is the next one, or the input ends.
+    fn copy_until<W: Write>(
+        &mut self,
+        index: usize,
+        output: &mut LineWriter<W>,
+    ) -> Result<(), Error> {
+        while self.next_index < index {
+            if let Some(line) = self.pending.pop_front() {
+                output.write_line(&line)?;
+            } else if self.read_line()? {
+                output.write_line(&self.buffer)?;
+            } else {
+                break;
+            }
+            self.next_index += 1;
+        }
+        Ok(())
+    }
+
+    /// Consumes the next pending line.
+    fn take(&mut self) -> Vec<u8> {
+        self.next_index += 1;
+        self.pending.pop_front().unwrap_or_default()
+    }
+}
+
+/// Writes lines separated by `\n`; the caller decides about the final newline.
+struct LineWriter<W> {
+    writer: W,
+    written_any: bool,
+}
+
+impl<W: Write> LineWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written_any: false,
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> Result<(), Error> {
+        if self.written_any {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(line)?;
+        self.written_any = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::differ::{DiffAlgorithm, Differ};
+
+    fn stream(patch: &Patch, content: &str, reverse: bool, window: usize) -> Result<String, Error> {
+        let mut output = Vec::new();
+        apply_stream(patch, content.as_bytes(), &mut output, reverse, window)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_apply_stream_round_trip() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\nI\nj\nk";
+        let patch = Differ::new(old, new).context_lines(1).generate();
+
+        assert_eq!(stream(&patch, old, false, 0).unwrap(), new);
+        assert_eq!(stream(&patch, new, true, 0).unwrap(), old);
+    }
+
+    #[test]
+    fn test_apply_stream_searches_window() {
+        let patch = Differ::new("x\ny\nz\n", "x\nY\nz\n").generate();
+        let content = "new 1\nnew 2\nx\ny\nz\n";
+
+        assert!(stream(&patch, content, false, 1).is_err());
+        assert_eq!(
+            stream(&patch, content, false, 2).unwrap(),
+            "new 1\nnew 2\nx\nY\nz\n"
+        );
+    }
+}
@@ -65,38 +65,6 @@ fn setup_git_checkout(tag_name: &str) -> (TempDir, PathBuf) {
     (temp_dir, temp_path)
 }
 
-// Helper function to update patch file paths to point to the temp directory
-fn update_patch_file_paths(mp: MultifilePatch, temp_path: &Path) -> MultifilePatch {
-    let mut updated_patches = Vec::new();
-
-    for mut patch in mp.patches {
-        // Convert relative paths to absolute paths
-        if patch.old_file == "/dev/null" {
-            // For new files, keep /dev/null as is
-        } else {
-            patch.old_file = temp_path
-                .join(&patch.old_file)
-                .to_str()
-                .unwrap()
-                .to_string();
-        }
-
-        if patch.new_file == "/dev/null" {
-            // For deleted files, keep /dev/null as is
-        } else {
-            patch.new_file = temp_path
-                .join(&patch.new_file)
-                .to_str()
-                .unwrap()
-                .to_string();
-        }
-
-        updated_patches.push(patch);
-    }
-
-    MultifilePatch::new(updated_patches)
-}
-
 // Helper function to apply a patch and verify the results
 fn apply_and_verify_patch(
     patch_path: PathBuf,
@@ -105,10 +73,10 @@ fn apply_and_verify_patch(
     ignore_errors: bool,
 ) {
     // Parse the patch
-    let multifile_patch = MultifilePatch::parse_from_file(patch_path).unwrap();
+    let mut updated_patch = MultifilePatch::parse_from_file(patch_path).unwrap();
 
     // Update file paths in the parsed patch to point to our temp directory
-    let updated_patch = update_patch_file_paths(multifile_patch, temp_path);
+    updated_patch.remap_paths(|path| temp_path.join(path).to_str().unwrap().to_string());
 
     // Debug info: Print first patch details
     if !updated_patch.patches.is_empty() {
@@ -268,10 +236,10 @@ diff --git a/src/test.txt b/src/test.txt
     fs::write(temp_path.join("src/test.txt"), "line1\nline2\n").unwrap();
 
     // Parse and apply the patch
-    let multifile_patch = MultifilePatch::parse_from_file(patch_file).unwrap();
+    let mut updated_patch = MultifilePatch::parse_from_file(patch_file).unwrap();
 
     // Update file paths in the parsed patch to point to our temp directory
-    let updated_patch = update_patch_file_paths(multifile_patch, temp_path);
+    updated_patch.remap_paths(|path| temp_path.join(path).to_str().unwrap().to_string());
 
     let patcher = MultifilePatcher::new(updated_patch);
     let patched_files = patcher.apply_and_write(false).unwrap();